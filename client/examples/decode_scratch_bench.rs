@@ -0,0 +1,148 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use pancake_db_idl::ddl::{DropTableRequest, GetSchemaRequest};
+use pancake_db_idl::dml::{ListSegmentsRequest, WriteToPartitionRequest};
+use pancake_db_idl::dtype::DataType;
+use pancake_db_idl::schema::{ColumnMeta, Schema};
+
+use pancake_db_client::{Client, DecodeScratch, SegmentKey};
+use pancake_db_client::errors::ClientResult;
+use pancake_db_client::row_helpers::{FieldValue, Row};
+use pancake_db_client::row_helpers::FieldValueConverter;
+
+// Compares allocations when decoding a wide segment column by column,
+// with and without a reused DecodeScratch.
+// Requires a PancakeDB server at localhost:3842.
+
+const TABLE_NAME: &str = "decode_scratch_bench_table";
+const N_COLUMNS: usize = 64;
+const N_ROWS: usize = 2000;
+const BATCH_SIZE: usize = 250;
+
+struct CountingAlloc;
+
+static N_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn column_name(i: usize) -> String {
+  format!("c{}", i)
+}
+
+#[tokio::main]
+async fn main() -> ClientResult<()> {
+  let mut client = Client::connect("http://localhost:3842").await?;
+
+  let _ = client.drop_table_blocking(
+    TABLE_NAME,
+    Duration::from_millis(100),
+    Duration::from_secs(10),
+  ).await;
+  let mut schema = Schema::default();
+  for i in 0..N_COLUMNS {
+    schema.columns.insert(column_name(i), ColumnMeta {
+      dtype: DataType::Int64 as i32,
+      ..Default::default()
+    });
+  }
+  client.ensure_table(TABLE_NAME, &schema).await?;
+
+  for batch_idx in 0..N_ROWS / BATCH_SIZE {
+    let rows = (0..BATCH_SIZE)
+      .map(|row_idx| {
+        let mut row = Row::default();
+        for i in 0..N_COLUMNS {
+          let x = (batch_idx * BATCH_SIZE + row_idx) as i64 * i as i64;
+          row.fields.insert(column_name(i), FieldValue { value: x.to_value() });
+        }
+        row
+      })
+      .collect();
+    client.write_to_partition(WriteToPartitionRequest {
+      table_name: TABLE_NAME.to_string(),
+      rows,
+      ..Default::default()
+    }).await?;
+  }
+
+  let columns = client.get_schema(GetSchemaRequest {
+    table_name: TABLE_NAME.to_string(),
+  }).await?.schema.unwrap_or_default().columns;
+  let segments = client.list_segments(ListSegmentsRequest {
+    table_name: TABLE_NAME.to_string(),
+    ..Default::default()
+  }).await?.segments;
+
+  for segment in segments {
+    let segment_key = SegmentKey {
+      table_name: TABLE_NAME.to_string(),
+      partition: segment.partition,
+      segment_id: segment.segment_id,
+    };
+    let correlation_id = pancake_db_client::new_correlation_id();
+    let is_deleted = client.decode_is_deleted(&segment_key, &correlation_id).await?;
+
+    let n_allocs_before = N_ALLOCS.load(Ordering::Relaxed);
+    let mut fresh_results = HashMap::new();
+    for (name, column) in &columns {
+      let fvs = client.decode_segment_column(
+        &segment_key,
+        name,
+        column,
+        &is_deleted,
+        &correlation_id,
+      ).await?;
+      fresh_results.insert(name.clone(), fvs);
+    }
+    let fresh_allocs = N_ALLOCS.load(Ordering::Relaxed) - n_allocs_before;
+
+    let n_allocs_before = N_ALLOCS.load(Ordering::Relaxed);
+    let mut scratch = DecodeScratch::default();
+    let mut scratch_results = HashMap::new();
+    for (name, column) in &columns {
+      let fvs = client.decode_segment_column_with_scratch(
+        &segment_key,
+        name,
+        column,
+        &is_deleted,
+        &correlation_id,
+        &mut scratch,
+      ).await?;
+      scratch_results.insert(name.clone(), fvs);
+    }
+    let scratch_allocs = N_ALLOCS.load(Ordering::Relaxed) - n_allocs_before;
+
+    assert_eq!(fresh_results, scratch_results);
+    println!(
+      "segment {}: {} allocations without scratch, {} with scratch",
+      segment_key.segment_id,
+      fresh_allocs,
+      scratch_allocs,
+    );
+  }
+
+  client.drop_table(DropTableRequest {
+    table_name: TABLE_NAME.to_string(),
+  }).await?;
+  Ok(())
+}
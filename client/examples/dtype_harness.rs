@@ -0,0 +1,191 @@
+//! End-to-end read/write checks for every column data type.
+//!
+//! Runs against the server at `PANCAKE_DB_URL`, e.g.
+//! `PANCAKE_DB_URL=http://localhost:3842 cargo run --example dtype_harness --features read`,
+//! and does nothing if it is unset.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use pancake_db_client::{Client, make_row, SegmentKey};
+use pancake_db_client::errors::{ClientError, ClientErrorKind, ClientResult};
+use pancake_db_client::row_helpers::rows_semantically_equal;
+use pancake_db_idl::ddl::{CreateTableRequest, DropTableRequest};
+use pancake_db_idl::dml::{ListSegmentsRequest, Row, WriteToPartitionRequest};
+use pancake_db_idl::dtype::DataType;
+use pancake_db_idl::schema::{ColumnMeta, Schema};
+use tonic::Code;
+
+const URL_VAR: &str = "PANCAKE_DB_URL";
+const COLUMN_NAME: &str = "v";
+
+struct Case {
+  dtype: DataType,
+  rows: Vec<Row>,
+}
+
+fn timestamp(micros: u64) -> SystemTime {
+  SystemTime::UNIX_EPOCH + Duration::from_micros(micros)
+}
+
+fn cases() -> Vec<Case> {
+  vec![
+    Case {
+      dtype: DataType::String,
+      rows: vec![
+        make_row! { COLUMN_NAME => "abc".to_string() },
+        make_row! { COLUMN_NAME => "".to_string() },
+        make_row! { COLUMN_NAME => None::<String> },
+        make_row! { COLUMN_NAME => "\u{fe}\u{ff} unicode ✓".to_string() },
+      ],
+    },
+    Case {
+      dtype: DataType::Int64,
+      rows: vec![
+        make_row! { COLUMN_NAME => i64::MIN },
+        make_row! { COLUMN_NAME => None::<i64> },
+        make_row! { COLUMN_NAME => 0_i64 },
+        make_row! { COLUMN_NAME => i64::MAX },
+      ],
+    },
+    Case {
+      dtype: DataType::Bytes,
+      rows: vec![
+        make_row! { COLUMN_NAME => vec![0_u8, 253, 254, 255] },
+        make_row! { COLUMN_NAME => Vec::<u8>::new() },
+        make_row! { COLUMN_NAME => None::<Vec<u8>> },
+      ],
+    },
+    Case {
+      dtype: DataType::Bool,
+      rows: vec![
+        make_row! { COLUMN_NAME => true },
+        make_row! { COLUMN_NAME => None::<bool> },
+        make_row! { COLUMN_NAME => false },
+      ],
+    },
+    Case {
+      dtype: DataType::Float32,
+      rows: vec![
+        make_row! { COLUMN_NAME => 1.5_f32 },
+        make_row! { COLUMN_NAME => f32::NAN },
+        make_row! { COLUMN_NAME => None::<f32> },
+        make_row! { COLUMN_NAME => f32::NEG_INFINITY },
+      ],
+    },
+    Case {
+      dtype: DataType::Float64,
+      rows: vec![
+        make_row! { COLUMN_NAME => -0.25_f64 },
+        make_row! { COLUMN_NAME => None::<f64> },
+        make_row! { COLUMN_NAME => f64::MAX },
+      ],
+    },
+    Case {
+      dtype: DataType::TimestampMicros,
+      rows: vec![
+        make_row! { COLUMN_NAME => timestamp(0) },
+        make_row! { COLUMN_NAME => None::<SystemTime> },
+        make_row! { COLUMN_NAME => timestamp(1_600_000_000_123_456) },
+      ],
+    },
+  ]
+}
+
+#[tokio::main]
+async fn main() -> ClientResult<()> {
+  let url = match std::env::var(URL_VAR) {
+    Ok(url) => url,
+    Err(_) => {
+      println!("{} is not set; skipping the data type harness", URL_VAR);
+      return Ok(());
+    },
+  };
+  let client = Client::connect(url).await?;
+
+  let mut n_failures = 0;
+  for case in cases() {
+    match run_case(client.clone(), &case).await {
+      Ok(()) => println!("{:?}: ok", case.dtype),
+      Err(err) => {
+        println!("{:?}: FAILED: {}", case.dtype, err);
+        n_failures += 1;
+      },
+    }
+  }
+
+  if n_failures > 0 {
+    return Err(ClientError::other(format!("{} data type cases failed", n_failures)));
+  }
+  Ok(())
+}
+
+async fn run_case(mut client: Client, case: &Case) -> ClientResult<()> {
+  let table_name = format!("dtype_harness_{:?}", case.dtype).to_lowercase();
+  drop_if_exists(&mut client, &table_name).await?;
+
+  let mut columns = HashMap::new();
+  columns.insert(COLUMN_NAME.to_string(), ColumnMeta {
+    dtype: case.dtype as i32,
+    ..Default::default()
+  });
+  client.create_table(CreateTableRequest {
+    table_name: table_name.clone(),
+    schema: Some(Schema {
+      columns: columns.clone(),
+      ..Default::default()
+    }),
+    ..Default::default()
+  }).await?;
+
+  client.write_to_partition(WriteToPartitionRequest {
+    table_name: table_name.clone(),
+    rows: case.rows.clone(),
+    ..Default::default()
+  }).await?;
+
+  let segments = client.list_segments(ListSegmentsRequest {
+    table_name: table_name.clone(),
+    ..Default::default()
+  }).await?.segments;
+  let mut read_rows = Vec::new();
+  for segment in segments {
+    let segment_key = SegmentKey {
+      table_name: table_name.clone(),
+      partition: segment.partition,
+      segment_id: segment.segment_id,
+    };
+    read_rows.extend(client.decode_segment(&segment_key, &columns).await?);
+  }
+
+  if read_rows.len() != case.rows.len() {
+    return Err(ClientError::other(format!(
+      "wrote {} rows but read {}",
+      case.rows.len(),
+      read_rows.len(),
+    )));
+  }
+  for (written, read) in case.rows.iter().zip(&read_rows) {
+    if !rows_semantically_equal(written, read) {
+      return Err(ClientError::other(format!(
+        "wrote {:?} but read {:?}",
+        written,
+        read,
+      )));
+    }
+  }
+
+  client.drop_table(DropTableRequest { table_name }).await?;
+  Ok(())
+}
+
+async fn drop_if_exists(client: &mut Client, table_name: &str) -> ClientResult<()> {
+  let res = client.drop_table(DropTableRequest {
+    table_name: table_name.to_string(),
+  }).await;
+  match res {
+    Ok(_) => Ok(()),
+    Err(ClientError { kind: ClientErrorKind::Grpc { code: Code::NotFound }, .. }) => Ok(()),
+    Err(err) => Err(err),
+  }
+}
@@ -15,6 +15,7 @@ use tonic::Code;
 
 use pancake_db_client::{Client, SegmentKey};
 use pancake_db_client::errors::{ClientError, ClientErrorKind, ClientResult};
+use pancake_db_client::segment_helpers::SegmentExt;
 
 const TABLE_NAME: &str = "fuzz_test_table";
 const BATCH_SIZE: usize = 250;
@@ -247,8 +248,7 @@ async fn delete(opt: &Opt, mut client: Client, n_deletions_ub: &mut usize) -> Cl
   let segment = &segments[rng.gen_range(0..segments.len())];
   let mut to_delete = Vec::new();
   for _ in 0..opt.max_deletions_per_req {
-    let metadata = segment.metadata.clone().unwrap();
-    let row_id_range = metadata.row_count; // could go higher
+    let row_id_range = segment.row_count().unwrap(); // could go higher
     to_delete.push(rng.gen_range(0..row_id_range) as u32);
   }
   let distinct_to_delete: HashSet<_> = to_delete.iter().cloned().collect();
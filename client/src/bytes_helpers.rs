@@ -0,0 +1,159 @@
+//! Canonical text encodings for bytes values.
+//!
+//! JSON conversion and other tooling share these so bytes columns are always
+//! rendered the same way.
+//! Base64 uses the standard alphabet with padding, and hex uses lowercase
+//! digits.
+
+use crate::errors::{ClientError, ClientResult};
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as padded standard base64.
+///
+/// ```
+/// use pancake_db_client::bytes_helpers::{decode_bytes_base64, encode_bytes_base64};
+///
+/// assert_eq!(encode_bytes_base64(&[0, 255, 77, 1]), "AP9NAQ==");
+/// assert_eq!(decode_bytes_base64("AP9NAQ==").unwrap(), vec![0, 255, 77, 1]);
+/// ```
+pub fn encode_bytes_base64(bytes: &[u8]) -> String {
+  let mut res = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let b0 = chunk[0] as usize;
+    let b1 = chunk.get(1).copied().unwrap_or(0) as usize;
+    let b2 = chunk.get(2).copied().unwrap_or(0) as usize;
+    res.push(BASE64_CHARS[b0 >> 2] as char);
+    res.push(BASE64_CHARS[((b0 & 3) << 4) | (b1 >> 4)] as char);
+    if chunk.len() > 1 {
+      res.push(BASE64_CHARS[((b1 & 15) << 2) | (b2 >> 6)] as char);
+    } else {
+      res.push('=');
+    }
+    if chunk.len() > 2 {
+      res.push(BASE64_CHARS[b2 & 63] as char);
+    } else {
+      res.push('=');
+    }
+  }
+  res
+}
+
+fn base64_digit(c: u8) -> Option<u32> {
+  match c {
+    b'A'..=b'Z' => Some((c - b'A') as u32),
+    b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+    b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+    b'+' => Some(62),
+    b'/' => Some(63),
+    _ => None,
+  }
+}
+
+/// Decodes padded standard base64, as produced by [`encode_bytes_base64`].
+///
+/// Returns an error if the text has invalid characters, misplaced padding,
+/// or a length that isn't a multiple of 4.
+pub fn decode_bytes_base64(text: &str) -> ClientResult<Vec<u8>> {
+  let invalid = || ClientError::other(format!("invalid base64: {:?}", text));
+  let chars = text.as_bytes();
+  if chars.len() % 4 != 0 {
+    return Err(invalid());
+  }
+
+  let mut res = Vec::with_capacity(chars.len() / 4 * 3);
+  for (chunk_idx, chunk) in chars.chunks(4).enumerate() {
+    let is_last = chunk_idx == chars.len() / 4 - 1;
+    let n_padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+    if n_padding > 2 || (n_padding > 0 && !is_last) {
+      return Err(invalid());
+    }
+
+    let mut group = 0_u32;
+    for &c in &chunk[..4 - n_padding] {
+      group = (group << 6) | base64_digit(c).ok_or_else(invalid)?;
+    }
+    group <<= 6 * n_padding as u32;
+    let group_bytes = group.to_be_bytes();
+    res.extend_from_slice(&group_bytes[1..4 - n_padding]);
+  }
+  Ok(res)
+}
+
+/// Encodes bytes as lowercase hex, two digits per byte.
+///
+/// ```
+/// use pancake_db_client::bytes_helpers::{decode_bytes_hex, encode_bytes_hex};
+///
+/// assert_eq!(encode_bytes_hex(&[0, 255, 77, 1]), "00ff4d01");
+/// assert_eq!(decode_bytes_hex("00FF4d01").unwrap(), vec![0, 255, 77, 1]);
+/// ```
+pub fn encode_bytes_hex(bytes: &[u8]) -> String {
+  bytes.iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Decodes hex, accepting either case.
+///
+/// Returns an error if the text has an odd length or a non-hex character.
+pub fn decode_bytes_hex(text: &str) -> ClientResult<Vec<u8>> {
+  let invalid = || ClientError::other(format!("invalid hex: {:?}", text));
+  let chars = text.as_bytes();
+  if chars.len() % 2 != 0 {
+    return Err(invalid());
+  }
+
+  chars.chunks(2)
+    .map(|pair| {
+      let hi = (pair[0] as char).to_digit(16).ok_or_else(invalid)?;
+      let lo = (pair[1] as char).to_digit(16).ok_or_else(invalid)?;
+      Ok((hi * 16 + lo) as u8)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sample_byte_seqs() -> Vec<Vec<u8>> {
+    vec![
+      vec![],
+      vec![0],
+      vec![255],
+      vec![253, 254, 255],
+      vec![0, 255, 77, 1],
+      b"foobar".to_vec(),
+      (0..=255).collect(),
+    ]
+  }
+
+  #[test]
+  fn test_base64_padding() {
+    assert_eq!(encode_bytes_base64(b""), "");
+    assert_eq!(encode_bytes_base64(b"f"), "Zg==");
+    assert_eq!(encode_bytes_base64(b"fo"), "Zm8=");
+    assert_eq!(encode_bytes_base64(b"foo"), "Zm9v");
+    assert_eq!(encode_bytes_base64(b"foob"), "Zm9vYg==");
+  }
+
+  #[test]
+  fn test_round_trips() -> ClientResult<()> {
+    for bytes in sample_byte_seqs() {
+      assert_eq!(decode_bytes_base64(&encode_bytes_base64(&bytes))?, bytes);
+      assert_eq!(decode_bytes_hex(&encode_bytes_hex(&bytes))?, bytes);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_invalid() {
+    for text in ["Zg=", "Z===", "Zg==Zm9v", "Zm9v!A==", "Zm=v"] {
+      assert!(decode_bytes_base64(text).is_err(), "{}", text);
+    }
+    for text in ["0", "0g", "zz", "00 1"] {
+      assert!(decode_bytes_hex(text).is_err(), "{}", text);
+    }
+  }
+}
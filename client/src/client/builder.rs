@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use pancake_db_idl::service::pancake_db_client::PancakeDbClient;
+use tonic::codegen::StdError;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::errors::{ClientError, ClientResult};
+
+use super::Client;
+
+/// Configures and connects a [`Client`].
+///
+/// Every option defaults to Tonic's own default, so
+/// `ClientBuilder::default().connect(dst)` is equivalent to
+/// [`Client::connect`].
+///
+/// ```
+/// use std::time::Duration;
+/// use pancake_db_client::ClientBuilder;
+/// # use pancake_db_client::errors::ClientError;
+///
+/// # async { // we don't actually run this in the test, only compile
+/// let client = ClientBuilder::default()
+///   .connect_timeout(Duration::from_secs(5))
+///   .timeout(Duration::from_secs(30))
+///   .connect("http://localhost:3842")
+///   .await?;
+/// # Ok::<(), ClientError>(())
+/// # };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientBuilder {
+  connect_timeout: Option<Duration>,
+  timeout: Option<Duration>,
+  tcp_keepalive: Option<Duration>,
+  tcp_nodelay: Option<bool>,
+  concurrency_limit: Option<usize>,
+  http2_keep_alive_interval: Option<Duration>,
+  keep_alive_timeout: Option<Duration>,
+  user_agent: Option<String>,
+  metadata: Vec<(String, String)>,
+}
+
+impl ClientBuilder {
+  /// Sets how long to wait while establishing a connection.
+  pub fn connect_timeout(mut self, dur: Duration) -> Self {
+    self.connect_timeout = Some(dur);
+    self
+  }
+
+  /// Sets how long to wait for each request before failing it.
+  pub fn timeout(mut self, dur: Duration) -> Self {
+    self.timeout = Some(dur);
+    self
+  }
+
+  /// Sets the TCP keepalive interval of the connection.
+  pub fn tcp_keepalive(mut self, dur: Duration) -> Self {
+    self.tcp_keepalive = Some(dur);
+    self
+  }
+
+  /// Sets whether to disable Nagle's algorithm on the connection.
+  pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+    self.tcp_nodelay = Some(enabled);
+    self
+  }
+
+  /// Limits how many requests may be in flight at once.
+  pub fn concurrency_limit(mut self, limit: usize) -> Self {
+    self.concurrency_limit = Some(limit);
+    self
+  }
+
+  /// Sets how often to send HTTP2 keepalive pings.
+  pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
+    self.http2_keep_alive_interval = Some(interval);
+    self
+  }
+
+  /// Sets how long to wait for a keepalive ping to be acknowledged.
+  pub fn keep_alive_timeout(mut self, dur: Duration) -> Self {
+    self.keep_alive_timeout = Some(dur);
+    self
+  }
+
+  /// Sets the user agent of every request.
+  ///
+  /// Tonic appends its own user agent, e.g. `my-service tonic/0.6.2`.
+  pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+    self.user_agent = Some(user_agent.into());
+    self
+  }
+
+  /// Adds an ASCII GRPC metadata entry to send with every request, e.g. a
+  /// tenant ID.
+  ///
+  /// Adding the same key again adds another value for it.
+  /// The client's metadata can also be changed after connecting via
+  /// [`Client::metadata_mut`].
+  pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+    self.metadata.push((key.into(), value.into()));
+    self
+  }
+
+  /// Creates a client connected to the given endpoint.
+  ///
+  /// See [`Client::connect`] for what qualifies as an endpoint.
+  pub async fn connect<D>(&self, dst: D) -> ClientResult<Client> where
+    D: std::convert::TryInto<Endpoint>,
+    D::Error: Into<StdError>,
+  {
+    let endpoint = self.endpoint(dst)?;
+    let metadata = self.metadata_map()?;
+    let channel = endpoint.connect().await?;
+    Ok(self.client(channel, metadata))
+  }
+
+  /// Creates a client that connects to the given endpoint on its first
+  /// request.
+  ///
+  /// Must be called within a Tokio runtime.
+  pub fn connect_lazy<D>(&self, dst: D) -> ClientResult<Client> where
+    D: std::convert::TryInto<Endpoint>,
+    D::Error: Into<StdError>,
+  {
+    let channel = self.endpoint(dst)?.connect_lazy();
+    Ok(self.client(channel, self.metadata_map()?))
+  }
+
+  fn client(&self, channel: Channel, metadata: MetadataMap) -> Client {
+    let mut client = Client::from_grpc(PancakeDbClient::new(channel));
+    *client.metadata_mut() = metadata;
+    client
+  }
+
+  fn metadata_map(&self) -> ClientResult<MetadataMap> {
+    let mut res = MetadataMap::new();
+    for (key, value) in &self.metadata {
+      let parsed_key = MetadataKey::from_bytes(key.as_bytes())
+        .map_err(|_| ClientError::other(format!("invalid metadata key {}", key)))?;
+      let parsed_value = MetadataValue::from_str(value)
+        .map_err(|_| ClientError::other(format!("invalid value for metadata key {}", key)))?;
+      res.append(parsed_key, parsed_value);
+    }
+    Ok(res)
+  }
+
+  fn endpoint<D>(&self, dst: D) -> ClientResult<Endpoint> where
+    D: std::convert::TryInto<Endpoint>,
+    D::Error: Into<StdError>,
+  {
+    let mut endpoint = dst.try_into()
+      .map_err(|e| ClientError::other(format!("invalid endpoint: {}", e.into())))?;
+    if let Some(dur) = self.connect_timeout {
+      endpoint = endpoint.connect_timeout(dur);
+    }
+    if let Some(dur) = self.timeout {
+      endpoint = endpoint.timeout(dur);
+    }
+    if self.tcp_keepalive.is_some() {
+      endpoint = endpoint.tcp_keepalive(self.tcp_keepalive);
+    }
+    if let Some(enabled) = self.tcp_nodelay {
+      endpoint = endpoint.tcp_nodelay(enabled);
+    }
+    if let Some(limit) = self.concurrency_limit {
+      endpoint = endpoint.concurrency_limit(limit);
+    }
+    if let Some(interval) = self.http2_keep_alive_interval {
+      endpoint = endpoint.http2_keep_alive_interval(interval);
+    }
+    if let Some(dur) = self.keep_alive_timeout {
+      endpoint = endpoint.keep_alive_timeout(dur);
+    }
+    if let Some(user_agent) = &self.user_agent {
+      endpoint = endpoint.user_agent(user_agent.as_str())
+        .map_err(|_| ClientError::other(format!("invalid user agent {}", user_agent)))?;
+    }
+    Ok(endpoint)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_options_applied() {
+    let builder = ClientBuilder::default()
+      .connect_timeout(Duration::from_secs(1))
+      .timeout(Duration::from_secs(2))
+      .tcp_nodelay(true)
+      .concurrency_limit(8);
+    assert_eq!(builder, ClientBuilder {
+      connect_timeout: Some(Duration::from_secs(1)),
+      timeout: Some(Duration::from_secs(2)),
+      tcp_nodelay: Some(true),
+      concurrency_limit: Some(8),
+      ..Default::default()
+    });
+
+    let endpoint = builder.endpoint("http://localhost:3842").unwrap();
+    assert_eq!(endpoint.uri().port_u16(), Some(3842));
+  }
+
+  #[tokio::test]
+  async fn test_connect_lazy() {
+    let builder = ClientBuilder::default()
+      .timeout(Duration::from_secs(2))
+      .keep_alive_timeout(Duration::from_secs(3));
+    assert!(builder.connect_lazy("http://localhost:3842").is_ok());
+    assert!(builder.connect_lazy("not a uri").is_err());
+  }
+}
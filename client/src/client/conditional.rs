@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use pancake_db_idl::dml::{FieldValue, ListSegmentsRequest, PartitionFieldValue, Row, WriteToPartitionRequest};
+
+use crate::errors::{ClientError, ClientResult};
+use crate::row_helpers;
+use crate::types::SegmentKey;
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this for idempotent writes.
+impl Client {
+  /// Writes the row to the partition unless a row with the same
+  /// `key_column` value already exists there.
+  ///
+  /// Returns whether the row was written.
+  ///
+  /// PancakeDB has no conditional writes, so this reads the key column of
+  /// every segment in the partition, then writes if no live row matches.
+  /// This is not atomic: a concurrent writer can insert the same key between
+  /// the check and the write, so callers needing strict uniqueness must
+  /// serialize their writes per key.
+  /// It also reads the whole key column, so it is only suited to partitions
+  /// of modest size.
+  pub async fn write_if_absent(
+    &mut self,
+    table_name: &str,
+    partition: &HashMap<String, PartitionFieldValue>,
+    row: Row,
+    key_column: &str,
+  ) -> ClientResult<bool> {
+    let key = match row.fields.get(key_column) {
+      Some(fv) if fv.value.is_some() => fv.clone(),
+      _ => return Err(ClientError::other(format!(
+        "row has no value for key column {}",
+        key_column,
+      ))),
+    };
+    let columns = self.columns_for(table_name, false).await?;
+    let key_meta = columns.get(key_column)
+      .ok_or_else(|| ClientError::other(format!(
+        "table {} has no column {}",
+        table_name,
+        key_column,
+      )))?
+      .clone();
+
+    let segments = self.list_segments(ListSegmentsRequest {
+      table_name: table_name.to_string(),
+      ..Default::default()
+    }).await?.segments;
+    let segment_keys = segments.into_iter()
+      .filter(|segment| &segment.partition == partition)
+      .map(|segment| SegmentKey {
+        table_name: table_name.to_string(),
+        partition: segment.partition,
+        segment_id: segment.segment_id,
+      })
+      .collect();
+
+    let client = self.clone();
+    let read_keys = |segment_key: SegmentKey| {
+      let mut client = client.clone();
+      let key_meta = key_meta.clone();
+      async move {
+        let correlation_id = crate::utils::new_correlation_id();
+        let is_deleted = client.decode_is_deleted(&segment_key, &correlation_id).await?;
+        client.decode_segment_column(
+          &segment_key,
+          key_column,
+          &key_meta,
+          &is_deleted,
+          &correlation_id,
+        ).await
+      }
+    };
+    let write = |row: Row| {
+      let mut client = client.clone();
+      let req = WriteToPartitionRequest {
+        table_name: table_name.to_string(),
+        partition: partition.clone(),
+        rows: vec![row],
+      };
+      async move {
+        client.write_to_partition(req).await?;
+        Ok(())
+      }
+    };
+    write_if_key_absent(&key, row, segment_keys, read_keys, write).await
+  }
+}
+
+async fn write_if_key_absent<R, RFut, W, WFut>(
+  key: &FieldValue,
+  row: Row,
+  segment_keys: Vec<SegmentKey>,
+  read_keys: R,
+  write: W,
+) -> ClientResult<bool> where
+  R: Fn(SegmentKey) -> RFut,
+  RFut: Future<Output=ClientResult<Vec<FieldValue>>>,
+  W: FnOnce(Row) -> WFut,
+  WFut: Future<Output=ClientResult<()>>,
+{
+  for segment_key in segment_keys {
+    let keys = read_keys(segment_key).await?;
+    if keys.iter().any(|fv| row_helpers::fields_semantically_equal(fv, key)) {
+      return Ok(false);
+    }
+  }
+
+  write(row).await?;
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use crate::make_row;
+
+  use super::*;
+
+  fn segment_key(segment_id: &str) -> SegmentKey {
+    SegmentKey {
+      segment_id: segment_id.to_string(),
+      ..Default::default()
+    }
+  }
+
+  async fn write_canned(key: i64, existing_keys: Vec<Vec<i64>>) -> ClientResult<(bool, Vec<Row>)> {
+    let row = make_row! { "id" => key, "x" => 1.5_f64 };
+    let segment_keys = (0..existing_keys.len())
+      .map(|i| segment_key(&i.to_string()))
+      .collect();
+    let read_keys = |segment_key: SegmentKey| {
+      let idx = segment_key.segment_id.parse::<usize>().unwrap();
+      let fvs = existing_keys[idx].iter()
+        .map(|&k| make_row! { "id" => k }.fields.remove("id").unwrap())
+        .collect();
+      async move { Ok(fvs) }
+    };
+    let written = Mutex::new(Vec::new());
+    let write = |row: Row| {
+      written.lock().unwrap().push(row);
+      async { Ok(()) }
+    };
+    let was_written = write_if_key_absent(
+      &row.fields["id"].clone(),
+      row,
+      segment_keys,
+      read_keys,
+      write,
+    ).await?;
+    Ok((was_written, written.into_inner().unwrap()))
+  }
+
+  #[tokio::test]
+  async fn test_write_if_absent() -> ClientResult<()> {
+    let (was_written, written) = write_canned(3, vec![vec![1, 2], vec![4]]).await?;
+    assert!(was_written);
+    assert_eq!(written, vec![make_row! { "id" => 3_i64, "x" => 1.5_f64 }]);
+
+    let (was_written, written) = write_canned(4, vec![vec![1, 2], vec![4]]).await?;
+    assert!(!was_written);
+    assert!(written.is_empty());
+    Ok(())
+  }
+}
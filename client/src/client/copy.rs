@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use pancake_db_idl::dml::{PartitionFieldValue, Row, WriteToPartitionRequest};
+use pancake_db_idl::schema::ColumnMeta;
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::SegmentKey;
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this for backfills and table migrations.
+impl Client {
+  /// Copies a segment's live rows into a partition of another table.
+  ///
+  /// Only the given columns are read and copied, so this can copy a subset
+  /// of the source table's columns.
+  /// The destination table must already exist with a compatible schema:
+  /// each copied column must be a column of it with the same data type and
+  /// nested list depth, or the server rejects the writes.
+  /// Rows are written in requests of up to `batch_size` rows, one request
+  /// after another, keeping the segment's row order.
+  ///
+  /// Returns how many rows were copied.
+  /// On an error, earlier requests may already have been written, so
+  /// retrying the copy can duplicate rows.
+  pub async fn copy_segment(
+    &mut self,
+    src_key: &SegmentKey,
+    dst_table: &str,
+    dst_partition: &HashMap<String, PartitionFieldValue>,
+    columns: &HashMap<String, ColumnMeta>,
+    batch_size: usize,
+  ) -> ClientResult<usize> {
+    let client = self.clone();
+    copy_rows(
+      self.decode_segment(src_key, columns),
+      dst_table,
+      dst_partition,
+      batch_size,
+      |req| {
+        let mut client = client.clone();
+        async move {
+          client.write_to_partition(req).await?;
+          Ok(())
+        }
+      },
+    ).await
+  }
+}
+
+// Kept separate from the GRPC calls so it can be tested on canned rows.
+async fn copy_rows<R, F, Fut>(
+  read_rows: R,
+  dst_table: &str,
+  dst_partition: &HashMap<String, PartitionFieldValue>,
+  batch_size: usize,
+  write: F,
+) -> ClientResult<usize> where
+  R: Future<Output=ClientResult<Vec<Row>>>,
+  F: Fn(WriteToPartitionRequest) -> Fut,
+  Fut: Future<Output=ClientResult<()>>,
+{
+  if batch_size == 0 {
+    return Err(ClientError::other("batch size must be positive".to_string()));
+  }
+
+  let rows = read_rows.await?;
+  for batch in rows.chunks(batch_size) {
+    write(WriteToPartitionRequest {
+      table_name: dst_table.to_string(),
+      partition: dst_partition.clone(),
+      rows: batch.to_vec(),
+    }).await?;
+  }
+  Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use pancake_db_idl::dml::partition_field_value::Value as PartitionValue;
+
+  use crate::make_row;
+
+  use super::*;
+
+  fn mock_segment_rows() -> Vec<Row> {
+    (0..5_i64)
+      .map(|i| make_row! { "id" => i, "name" => format!("row {}", i) })
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn test_copy_rows() -> ClientResult<()> {
+    let mut dst_partition = HashMap::new();
+    dst_partition.insert("region".to_string(), PartitionFieldValue {
+      value: Some(PartitionValue::StringVal("eu".to_string())),
+    });
+
+    let written = Mutex::new(Vec::new());
+    let write = |req: WriteToPartitionRequest| {
+      written.lock().unwrap().push(req);
+      async { Ok(()) }
+    };
+    let n_copied = copy_rows(
+      async { Ok(mock_segment_rows()) },
+      "dst",
+      &dst_partition,
+      2,
+      write,
+    ).await?;
+
+    assert_eq!(n_copied, 5);
+    let rows = mock_segment_rows();
+    let expected = rows.chunks(2)
+      .map(|batch| WriteToPartitionRequest {
+        table_name: "dst".to_string(),
+        partition: dst_partition.clone(),
+        rows: batch.to_vec(),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(written.into_inner().unwrap(), expected);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_copy_rows_stops_on_error() {
+    let n_writes = Mutex::new(0);
+    let write = |_req: WriteToPartitionRequest| {
+      *n_writes.lock().unwrap() += 1;
+      async { Err(ClientError::other("rejected".to_string())) }
+    };
+    let res = copy_rows(async { Ok(mock_segment_rows()) }, "dst", &HashMap::new(), 2, write).await;
+    assert!(res.is_err());
+    assert_eq!(*n_writes.lock().unwrap(), 1);
+
+    let res = copy_rows(async { Ok(mock_segment_rows()) }, "dst", &HashMap::new(), 0, write).await;
+    assert!(res.is_err());
+    assert_eq!(*n_writes.lock().unwrap(), 1);
+  }
+}
@@ -0,0 +1,495 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::Duration;
+
+use futures::StreamExt;
+use pancake_db_idl::ddl::{AlterTableRequest, CreateTableRequest, CreateTableResponse, DropTableRequest, GetSchemaRequest, GetSchemaResponse, ListTablesRequest};
+use pancake_db_idl::ddl::create_table_request::SchemaMode;
+use pancake_db_idl::dml::{ListSegmentsRequest, PartitionFieldValue, Segment};
+use pancake_db_idl::dtype::DataType;
+use pancake_db_idl::schema::{ColumnMeta, Schema};
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::{EnsureTableOutcome, PartitionRef, SchemaLookup, SchemaMigration};
+
+use super::Client;
+
+// how many get_schema requests get_schemas keeps in flight at once
+const MAX_CONCURRENT_SCHEMA_REQUESTS: usize = 8;
+
+/// Higher-level functionality.
+///
+/// Use this for managing tables.
+impl Client {
+  /// Creates the table if it doesn't exist, or adds any of the schema's
+  /// columns that it lacks.
+  ///
+  /// Unlike a plain [`create_table`][Client::create_table] request, this
+  /// does not fail when the table already exists.
+  /// It does still fail if the existing table is incompatible with the
+  /// schema, e.g. because a column has a different data type.
+  pub async fn ensure_table(
+    &mut self,
+    table_name: &str,
+    schema: &Schema,
+  ) -> ClientResult<EnsureTableOutcome> {
+    let req = CreateTableRequest {
+      table_name: table_name.to_string(),
+      schema: Some(schema.clone()),
+      mode: SchemaMode::AddNewColumns as i32,
+    };
+    let resp = self.create_table(req).await?;
+    Ok(ensure_table_outcome(resp))
+  }
+
+  /// Drops a table and waits until it no longer appears in the table list.
+  ///
+  /// The server may finish deleting a table after responding to the drop
+  /// request, so recreating the table right away can race with the deletion.
+  /// This polls [`list_tables`][Client::list_tables] every `poll_interval`
+  /// and returns an error of kind
+  /// [`ClientErrorKind::Timeout`][crate::errors::ClientErrorKind::Timeout] if the table
+  /// is still listed after `timeout`.
+  pub async fn drop_table_blocking(
+    &mut self,
+    table_name: &str,
+    poll_interval: Duration,
+    timeout: Duration,
+  ) -> ClientResult<()> {
+    self.drop_table(DropTableRequest {
+      table_name: table_name.to_string(),
+    }).await?;
+
+    let client = self.clone();
+    let table_exists = || {
+      let mut client = client.clone();
+      async move {
+        let resp = client.list_tables(ListTablesRequest {}).await?;
+        Ok(resp.tables.iter().any(|table| table.table_name == table_name))
+      }
+    };
+    wait_until_absent(table_name, table_exists, poll_interval, timeout).await
+  }
+
+  /// Returns the table's columns, ready to pass to
+  /// [`decode_segment`][Client::decode_segment].
+  ///
+  /// If `include_row_id` is true, the implicit `_row_id` column is included
+  /// too.
+  pub async fn columns_for(
+    &mut self,
+    table_name: &str,
+    include_row_id: bool,
+  ) -> ClientResult<HashMap<String, ColumnMeta>> {
+    let req = GetSchemaRequest {
+      table_name: table_name.to_string(),
+    };
+    let resp = self.get_schema(req).await?;
+    columns_from_schema(resp, include_row_id)
+  }
+
+  /// Fetches the schemas of several tables concurrently.
+  ///
+  /// Up to 8 requests are in flight at once, and each table name is only
+  /// requested once.
+  /// A failed request, e.g. for a table that doesn't exist, does not stop
+  /// the others; its error is recorded in the returned [`SchemaLookup`].
+  /// Use [`SchemaLookup::into_result`] to treat any failure as an error.
+  pub async fn get_schemas(&mut self, table_names: &[&str]) -> SchemaLookup {
+    let client = self.clone();
+    get_schemas_with(table_names, |table_name| {
+      let mut client = client.clone();
+      async move {
+        let resp = client.get_schema(GetSchemaRequest {
+          table_name: table_name.to_string(),
+        }).await?;
+        resp.schema.ok_or_else(|| ClientError::other(
+          "get schema response contained no schema".to_string()
+        ))
+      }
+    }).await
+  }
+
+  /// Lists the distinct partitions of the table that contain segments.
+  ///
+  /// Partitions are derived from a single
+  /// [`list_segments`][Client::list_segments] request, in the order their
+  /// first segments are listed.
+  /// This is useful for scanning a table one partition at a time.
+  pub async fn list_partitions(
+    &mut self,
+    table_name: &str,
+  ) -> ClientResult<Vec<HashMap<String, PartitionFieldValue>>> {
+    let req = ListSegmentsRequest {
+      table_name: table_name.to_string(),
+      ..Default::default()
+    };
+    let resp = self.list_segments(req).await?;
+    Ok(distinct_partitions(resp.segments))
+  }
+
+  /// Alters the table to have every column of the target schema.
+  ///
+  /// Fetches the current schema and adds whichever target columns it lacks
+  /// in a single [`alter_table`][Client::alter_table] request.
+  /// Returns an error without changing anything if the target is
+  /// incompatible with the current schema, i.e. if the partitioning differs
+  /// or an existing column would change its data type or nesting depth.
+  /// Columns the target lacks are reported rather than dropped.
+  pub async fn migrate_schema(
+    &mut self,
+    table_name: &str,
+    target: &Schema,
+  ) -> ClientResult<SchemaMigration> {
+    let resp = self.get_schema(GetSchemaRequest {
+      table_name: table_name.to_string(),
+    }).await?;
+    let current = resp.schema.ok_or_else(|| ClientError::other(
+      "get schema response contained no schema".to_string()
+    ))?;
+    let (new_columns, migration) = plan_migration(&current, target)?;
+    if !new_columns.is_empty() {
+      self.alter_table(AlterTableRequest {
+        table_name: table_name.to_string(),
+        new_columns,
+      }).await?;
+    }
+    Ok(migration)
+  }
+}
+
+async fn get_schemas_with<'a, F, Fut>(
+  table_names: &[&'a str],
+  get_schema: F,
+) -> SchemaLookup where
+  F: Fn(&'a str) -> Fut,
+  Fut: Future<Output=ClientResult<Schema>>,
+{
+  let mut seen = HashSet::new();
+  let unique_names = table_names.iter()
+    .filter(|table_name| seen.insert(**table_name))
+    .collect::<Vec<_>>();
+
+  let results = futures::stream::iter(unique_names)
+    .map(|&table_name| {
+      let schema_fut = get_schema(table_name);
+      async move { (table_name.to_string(), schema_fut.await) }
+    })
+    .buffer_unordered(MAX_CONCURRENT_SCHEMA_REQUESTS)
+    .collect::<Vec<_>>()
+    .await;
+
+  let mut lookup = SchemaLookup::default();
+  for (table_name, res) in results {
+    match res {
+      Ok(schema) => {
+        lookup.schemas.insert(table_name, schema);
+      },
+      Err(err) => {
+        lookup.errors.insert(table_name, err);
+      },
+    }
+  }
+  lookup
+}
+
+fn plan_migration(
+  current: &Schema,
+  target: &Schema,
+) -> ClientResult<(HashMap<String, ColumnMeta>, SchemaMigration)> {
+  if current.partitioning != target.partitioning {
+    return Err(ClientError::other(
+      "cannot migrate to a schema with different partitioning".to_string()
+    ));
+  }
+
+  let describe = |meta: &ColumnMeta| format!(
+    "{:?} at nested list depth {}",
+    meta.dtype(),
+    meta.nested_list_depth,
+  );
+  let mut new_columns = HashMap::new();
+  let mut incompatibilities = Vec::new();
+  for (name, target_meta) in &target.columns {
+    match current.columns.get(name) {
+      None => {
+        new_columns.insert(name.clone(), target_meta.clone());
+      },
+      Some(current_meta) if current_meta != target_meta => incompatibilities.push(format!(
+        "column {} is {} but the target has {}",
+        name,
+        describe(current_meta),
+        describe(target_meta),
+      )),
+      Some(_) => (),
+    }
+  }
+  if !incompatibilities.is_empty() {
+    incompatibilities.sort_unstable();
+    return Err(ClientError::other(format!(
+      "cannot migrate to an incompatible schema: {}",
+      incompatibilities.join("; "),
+    )));
+  }
+
+  let mut added_columns = new_columns.keys().cloned().collect::<Vec<_>>();
+  added_columns.sort_unstable();
+  let mut extra_columns = current.columns.keys()
+    .filter(|name| !target.columns.contains_key(*name))
+    .cloned()
+    .collect::<Vec<_>>();
+  extra_columns.sort_unstable();
+  Ok((new_columns, SchemaMigration { added_columns, extra_columns }))
+}
+
+fn distinct_partitions(segments: Vec<Segment>) -> Vec<HashMap<String, PartitionFieldValue>> {
+  let mut seen = HashSet::new();
+  let mut res = Vec::new();
+  for segment in &segments {
+    if seen.insert(PartitionRef(&segment.partition)) {
+      res.push(segment.partition.clone());
+    }
+  }
+  res
+}
+
+async fn wait_until_absent<F, Fut>(
+  table_name: &str,
+  table_exists: F,
+  poll_interval: Duration,
+  timeout: Duration,
+) -> ClientResult<()> where
+  F: Fn() -> Fut,
+  Fut: Future<Output=ClientResult<bool>>,
+{
+  crate::utils::poll_until(
+    || async { Ok(!table_exists().await?) },
+    poll_interval,
+    timeout,
+    &format!("table {} to be dropped", table_name),
+  ).await
+}
+
+fn columns_from_schema(
+  resp: GetSchemaResponse,
+  include_row_id: bool,
+) -> ClientResult<HashMap<String, ColumnMeta>> {
+  let mut columns = match resp.schema {
+    Some(schema) => schema.columns,
+    None => return Err(ClientError::other(
+      "get schema response contained no schema".to_string()
+    )),
+  };
+  if include_row_id {
+    columns.insert("_row_id".to_string(), ColumnMeta {
+      dtype: DataType::Int64 as i32,
+      ..Default::default()
+    });
+  }
+  Ok(columns)
+}
+
+fn ensure_table_outcome(resp: CreateTableResponse) -> EnsureTableOutcome {
+  if !resp.already_exists {
+    EnsureTableOutcome::Created
+  } else if resp.columns_added.is_empty() {
+    EnsureTableOutcome::AlreadyExists
+  } else {
+    EnsureTableOutcome::ColumnsAdded(resp.columns_added)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::partition_field_value::Value as PartitionValue;
+
+  use crate::errors::ClientErrorKind;
+
+  use super::*;
+
+  #[test]
+  fn test_ensure_table_outcome() {
+    assert_eq!(
+      ensure_table_outcome(CreateTableResponse {
+        already_exists: false,
+        columns_added: vec!["a".to_string()],
+      }),
+      EnsureTableOutcome::Created,
+    );
+    assert_eq!(
+      ensure_table_outcome(CreateTableResponse {
+        already_exists: true,
+        columns_added: vec![],
+      }),
+      EnsureTableOutcome::AlreadyExists,
+    );
+    assert_eq!(
+      ensure_table_outcome(CreateTableResponse {
+        already_exists: true,
+        columns_added: vec!["b".to_string()],
+      }),
+      EnsureTableOutcome::ColumnsAdded(vec!["b".to_string()]),
+    );
+  }
+
+  #[test]
+  fn test_columns_from_schema() -> ClientResult<()> {
+    let mut schema = Schema::default();
+    schema.columns.insert("s".to_string(), ColumnMeta {
+      dtype: DataType::String as i32,
+      nested_list_depth: 1,
+    });
+    let resp = GetSchemaResponse {
+      schema: Some(schema.clone()),
+    };
+
+    assert_eq!(columns_from_schema(resp.clone(), false)?, schema.columns);
+    let columns = columns_from_schema(resp, true)?;
+    assert_eq!(columns.len(), 2);
+    assert_eq!(columns["_row_id"].dtype(), DataType::Int64);
+    assert_eq!(columns["s"].nested_list_depth, 1);
+
+    assert!(columns_from_schema(GetSchemaResponse::default(), true).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_distinct_partitions() {
+    fn segment(segment_id: &str, day: i64, region: &str) -> Segment {
+      let mut partition = HashMap::new();
+      partition.insert("day".to_string(), PartitionFieldValue {
+        value: Some(PartitionValue::Int64Val(day)),
+      });
+      partition.insert("region".to_string(), PartitionFieldValue {
+        value: Some(PartitionValue::StringVal(region.to_string())),
+      });
+      Segment {
+        segment_id: segment_id.to_string(),
+        partition,
+        ..Default::default()
+      }
+    }
+
+    let segments = vec![
+      segment("s0", 1, "us"),
+      segment("s1", 2, "us"),
+      segment("s2", 1, "us"),
+      segment("s3", 1, "eu"),
+      segment("s4", 2, "us"),
+    ];
+    let expected = vec![
+      segments[0].partition.clone(),
+      segments[1].partition.clone(),
+      segments[3].partition.clone(),
+    ];
+    assert_eq!(distinct_partitions(segments), expected);
+  }
+
+  fn schema(columns: &[(&str, DataType)]) -> Schema {
+    Schema {
+      columns: columns.iter()
+        .map(|(name, dtype)| (name.to_string(), ColumnMeta {
+          dtype: *dtype as i32,
+          ..Default::default()
+        }))
+        .collect(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_plan_migration_adds_columns() -> ClientResult<()> {
+    let current = schema(&[("a", DataType::Int64), ("b", DataType::String)]);
+    let target = schema(&[("a", DataType::Int64), ("c", DataType::Bool), ("d", DataType::Bytes)]);
+    let (new_columns, migration) = plan_migration(&current, &target)?;
+    assert_eq!(new_columns.len(), 2);
+    assert_eq!(new_columns["c"].dtype(), DataType::Bool);
+    assert_eq!(migration, SchemaMigration {
+      added_columns: vec!["c".to_string(), "d".to_string()],
+      extra_columns: vec!["b".to_string()],
+    });
+
+    let (new_columns, migration) = plan_migration(&current, &current)?;
+    assert!(new_columns.is_empty());
+    assert_eq!(migration, SchemaMigration::default());
+    Ok(())
+  }
+
+  #[test]
+  fn test_plan_migration_incompatible_dtype() {
+    let current = schema(&[("a", DataType::Int64)]);
+    let target = schema(&[("a", DataType::Float64), ("b", DataType::Bool)]);
+    let err = plan_migration(&current, &target).unwrap_err();
+    assert!(err.message.contains("column a is Int64"), "{}", err.message);
+
+    let mut nested_target = current.clone();
+    nested_target.columns.get_mut("a").unwrap().nested_list_depth = 1;
+    assert!(plan_migration(&current, &nested_target).is_err());
+  }
+
+  #[tokio::test]
+  async fn test_wait_until_absent() -> ClientResult<()> {
+    let n_polls = std::sync::atomic::AtomicUsize::new(0);
+    let table_exists = || {
+      let n = n_polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      async move { Ok(n == 0) }
+    };
+    wait_until_absent(
+      "t",
+      table_exists,
+      Duration::from_millis(1),
+      Duration::from_secs(10),
+    ).await?;
+    assert_eq!(n_polls.into_inner(), 2);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_wait_until_absent_timeout() {
+    let res = wait_until_absent(
+      "t",
+      || async { Ok(true) },
+      Duration::from_millis(1),
+      Duration::from_millis(20),
+    ).await;
+    assert_eq!(res.unwrap_err().kind, ClientErrorKind::Timeout);
+  }
+
+  #[tokio::test]
+  async fn test_get_schemas_with_missing_table() {
+    let schema_of = |table_name: &str| {
+      let mut columns = HashMap::new();
+      columns.insert(format!("{}_id", table_name), ColumnMeta {
+        dtype: DataType::Int64 as i32,
+        nested_list_depth: 0,
+      });
+      Schema { columns, ..Default::default() }
+    };
+    let n_requests = std::sync::atomic::AtomicUsize::new(0);
+    let get_schema = |table_name: &str| {
+      n_requests.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      let res = if table_name == "missing" {
+        Err(ClientError {
+          message: "table missing does not exist".to_string(),
+          kind: ClientErrorKind::Grpc { code: tonic::Code::NotFound },
+        })
+      } else {
+        Ok(schema_of(table_name))
+      };
+      async move { res }
+    };
+
+    let lookup = get_schemas_with(&["users", "missing", "events", "users"], get_schema).await;
+    assert_eq!(n_requests.into_inner(), 3);
+    assert_eq!(lookup.schemas.len(), 2);
+    assert_eq!(lookup.schemas["users"], schema_of("users"));
+    assert_eq!(lookup.schemas["events"], schema_of("events"));
+    let missing_err = &lookup.errors["missing"];
+    assert_eq!(missing_err.kind, ClientErrorKind::Grpc { code: tonic::Code::NotFound });
+    assert_eq!(lookup.errors.len(), 1);
+
+    let err = lookup.into_result().unwrap_err();
+    assert!(err.to_string().contains("1 of 3"), "{}", err);
+    assert!(err.to_string().contains("table missing"), "{}", err);
+  }
+}
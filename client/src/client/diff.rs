@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::{Hash, Hasher};
+
+use pancake_db_idl::dml::{FieldValue, Row};
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::schema::ColumnMeta;
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::{SegmentDiff, SegmentKey};
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this to check what changed between two snapshots of the same data.
+impl Client {
+  /// Decodes two segments and compares their rows, matching rows up by the
+  /// value of `key_column`.
+  ///
+  /// Only the given columns are read, and `key_column` must be one of them.
+  /// Every row of both segments must have a non-null key, and no two rows
+  /// of the same segment may share a key; otherwise this returns an error.
+  /// Keys match by [`FieldValue`] equality, so a float key of NaN never
+  /// matches anything.
+  ///
+  /// Rows only in `key_a`'s segment are removed, rows only in `key_b`'s
+  /// segment are added, and rows of both that differ in any of the columns
+  /// are changed.
+  pub async fn diff_segments(
+    &mut self,
+    key_a: &SegmentKey,
+    key_b: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    key_column: &str,
+  ) -> ClientResult<SegmentDiff> {
+    if !columns.contains_key(key_column) {
+      return Err(ClientError::other(format!(
+        "key column {} is not among the columns to diff",
+        key_column,
+      )));
+    }
+
+    let rows_a = self.decode_segment(key_a, columns).await?;
+    let rows_b = self.decode_segment(key_b, columns).await?;
+    diff_rows(rows_a, rows_b, key_column)
+  }
+}
+
+// Field values aren't hashable themselves, so we wrap them to use them as
+// keys.
+struct FieldValueRef<'a>(&'a FieldValue);
+
+impl<'a> PartialEq for FieldValueRef<'a> {
+  fn eq(&self, other: &Self) -> bool {
+    self.0 == other.0
+  }
+}
+
+impl<'a> Eq for FieldValueRef<'a> {}
+
+impl<'a> Hash for FieldValueRef<'a> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    hash_field_value(self.0, state);
+  }
+}
+
+fn hash_field_value<H: Hasher>(fv: &FieldValue, state: &mut H) {
+  match &fv.value {
+    None => state.write_u8(0),
+    Some(Value::ListVal(list)) => {
+      state.write_u8(1);
+      state.write_usize(list.vals.len());
+      for val in &list.vals {
+        hash_field_value(val, state);
+      }
+    },
+    Some(Value::StringVal(s)) => {
+      state.write_u8(2);
+      s.hash(state);
+    },
+    Some(Value::BoolVal(b)) => {
+      state.write_u8(3);
+      b.hash(state);
+    },
+    Some(Value::BytesVal(b)) => {
+      state.write_u8(4);
+      b.hash(state);
+    },
+    Some(Value::Int64Val(x)) => {
+      state.write_u8(5);
+      x.hash(state);
+    },
+    // 0.0 and -0.0 are equal, so they must hash the same
+    Some(Value::Float32Val(x)) => {
+      state.write_u8(6);
+      (if *x == 0.0 { 0.0_f32 } else { *x }).to_bits().hash(state);
+    },
+    Some(Value::Float64Val(x)) => {
+      state.write_u8(7);
+      (if *x == 0.0 { 0.0_f64 } else { *x }).to_bits().hash(state);
+    },
+    Some(Value::TimestampVal(t)) => {
+      state.write_u8(8);
+      t.seconds.hash(state);
+      t.nanos.hash(state);
+    },
+  }
+}
+
+fn index_by_key<'a>(
+  rows: &'a [Row],
+  key_column: &str,
+  segment_name: &str,
+) -> ClientResult<HashMap<FieldValueRef<'a>, &'a Row>> {
+  let mut res = HashMap::with_capacity(rows.len());
+  for (row_idx, row) in rows.iter().enumerate() {
+    let key = match row.fields.get(key_column) {
+      Some(fv) if fv.value.is_some() => fv,
+      _ => return Err(ClientError::other(format!(
+        "row {} of segment {} has no value for key column {}",
+        row_idx,
+        segment_name,
+        key_column,
+      ))),
+    };
+    match res.entry(FieldValueRef(key)) {
+      Entry::Occupied(_) => return Err(ClientError::other(format!(
+        "segment {} has more than one row with key {:?}",
+        segment_name,
+        key,
+      ))),
+      Entry::Vacant(entry) => {
+        entry.insert(row);
+      },
+    }
+  }
+  Ok(res)
+}
+
+// Kept separate from the GRPC calls so it can be tested on canned rows.
+// Output rows keep the order of the segment they came from.
+fn diff_rows(rows_a: Vec<Row>, rows_b: Vec<Row>, key_column: &str) -> ClientResult<SegmentDiff> {
+  let by_key_a = index_by_key(&rows_a, key_column, "a")?;
+  let by_key_b = index_by_key(&rows_b, key_column, "b")?;
+
+  let mut res = SegmentDiff::default();
+  for row_a in &rows_a {
+    match by_key_b.get(&FieldValueRef(&row_a.fields[key_column])) {
+      None => res.removed.push(row_a.clone()),
+      Some(&row_b) if row_b != row_a => res.changed.push((row_a.clone(), row_b.clone())),
+      Some(_) => (),
+    }
+  }
+  for row_b in &rows_b {
+    if !by_key_a.contains_key(&FieldValueRef(&row_b.fields[key_column])) {
+      res.added.push(row_b.clone());
+    }
+  }
+  Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::make_row;
+
+  use super::*;
+
+  fn mock_segment_rows() -> Vec<Row> {
+    (0..4_i64)
+      .map(|i| make_row! { "id" => i, "name" => format!("row {}", i) })
+      .collect()
+  }
+
+  #[test]
+  fn test_diff_rows() -> ClientResult<()> {
+    let rows_a = mock_segment_rows();
+    let mut rows_b = mock_segment_rows();
+    rows_b[2] = make_row! { "id" => 2_i64, "name" => "renamed".to_string() };
+
+    let diff = diff_rows(rows_a.clone(), rows_b.clone(), "id")?;
+    assert_eq!(diff, SegmentDiff {
+      added: vec![],
+      removed: vec![],
+      changed: vec![(rows_a[2].clone(), rows_b[2].clone())],
+    });
+
+    let mut rows_b = mock_segment_rows();
+    rows_b.remove(1);
+    rows_b.push(make_row! { "id" => 4_i64, "name" => "row 4".to_string() });
+    let diff = diff_rows(rows_a.clone(), rows_b.clone(), "id")?;
+    assert_eq!(diff, SegmentDiff {
+      added: vec![rows_b[3].clone()],
+      removed: vec![rows_a[1].clone()],
+      changed: vec![],
+    });
+
+    assert_eq!(diff_rows(rows_a.clone(), rows_a.clone(), "id")?, SegmentDiff::default());
+    Ok(())
+  }
+
+  #[test]
+  fn test_diff_rows_bad_keys() {
+    let rows = mock_segment_rows();
+    let mut duplicated = mock_segment_rows();
+    duplicated.push(rows[0].clone());
+    assert!(diff_rows(rows.clone(), duplicated, "id").is_err());
+    assert!(diff_rows(rows.clone(), rows, "missing").is_err());
+  }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::Path;
+
+use futures::Stream;
+use pancake_db_idl::dml::ReadSegmentColumnResponse;
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dtype::DataType;
+use pancake_db_idl::schema::ColumnMeta;
+use tonic::Status;
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::{DecodeScratch, SegmentKey};
+
+use super::Client;
+use super::read;
+
+/// Higher-level functionality.
+///
+/// Use this for exporting blobs.
+impl Client {
+  /// Decodes a bytes column of the segment, writing each row's value to its
+  /// own file in the directory.
+  ///
+  /// Each file is named by its row's index within the segment, so the names
+  /// of live rows have gaps where rows were deleted.
+  /// Deleted rows and null values are skipped, and the directory is created
+  /// if it doesn't exist.
+  /// The column's raw data is held in memory while decoding, and compressed
+  /// data is decompressed to its atoms up front, but each value is written
+  /// as soon as it is built and is not kept afterward.
+  /// Decoding and writing happen on a blocking thread.
+  ///
+  /// The column must be a non-nested bytes column.
+  /// Returns how many files were written.
+  pub async fn stream_bytes_column_to_dir(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_name: &str,
+    dir: &Path,
+  ) -> ClientResult<usize> {
+    let correlation_id = crate::utils::new_correlation_id();
+    let is_deleted = self.decode_is_deleted(segment_key, &correlation_id).await?;
+    let responses = self.read_segment_column_stream(
+      segment_key,
+      column_name,
+      &correlation_id,
+    ).await?;
+    write_column_responses_to_dir(responses, &is_deleted, dir).await
+  }
+}
+
+// Kept separate from the GRPC calls so it can be tested on canned responses.
+async fn write_column_responses_to_dir<S>(
+  responses: S,
+  is_deleted: &[bool],
+  dir: &Path,
+) -> ClientResult<usize> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>> + Unpin,
+{
+  let mut scratch = DecodeScratch::default();
+  let gathered = read::gather_column_responses(responses, &mut scratch).await?;
+  let is_deleted = is_deleted.to_vec();
+  let dir = dir.to_path_buf();
+  tokio::task::spawn_blocking(move || write_gathered_to_dir(&gathered, &scratch, &is_deleted, &dir))
+    .await
+    .map_err(|e| ClientError::other(format!("export thread failed: {}", e)))?
+}
+
+fn write_gathered_to_dir(
+  gathered: &read::GatheredColumn,
+  scratch: &DecodeScratch,
+  is_deleted: &[bool],
+  dir: &Path,
+) -> ClientResult<usize> {
+  fs::create_dir_all(dir)
+    .map_err(|e| ClientError::other(format!("unable to create {}: {}", dir.display(), e)))?;
+
+  let column = ColumnMeta {
+    dtype: DataType::Bytes as i32,
+    nested_list_depth: 0,
+  };
+  let mut n_written = 0;
+  read::visit_gathered_column(
+    gathered,
+    scratch,
+    &column,
+    is_deleted,
+    |row_idx, fv| {
+      match fv.value {
+        Some(Value::BytesVal(bytes)) => {
+          let path = dir.join(row_idx.to_string());
+          fs::write(&path, bytes)
+            .map_err(|e| ClientError::other(format!("unable to write {}: {}", path.display(), e)))?;
+          n_written += 1;
+          Ok(())
+        },
+        None => Ok(()),
+        Some(other) => Err(ClientError::other(format!(
+          "expected bytes in row {} but found {:?}",
+          row_idx,
+          other,
+        ))),
+      }
+    },
+  )?;
+  Ok(n_written)
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_core::compression;
+  use pancake_db_idl::dml::FieldValue;
+
+  use super::*;
+
+  fn bytes_fv(bytes: &[u8]) -> FieldValue {
+    FieldValue {
+      value: Some(Value::BytesVal(bytes.to_vec())),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_write_to_dir() -> ClientResult<()> {
+    let fvs = [
+      bytes_fv(b"zero"),
+      bytes_fv(b"deleted"),
+      FieldValue::default(),
+      bytes_fv(&[0, 255, 3]),
+    ];
+    let compressed = compression::new_codec(DataType::Bytes, compression::ZSTD)?
+      .compress(&fvs[..2], 0)?;
+    let uncompressed = pancake_db_core::encoding::new_encoder(DataType::Bytes, 0)
+      .encode(&fvs[2..])?;
+    let responses = vec![
+      ReadSegmentColumnResponse {
+        codec: compression::ZSTD.to_string(),
+        data: compressed,
+        ..Default::default()
+      },
+      ReadSegmentColumnResponse {
+        data: uncompressed,
+        ..Default::default()
+      },
+    ];
+
+    let dir = std::env::temp_dir().join(format!("pancake-export-{}", uuid::Uuid::new_v4()));
+    let stream = futures::stream::iter(responses.into_iter().map(Ok));
+    let n_written = write_column_responses_to_dir(stream, &[false, true], &dir).await;
+
+    let mut files = fs::read_dir(&dir).unwrap()
+      .map(|entry| {
+        let entry = entry.unwrap();
+        (entry.file_name().into_string().unwrap(), fs::read(entry.path()).unwrap())
+      })
+      .collect::<Vec<_>>();
+    files.sort();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(n_written?, 2);
+    assert_eq!(files, vec![
+      ("0".to_string(), b"zero".to_vec()),
+      ("3".to_string(), vec![0, 255, 3]),
+    ]);
+    Ok(())
+  }
+}
@@ -1,13 +1,39 @@
+use std::net::{IpAddr, SocketAddr};
+
 use pancake_db_idl::ddl::*;
 use pancake_db_idl::dml::*;
 use pancake_db_idl::service::pancake_db_client::PancakeDbClient;
+use futures::{Stream, StreamExt};
 use tonic::codegen::StdError;
+use tonic::metadata::MetadataMap;
 use tonic::transport::Channel;
+use tonic::Status;
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::{SegmentColumnChunk, SegmentKey};
 
-use crate::errors::ClientResult;
+use shutdown::WriteTracker;
 
+pub use builder::ClientBuilder;
+
+mod builder;
+#[cfg(feature = "read")]
+mod conditional;
+#[cfg(feature = "read")]
+mod copy;
+mod ddl;
+#[cfg(feature = "read")]
+mod diff;
+#[cfg(feature = "read")]
+mod export;
+#[cfg(feature = "otel")]
+mod otel;
 #[cfg(feature = "read")]
 mod read;
+mod shutdown;
+mod storage;
+mod tail;
+mod write;
 
 /// The best way to communicate with a PancakeDB server from Rust.
 ///
@@ -16,6 +42,19 @@ mod read;
 /// format, `Client` supports some higher-level functionality for reading
 /// whole segments into a meaningful representation.
 ///
+/// With the `otel` feature, every request carries W3C trace context headers
+/// for the current OpenTelemetry context, so calls made within a span show
+/// up in distributed traces.
+///
+/// Every request also carries the client's default
+/// [`metadata`][Client::metadata_mut], e.g. a tenant ID for server-side
+/// rate-limiting.
+/// To override it for some calls, clone the client and change the clone's
+/// metadata.
+///
+/// Construct a client with [`connect`][Client::connect], [`ClientBuilder`],
+/// or [`from_grpc`][Client::from_grpc].
+///
 /// ```
 /// use pancake_db_client::Client;
 /// # use pancake_db_client::errors::ClientError;
@@ -31,63 +70,101 @@ pub struct Client {
   ///
   /// All client calls ultimately go through this.
   /// You can manually make low-level calls like `read_segment_columns` through
-  /// this GRPC client.
+  /// this GRPC client, though they skip the trace context headers that
+  /// `Client` methods attach with the `otel` feature, and the default
+  /// metadata.
   pub grpc: PancakeDbClient<Channel>,
+  metadata: MetadataMap,
+  write_tracker: WriteTracker,
 }
 
 impl Client {
   /// Creates a new client connected to the given endpoint.
   ///
   /// See [`tonic::transport::Endpoint`] for what qualifies as an endpoint.
-  /// One option is a string of format `"http://$HOST:$PORT"`
+  /// One option is a string of format `"http://$HOST:$PORT"`.
+  /// Use [`ClientBuilder`] to configure timeouts and other connection
+  /// options.
   pub async fn connect<D>(dst: D) -> ClientResult<Self> where
     D: std::convert::TryInto<tonic::transport::Endpoint>,
     D::Error: Into<StdError>,
   {
-    let grpc = PancakeDbClient::connect(dst).await?;
-    Ok(Client { grpc })
+    ClientBuilder::default().connect(dst).await
+  }
+
+  /// Creates a new client that sends requests through the given GRPC
+  /// client.
+  pub fn from_grpc(grpc: PancakeDbClient<Channel>) -> Self {
+    Client {
+      grpc,
+      metadata: MetadataMap::new(),
+      write_tracker: WriteTracker::default(),
+    }
+  }
+
+  /// Returns the GRPC metadata sent with every request.
+  pub fn metadata(&self) -> &MetadataMap {
+    &self.metadata
+  }
+
+  /// Returns the GRPC metadata sent with every request, for modification.
+  ///
+  /// Headers reserved by GRPC, such as `user-agent`, are dropped before
+  /// sending; use [`ClientBuilder::user_agent`] for that instead.
+  pub fn metadata_mut(&mut self) -> &mut MetadataMap {
+    &mut self.metadata
+  }
+
+  /// Creates a new client for the server at the IP address and port,
+  /// without waiting to connect.
+  ///
+  /// The connection is established on the first request, so this is
+  /// synchronous, but it must still be called within a Tokio runtime.
+  /// Use [`ClientBuilder::connect_lazy`] to configure connection options.
+  pub fn from_ip_port(ip: IpAddr, port: u16) -> ClientResult<Self> {
+    ClientBuilder::default().connect_lazy(ip_port_uri(ip, port))
   }
 
   /// Alters a table, e.g. by adding columns.
   pub async fn alter_table(&mut self, req: AlterTableRequest) -> ClientResult<AlterTableResponse> {
-    let resp = self.grpc.alter_table(req).await?.into_inner();
+    let resp = self.grpc.alter_table(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Creates or asserts or declaratively updates a table.
   pub async fn create_table(&mut self, req: CreateTableRequest) -> ClientResult<CreateTableResponse> {
-    let resp = self.grpc.create_table(req).await?.into_inner();
+    let resp = self.grpc.create_table(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Drops a table, deleting all its data.
   pub async fn drop_table(&mut self, req: DropTableRequest) -> ClientResult<DropTableResponse> {
-    let resp = self.grpc.drop_table(req).await?.into_inner();
+    let resp = self.grpc.drop_table(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Returns the table's schema.
   pub async fn get_schema(&mut self, req: GetSchemaRequest) -> ClientResult<GetSchemaResponse> {
-    let resp = self.grpc.get_schema(req).await?.into_inner();
+    let resp = self.grpc.get_schema(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Deletes specific rows from the segment.
   pub async fn delete_from_segment(&mut self, req: DeleteFromSegmentRequest) -> ClientResult<DeleteFromSegmentResponse> {
-    let resp = self.grpc.delete_from_segment(req).await?.into_inner();
+    let resp = self.grpc.delete_from_segment(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Lists of all tables.
   pub async fn list_tables(&mut self, req: ListTablesRequest) -> ClientResult<ListTablesResponse> {
-    let resp = self.grpc.list_tables(req).await?.into_inner();
+    let resp = self.grpc.list_tables(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
   /// Lists all segments in the table, optionally subject to a partition
   /// filter.
   pub async fn list_segments(&mut self, req: ListSegmentsRequest) -> ClientResult<ListSegmentsResponse> {
-    let resp = self.grpc.list_segments(req).await?.into_inner();
+    let resp = self.grpc.list_segments(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
@@ -96,10 +173,35 @@ impl Client {
   /// Uncommonly used; you should typically use
   /// [`Client::decode_segment`] instead.
   pub async fn read_segment_deletions(&mut self, req: ReadSegmentDeletionsRequest) -> ClientResult<ReadSegmentDeletionsResponse> {
-    let resp = self.grpc.read_segment_deletions(req).await?.into_inner();
+    let resp = self.grpc.read_segment_deletions(self.request(req)).await?.into_inner();
     Ok(resp)
   }
 
+  /// Streams the raw bytes of a segment column as the server sends them.
+  ///
+  /// Useful for proxying column data without decoding it or buffering the
+  /// whole column.
+  /// Each chunk carries its own codec, so the last chunk reports the codec of
+  /// the final compressed data.
+  pub async fn stream_segment_column_bytes(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_name: &str,
+    correlation_id: &str,
+  ) -> ClientResult<impl Stream<Item=ClientResult<SegmentColumnChunk>>> {
+    let req = ReadSegmentColumnRequest {
+      table_name: segment_key.table_name.to_string(),
+      partition: segment_key.partition.clone(),
+      segment_id: segment_key.segment_id.to_string(),
+      column_name: column_name.to_string(),
+      correlation_id: correlation_id.to_string(),
+    };
+    let responses = self.grpc.read_segment_column(self.request(req))
+      .await?
+      .into_inner();
+    Ok(column_chunks(responses))
+  }
+
   /// Writes rows to a partition of a table.
   ///
   /// The request can be easily constructed with macros:
@@ -122,8 +224,146 @@ impl Client {
   ///   ],
   /// };
   /// ```
+  ///
+  /// Requests are sent uncompressed.
+  /// The generated GRPC client has `send_gzip` and `accept_gzip`, but they
+  /// panic unless Tonic's `compression` feature is enabled, and the
+  /// generated server code in `pancake-db-idl` 0.2.0 doesn't compile with
+  /// that feature on.
+  ///
+  /// Fails without sending anything once [`shutdown`][Client::shutdown] has
+  /// been called.
   pub async fn write_to_partition(&mut self, req: WriteToPartitionRequest) -> ClientResult<WriteToPartitionResponse> {
-    let resp = self.grpc.write_to_partition(req).await?.into_inner();
+    let _in_flight = self.write_tracker.start_write()?;
+    let resp = self.grpc.write_to_partition(self.request(req)).await?.into_inner();
     Ok(resp)
   }
+
+  fn request<T>(&self, message: T) -> tonic::Request<T> {
+    request_with_metadata(&self.metadata, message)
+  }
+}
+
+// Wraps a message for the GRPC client, attaching the default metadata.
+// With the `otel` feature, this also attaches W3C trace context headers for
+// the current OpenTelemetry context, so calls made while a span is active
+// are correlated with it.
+// Calls made directly through `Client::grpc` skip this.
+fn request_with_metadata<T>(metadata: &MetadataMap, message: T) -> tonic::Request<T> {
+  let mut req = tonic::Request::new(message);
+  *req.metadata_mut() = metadata.clone();
+  #[cfg(feature = "otel")]
+  otel::inject_trace_context(req.metadata_mut());
+  req
+}
+
+fn ip_port_uri(ip: IpAddr, port: u16) -> String {
+  format!("http://{}", SocketAddr::new(ip, port))
+}
+
+fn column_chunks<S>(responses: S) -> impl Stream<Item=ClientResult<SegmentColumnChunk>> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>>,
+{
+  responses.map(|resp_res| {
+    let resp = resp_res.map_err(ClientError::from)?;
+    Ok(SegmentColumnChunk {
+      codec: resp.codec,
+      data: resp.data,
+      implicit_nulls_count: resp.implicit_nulls_count,
+    })
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use tonic::service::Interceptor;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_from_ip_port() {
+    let v4 = IpAddr::from([127, 0, 0, 1]);
+    let v6 = IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(ip_port_uri(v4, 3842), "http://127.0.0.1:3842");
+    assert_eq!(ip_port_uri(v6, 3842), "http://[::1]:3842");
+
+    for ip in [v4, v6] {
+      let endpoint = tonic::transport::Endpoint::from_shared(ip_port_uri(ip, 3842)).unwrap();
+      assert_eq!(endpoint.uri().port_u16(), Some(3842));
+      assert!(Client::from_ip_port(ip, 3842).is_ok());
+    }
+  }
+
+  // records the metadata of each request passing through
+  #[derive(Default)]
+  struct RecordingInterceptor(Vec<MetadataMap>);
+
+  impl Interceptor for RecordingInterceptor {
+    fn call(&mut self, req: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+      self.0.push(req.metadata().clone());
+      Ok(req)
+    }
+  }
+
+  #[tokio::test]
+  async fn test_default_metadata_on_requests() -> ClientResult<()> {
+    let mut client = ClientBuilder::default()
+      .user_agent("my-service")
+      .metadata("x-tenant-id", "acme")
+      .connect_lazy("http://localhost:3842")?;
+
+    let mut interceptor = RecordingInterceptor::default();
+    Interceptor::call(&mut interceptor, client.request(())).unwrap();
+
+    // overriding for some calls on a clone leaves the original alone
+    let mut overridden = client.clone();
+    overridden.metadata_mut().insert("x-tenant-id", "other".parse().unwrap());
+    Interceptor::call(&mut interceptor, overridden.request(())).unwrap();
+    client.metadata_mut().insert("x-request-source", "backfill".parse().unwrap());
+    Interceptor::call(&mut interceptor, client.request(())).unwrap();
+
+    let seen = interceptor.0;
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[0].get("x-tenant-id").unwrap(), "acme");
+    assert_eq!(seen[1].get("x-tenant-id").unwrap(), "other");
+    assert_eq!(seen[2].get("x-tenant-id").unwrap(), "acme");
+    assert_eq!(seen[2].get("x-request-source").unwrap(), "backfill");
+
+    assert!(ClientBuilder::default().metadata("bad key", "x").connect_lazy("http://localhost:3842").is_err());
+    assert!(ClientBuilder::default().user_agent("bad\nagent").connect_lazy("http://localhost:3842").is_err());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_column_chunks_in_order() {
+    let responses = (0..3_u8)
+      .map(|i| ReadSegmentColumnResponse {
+        codec: if i == 2 { "q_compress".to_string() } else { "".to_string() },
+        data: vec![i; 2],
+        ..Default::default()
+      })
+      .map(Ok);
+    let chunks = column_chunks(futures::stream::iter(responses))
+      .collect::<Vec<_>>()
+      .await
+      .into_iter()
+      .collect::<ClientResult<Vec<_>>>()
+      .unwrap();
+
+    let datas = chunks.iter()
+      .map(|chunk| chunk.data.clone())
+      .collect::<Vec<_>>();
+    assert_eq!(datas, vec![vec![0, 0], vec![1, 1], vec![2, 2]]);
+    assert_eq!(chunks.last().unwrap().codec, "q_compress");
+  }
+
+  #[tokio::test]
+  async fn test_column_chunks_error() {
+    let responses = vec![Err(Status::internal("oops"))];
+    let chunks = column_chunks(futures::stream::iter(responses))
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(chunks.len(), 1);
+    assert!(chunks[0].is_err());
+  }
 }
@@ -0,0 +1,56 @@
+use opentelemetry::Context;
+use opentelemetry::propagation::{Injector, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl<'a> Injector for MetadataInjector<'a> {
+  fn set(&mut self, key: &str, value: String) {
+    // propagators only produce valid header names and values, but skip any
+    // that aren't rather than failing the request
+    if let (Ok(key), Ok(value)) = (
+      MetadataKey::from_bytes(key.as_bytes()),
+      MetadataValue::from_str(&value),
+    ) {
+      self.0.insert(key, value);
+    }
+  }
+}
+
+// Adds W3C trace context headers for the current OpenTelemetry context, if
+// it has an active span.
+pub(super) fn inject_trace_context(metadata: &mut MetadataMap) {
+  TraceContextPropagator::new().inject_context(
+    &Context::current(),
+    &mut MetadataInjector(metadata),
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+
+  use super::*;
+
+  #[test]
+  fn test_traceparent_attached_with_active_span() {
+    let mut metadata = MetadataMap::new();
+    inject_trace_context(&mut metadata);
+    assert!(metadata.get("traceparent").is_none());
+
+    let span_context = SpanContext::new(
+      TraceId::from_bytes(0x4bf92f3577b34da6a3ce929d0e0e4736_u128.to_be_bytes()),
+      SpanId::from_bytes(0x00f067aa0ba902b7_u64.to_be_bytes()),
+      TraceFlags::SAMPLED,
+      true,
+      TraceState::default(),
+    );
+    let _guard = Context::current().with_remote_span_context(span_context).attach();
+    let req = super::super::request_with_metadata(&MetadataMap::new(), ());
+    assert_eq!(
+      req.metadata().get("traceparent").unwrap(),
+      "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+    );
+  }
+}
@@ -1,17 +1,40 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::future::Future;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use pancake_db_core::compression;
 use pancake_db_core::deletion;
 use pancake_db_core::encoding;
-use pancake_db_idl::dml::{FieldValue, ReadSegmentColumnRequest, ReadSegmentDeletionsRequest, Row};
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dml::partition_field_value::Value as PartitionValue;
+use pancake_db_idl::dml::{FieldValue, ListSegmentsRequest, ReadSegmentColumnRequest, ReadSegmentColumnResponse, ReadSegmentDeletionsRequest, RepeatedFieldValue, Row, Segment};
+use pancake_db_idl::dtype::DataType;
 use pancake_db_idl::schema::ColumnMeta;
+use tokio::time::Instant;
+use tonic::{Status, Streaming};
 
 use crate::errors::{ClientError, ClientResult};
-use crate::types::SegmentKey;
+use crate::types::{DecodeScratch, PartialSegment, RetryPolicy, SegmentKey};
 
 use super::Client;
 
+const ALL_DTYPES: [DataType; 7] = [
+  DataType::Int64,
+  DataType::String,
+  DataType::Float32,
+  DataType::Float64,
+  DataType::Bytes,
+  DataType::Bool,
+  DataType::TimestampMicros,
+];
+
+// how many consecutive responses without new data to tolerate from a
+// read_segment_column stream before assuming the server is stuck
+const MAX_STALLED_RESPONSES: usize = 16;
+
 /// Higher-level functionality.
 ///
 /// Use this for bulk reads.
@@ -43,7 +66,10 @@ impl Client {
     Ok(bools)
   }
 
-  /// Reads the segment column, following continuation tokens.
+  /// Reads the segment column from every response the server streams.
+  ///
+  /// Returns an error if the server keeps streaming responses that carry no
+  /// new data, rather than waiting on it forever.
   ///
   /// Typically you'll want to use the higher-level
   /// [`decode_segment`][Client::decode_segment] instead.
@@ -55,15 +81,71 @@ impl Client {
     is_deleted: &[bool],
     correlation_id: &str,
   ) -> ClientResult<Vec<FieldValue>> {
+    self.decode_segment_column_with_scratch(
+      segment_key,
+      column_name,
+      column,
+      is_deleted,
+      correlation_id,
+      &mut DecodeScratch::default(),
+    ).await
+  }
+
+  /// Reads the segment column, reusing the scratch's buffers.
+  ///
+  /// Like [`decode_segment_column`][Client::decode_segment_column], but
+  /// reusing one [`DecodeScratch`] across many calls avoids reallocating
+  /// the buffers for each column's raw bytes.
+  pub async fn decode_segment_column_with_scratch(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_name: &str,
+    column: &ColumnMeta,
+    is_deleted: &[bool],
+    correlation_id: &str,
+    scratch: &mut DecodeScratch,
+  ) -> ClientResult<Vec<FieldValue>> {
+    let responses = self.read_segment_column_stream(
+      segment_key,
+      column_name,
+      correlation_id,
+    ).await?;
+    decode_column_responses(responses, column, is_deleted, scratch, &DecodeBudget::unlimited()).await
+  }
+
+  /// Reads the segment column as a single IDL list value.
+  ///
+  /// Like [`decode_segment_column`][Client::decode_segment_column], but
+  /// returns the values wrapped in a [`RepeatedFieldValue`], the way nested
+  /// lists are represented elsewhere, for interop with other code consuming
+  /// the IDL types.
+  pub async fn decode_segment_column_repeated(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_name: &str,
+    column: &ColumnMeta,
+    is_deleted: &[bool],
+    correlation_id: &str,
+  ) -> ClientResult<RepeatedFieldValue> {
+    let responses = self.read_segment_column_stream(
+      segment_key,
+      column_name,
+      correlation_id,
+    ).await?;
+    decode_repeated_column_responses(responses, column, is_deleted).await
+  }
+
+  pub(super) async fn read_segment_column_stream(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_name: &str,
+    correlation_id: &str,
+  ) -> ClientResult<Streaming<ReadSegmentColumnResponse>> {
     let SegmentKey {
       table_name,
       partition,
       segment_id,
     } = segment_key;
-    let mut compressed_bytes = Vec::new();
-    let mut uncompressed_bytes = Vec::new();
-    let mut codec = "".to_string();
-    let mut implicit_nulls_count = 0;
     let req = ReadSegmentColumnRequest {
       table_name: table_name.to_string(),
       partition: partition.clone(),
@@ -71,76 +153,274 @@ impl Client {
       column_name: column_name.to_string(),
       correlation_id: correlation_id.to_string(),
     };
-    let mut read_segment_stream = self.grpc.read_segment_column(req)
+    let read_segment_stream = self.grpc.read_segment_column(self.request(req))
       .await?
       .into_inner();
-    while let Some(resp_res) = read_segment_stream.next().await {
-      let resp = resp_res?;
-      if resp.codec.is_empty() {
-        uncompressed_bytes.extend(&resp.data);
-      } else {
-        compressed_bytes.extend(&resp.data);
-        codec = resp.codec.clone();
-      }
-      implicit_nulls_count = resp.implicit_nulls_count;
-    }
+    Ok(read_segment_stream)
+  }
 
-    let mut res = Vec::new();
+  /// Reads multiple columns for the same segment and applies deletion data.
+  ///
+  /// Columns are read concurrently.
+  pub async fn decode_segment(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<Vec<Row>> {
+    let correlation_id = crate::utils::new_correlation_id();
+    self.decode_segment_with_correlation_id(segment_key, columns, &correlation_id).await
+  }
 
-    let dtype = column.dtype();
-    let mut row_idx = 0;
-    if !compressed_bytes.is_empty() {
-      if implicit_nulls_count > 0 {
-        return Err(ClientError::other(
-          "contradictory read responses containing both compacted and implicit data received".to_string()
-        ));
-      }
+  /// Reads multiple columns of every segment in the table.
+  ///
+  /// Each segment is read under its own fresh correlation ID, shared by its
+  /// deletion read and all its column reads, so every segment is internally
+  /// consistent.
+  /// Segments are read one after another, and their rows are concatenated.
+  pub async fn read_table_consistent(
+    &mut self,
+    table_name: &str,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<Vec<Row>> {
+    let list_resp = self.list_segments(ListSegmentsRequest {
+      table_name: table_name.to_string(),
+      ..Default::default()
+    }).await?;
+    let segment_keys = list_resp.segments.into_iter()
+      .map(|segment| SegmentKey {
+        table_name: table_name.to_string(),
+        partition: segment.partition,
+        segment_id: segment.segment_id,
+      })
+      .collect();
 
-      let decompressor = compression::new_codec(
-        dtype,
-        &codec,
-      )?;
-      let fvs = decompressor.decompress(
-        &compressed_bytes,
-        column.nested_list_depth as u8,
-      )?;
-      for fv in fvs {
-        if row_idx >= is_deleted.len() || !is_deleted[row_idx] {
-          res.push(fv);
-        }
-        row_idx += 1
+    let client = self.clone();
+    read_segments_with_fresh_ids(segment_keys, |segment_key, correlation_id| {
+      let mut client = client.clone();
+      async move {
+        client.decode_segment_with_correlation_id(
+          &segment_key,
+          columns,
+          &correlation_id,
+        ).await
       }
-    }
+    }).await
+  }
 
-    for _ in 0..implicit_nulls_count {
-      if row_idx >= is_deleted.len() || !is_deleted[row_idx] {
-        res.push(FieldValue::default());
-      }
-      row_idx += 1;
-    }
+  /// Reads up to `n` of the table's most recent rows, newest first.
+  ///
+  /// Segments are read from newest to oldest, judged by the latest
+  /// timestamp among their partition values, and rows are taken from the
+  /// end of each segment.
+  /// Reading stops once `n` rows are gathered, so older segments are never
+  /// read.
+  ///
+  /// PancakeDB doesn't record when rows were written, so this is only as
+  /// accurate as the table's partitioning.
+  /// Segments without a timestamp partition value are read after all
+  /// others, in the reverse of the order they are listed, which need not
+  /// match the order they were written.
+  /// Within a segment, rows are assumed to be stored in the order they were
+  /// written.
+  pub async fn tail_rows(
+    &mut self,
+    table_name: &str,
+    n: usize,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<Vec<Row>> {
+    let list_resp = self.list_segments(ListSegmentsRequest {
+      table_name: table_name.to_string(),
+      ..Default::default()
+    }).await?;
 
-    if !uncompressed_bytes.is_empty() {
-      let decoder = encoding::new_field_value_decoder(
-        dtype,
-        column.nested_list_depth as u8,
-      );
-      for fv in decoder.decode(&uncompressed_bytes)? {
-        if row_idx >= is_deleted.len() || !is_deleted[row_idx] {
-          res.push(fv);
-        }
-        row_idx += 1
+    let client = self.clone();
+    tail_rows_of(list_resp.segments, n, |segment| {
+      let mut client = client.clone();
+      let segment_key = SegmentKey {
+        table_name: table_name.to_string(),
+        partition: segment.partition,
+        segment_id: segment.segment_id,
+      };
+      async move {
+        client.decode_segment(&segment_key, columns).await
       }
-    }
+    }).await
+  }
 
-    Ok(res)
+  /// Reads multiple columns for the same segment, giving up once the
+  /// deadline passes.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but returns an error
+  /// of kind [`ClientErrorKind::Timeout`][crate::errors::ClientErrorKind::Timeout]
+  /// if the deadline passes first, abandoning any outstanding requests.
+  pub async fn decode_segment_with_deadline(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    deadline: Instant,
+  ) -> ClientResult<Vec<Row>> {
+    crate::utils::with_deadline(deadline, self.decode_segment(segment_key, columns)).await
   }
 
-  /// Reads multiple columns for the same segment and applies deletion data.
-  pub async fn decode_segment(
+  /// Reads multiple columns for the same segment, tolerating failures of
+  /// individual columns.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but a column that fails
+  /// to read is left out of the rows and its error is reported instead.
+  /// Failing to read the segment's deletion data still fails the whole call,
+  /// since no column can be decoded correctly without it.
+  pub async fn decode_segment_partial(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<PartialSegment> {
+    let correlation_id = crate::utils::new_correlation_id();
+    let column_results = self.decode_columns(
+      segment_key,
+      columns,
+      &correlation_id,
+      &RetryPolicy::no_retries(),
+      &DecodeBudget::unlimited(),
+    ).await?;
+    Ok(partial_segment(column_results))
+  }
+
+  /// Reads multiple columns for the same segment, retrying each read
+  /// independently.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but when the deletion
+  /// read or a column read fails with a transient error, only that read is
+  /// retried according to the policy.
+  /// The segment read fails only once some read exhausts its retries.
+  pub async fn decode_segment_with_retries(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    retry_policy: &RetryPolicy,
+  ) -> ClientResult<Vec<Row>> {
+    let correlation_id = crate::utils::new_correlation_id();
+    self.decode_segment_with_policy(segment_key, columns, &correlation_id, retry_policy).await
+  }
+
+  async fn decode_segment_with_correlation_id(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    correlation_id: &str,
+  ) -> ClientResult<Vec<Row>> {
+    self.decode_segment_with_policy(
+      segment_key,
+      columns,
+      correlation_id,
+      &RetryPolicy::no_retries(),
+    ).await
+  }
+
+  async fn decode_segment_with_policy(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    correlation_id: &str,
+    retry_policy: &RetryPolicy,
+  ) -> ClientResult<Vec<Row>> {
+    let column_results = self.decode_columns(
+      segment_key,
+      columns,
+      correlation_id,
+      retry_policy,
+      &DecodeBudget::unlimited(),
+    ).await?;
+    Ok(assemble_rows(column_values(column_results)?))
+  }
+
+  /// Reads multiple columns for the same segment, keeping only rows that
+  /// match the predicate.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but each row is
+  /// checked as it is assembled and dropped unless the predicate holds.
+  /// This filters after decoding, not by pushing the predicate down to the
+  /// server: every column is still read and decoded in full.
+  /// It saves building and holding rows you would discard anyway.
+  pub async fn decode_segment_filtered<F>(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    predicate: F,
+  ) -> ClientResult<Vec<Row>> where F: Fn(&Row) -> bool {
+    let correlation_id = crate::utils::new_correlation_id();
+    let column_results = self.decode_columns(
+      segment_key,
+      columns,
+      &correlation_id,
+      &RetryPolicy::no_retries(),
+      &DecodeBudget::unlimited(),
+    ).await?;
+    Ok(assemble_matching_rows(column_values(column_results)?, predicate))
+  }
+
+  /// Reads multiple columns for the same segment, returning each column's
+  /// values instead of rows.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but skips assembling
+  /// rows, which saves work when processing one column at a time.
+  /// Deleted rows are filtered out of every column, and all columns are
+  /// truncated to the same length, so the values at each index form the row
+  /// `decode_segment` would return.
+  pub async fn decode_segment_columnar(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<HashMap<String, Vec<FieldValue>>> {
+    let correlation_id = crate::utils::new_correlation_id();
+    let column_results = self.decode_columns(
+      segment_key,
+      columns,
+      &correlation_id,
+      &RetryPolicy::no_retries(),
+      &DecodeBudget::unlimited(),
+    ).await?;
+    Ok(aligned_columns(column_values(column_results)?))
+  }
+
+  /// Reads multiple columns for the same segment, giving up once the decoded
+  /// values grow too large.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but keeps a running
+  /// estimate of the memory taken by all decoded values and returns an error
+  /// as soon as it exceeds `max_decoded_bytes`, rather than finishing the
+  /// decode.
+  /// The estimate is rough: it counts each value's in-memory size plus its
+  /// string, bytes, or list contents, but not allocator overhead or the
+  /// compressed data held while decoding.
+  /// Values are checked against the limit as they are built, so even a
+  /// single oversized column stops decoding once it crosses the limit.
+  pub async fn decode_segment_with_byte_limit(
     &mut self,
     segment_key: &SegmentKey,
     columns: &HashMap<String, ColumnMeta>,
+    max_decoded_bytes: usize,
   ) -> ClientResult<Vec<Row>> {
+    let correlation_id = crate::utils::new_correlation_id();
+    let column_results = self.decode_columns(
+      segment_key,
+      columns,
+      &correlation_id,
+      &RetryPolicy::no_retries(),
+      &DecodeBudget::new(max_decoded_bytes),
+    ).await?;
+    Ok(assemble_rows(column_values(column_results)?))
+  }
+
+  /// Reads multiple columns for the same segment, keeping deleted rows.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but instead of
+  /// filtering out deleted rows, pairs every row with whether it has been
+  /// deleted.
+  pub async fn decode_segment_with_tombstones(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+  ) -> ClientResult<Vec<(bool, Row)>> {
     if columns.is_empty() {
       return Err(ClientError::other(
         "unable to decode segment with no columns specified".to_string()
@@ -150,26 +430,1151 @@ impl Client {
     let correlation_id = crate::utils::new_correlation_id();
 
     let is_deleted = self.decode_is_deleted(segment_key, &correlation_id).await?;
+    let column_results = self.decode_columns_with_deletions(
+      segment_key,
+      columns,
+      &[],
+      &correlation_id,
+      &RetryPolicy::no_retries(),
+      &DecodeBudget::unlimited(),
+    ).await;
+    Ok(flag_tombstones(assemble_rows(column_values(column_results)?), &is_deleted))
+  }
 
-    let mut n = usize::MAX;
-    let mut rows = Vec::new();
-    for (column_name, column_meta) in columns {
-      let fvalues = self.decode_segment_column(
-        segment_key,
-        column_name,
-        column_meta,
-        &is_deleted,
-        &correlation_id,
-      ).await?;
-      n = n.min(fvalues.len());
-      for _ in rows.len()..n {
-        rows.push(Row::default());
+  /// Reads multiple columns for the same segment, applying deletion data
+  /// the caller already read.
+  ///
+  /// Like [`decode_segment`][Client::decode_segment], but uses `is_deleted`
+  /// instead of reading the segment's deletion data again, saving a round
+  /// trip when it was already read with
+  /// [`decode_is_deleted`][Client::decode_is_deleted].
+  /// Pass the same correlation ID that read `is_deleted`, so the columns are
+  /// consistent with it.
+  ///
+  /// Returns an error if `is_deleted` covers more rows than the segment's
+  /// columns hold.
+  pub async fn decode_segment_with_deletions(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    is_deleted: &[bool],
+    correlation_id: &str,
+  ) -> ClientResult<Vec<Row>> {
+    if columns.is_empty() {
+      return Err(ClientError::other(
+        "unable to decode segment with no columns specified".to_string()
+      ))
+    }
+
+    let client = self.clone();
+    decode_rows_with_deletions(columns, is_deleted, |column_name, column_meta| {
+      let mut client = client.clone();
+      async move {
+        let responses = client.read_segment_column_stream(
+          segment_key,
+          column_name,
+          correlation_id,
+        ).await?;
+        decode_column_responses(
+          responses,
+          column_meta,
+          &[],
+          &mut DecodeScratch::default(),
+          &DecodeBudget::unlimited(),
+        ).await
+      }
+    }).await
+  }
+
+  async fn decode_columns(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    correlation_id: &str,
+    retry_policy: &RetryPolicy,
+    budget: &DecodeBudget,
+  ) -> ClientResult<Vec<(String, ClientResult<Vec<FieldValue>>)>> {
+    if columns.is_empty() {
+      return Err(ClientError::other(
+        "unable to decode segment with no columns specified".to_string()
+      ))
+    }
+
+    let client = self.clone();
+    let is_deleted = crate::utils::retry(retry_policy, || {
+      let mut client = client.clone();
+      async move { client.decode_is_deleted(segment_key, correlation_id).await }
+    }).await?;
+
+    Ok(self.decode_columns_with_deletions(
+      segment_key,
+      columns,
+      &is_deleted,
+      correlation_id,
+      retry_policy,
+      budget,
+    ).await)
+  }
+
+  async fn decode_columns_with_deletions(
+    &mut self,
+    segment_key: &SegmentKey,
+    columns: &HashMap<String, ColumnMeta>,
+    is_deleted: &[bool],
+    correlation_id: &str,
+    retry_policy: &RetryPolicy,
+    budget: &DecodeBudget,
+  ) -> Vec<(String, ClientResult<Vec<FieldValue>>)> {
+    let client = self.clone();
+    read_columns_with_retries(columns, retry_policy, |column_name, column_meta| {
+      let mut client = client.clone();
+      async move {
+        let responses = client.read_segment_column_stream(
+          segment_key,
+          column_name,
+          correlation_id,
+        ).await?;
+        decode_column_responses(
+          responses,
+          column_meta,
+          is_deleted,
+          &mut DecodeScratch::default(),
+          budget,
+        ).await
       }
-      for i in 0..n {
-        rows[i].fields.insert(column_name.clone(), fvalues[i].clone());
+    }).await
+  }
+}
+
+// Reads columns concurrently, retrying each one independently.
+async fn read_columns_with_retries<'a, F, Fut>(
+  columns: &'a HashMap<String, ColumnMeta>,
+  retry_policy: &RetryPolicy,
+  read_column: F,
+) -> Vec<(String, ClientResult<Vec<FieldValue>>)> where
+  F: Fn(&'a str, &'a ColumnMeta) -> Fut,
+  Fut: Future<Output=ClientResult<Vec<FieldValue>>>,
+{
+  let column_futures = columns.iter()
+    .map(|(column_name, column_meta)| {
+      let read_column = &read_column;
+      async move {
+        let fvalues_result = crate::utils::retry(
+          retry_policy,
+          || read_column(column_name, column_meta),
+        ).await;
+        (column_name.clone(), fvalues_result)
       }
+    });
+  futures::future::join_all(column_futures).await
+}
+
+// Reads every column in full, then drops the deleted rows, so the deletion
+// data can be checked against the columns' length.
+async fn decode_rows_with_deletions<'a, F, Fut>(
+  columns: &'a HashMap<String, ColumnMeta>,
+  is_deleted: &[bool],
+  read_column: F,
+) -> ClientResult<Vec<Row>> where
+  F: Fn(&'a str, &'a ColumnMeta) -> Fut,
+  Fut: Future<Output=ClientResult<Vec<FieldValue>>>,
+{
+  let column_results = read_columns_with_retries(
+    columns,
+    &RetryPolicy::no_retries(),
+    read_column,
+  ).await;
+  let rows = assemble_rows(column_values(column_results)?);
+  if is_deleted.len() > rows.len() {
+    return Err(ClientError::other(format!(
+      "deletion data covers {} rows but the segment's columns hold only {}",
+      is_deleted.len(),
+      rows.len(),
+    )));
+  }
+
+  Ok(flag_tombstones(rows, is_deleted).into_iter()
+    .filter_map(|(deleted, row)| (!deleted).then_some(row))
+    .collect())
+}
+
+// Reads segments from newest to oldest, keeping the last rows of each until
+// n rows are gathered.
+async fn tail_rows_of<F, Fut>(
+  segments: Vec<Segment>,
+  n: usize,
+  read_segment: F,
+) -> ClientResult<Vec<Row>> where
+  F: Fn(Segment) -> Fut,
+  Fut: Future<Output=ClientResult<Vec<Row>>>,
+{
+  let mut rows = Vec::new();
+  for segment in newest_first(segments) {
+    if rows.len() >= n {
+      break;
     }
+    let segment_rows = read_segment(segment).await?;
+    let n_remaining = n - rows.len();
+    rows.extend(segment_rows.into_iter().rev().take(n_remaining));
+  }
+  Ok(rows)
+}
+
+// Sorts by each segment's latest partition timestamp, descending; segments
+// without one go last, in reverse listing order.
+fn newest_first(mut segments: Vec<Segment>) -> Vec<Segment> {
+  segments.reverse();
+  segments.sort_by_key(|segment| Reverse(latest_partition_timestamp(segment)));
+  segments
+}
+
+fn latest_partition_timestamp(segment: &Segment) -> Option<(i64, i32)> {
+  segment.partition.values()
+    .filter_map(|pfv| match &pfv.value {
+      Some(PartitionValue::TimestampVal(t)) => Some((t.seconds, t.nanos)),
+      _ => None,
+    })
+    .max()
+}
+
+// Reads segments in order, generating a fresh correlation ID for each.
+async fn read_segments_with_fresh_ids<F, Fut>(
+  segment_keys: Vec<SegmentKey>,
+  read_segment: F,
+) -> ClientResult<Vec<Row>> where
+  F: Fn(SegmentKey, String) -> Fut,
+  Fut: Future<Output=ClientResult<Vec<Row>>>,
+{
+  let mut rows = Vec::new();
+  for segment_key in segment_keys {
+    let correlation_id = crate::utils::new_correlation_id();
+    rows.extend(read_segment(segment_key, correlation_id).await?);
+  }
+  Ok(rows)
+}
+
+// Decodes the responses of a read_segment_column call.
+// Kept separate from the GRPC call so it can be tested on canned responses.
+async fn decode_column_responses<S>(
+  responses: S,
+  column: &ColumnMeta,
+  is_deleted: &[bool],
+  scratch: &mut DecodeScratch,
+  budget: &DecodeBudget,
+) -> ClientResult<Vec<FieldValue>> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>> + Unpin,
+{
+  let mut res = Vec::new();
+  visit_column_responses(responses, column, is_deleted, scratch, |_, fv| {
+    budget.charge(&fv)?;
+    res.push(fv);
+    Ok(())
+  }).await?;
+  Ok(res)
+}
+
+// Decodes the responses of a read_segment_column call into one list value.
+// Kept separate from the GRPC call so it can be tested on canned responses.
+async fn decode_repeated_column_responses<S>(
+  responses: S,
+  column: &ColumnMeta,
+  is_deleted: &[bool],
+) -> ClientResult<RepeatedFieldValue> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>> + Unpin,
+{
+  let vals = decode_column_responses(
+    responses,
+    column,
+    is_deleted,
+    &mut DecodeScratch::default(),
+    &DecodeBudget::unlimited(),
+  ).await?;
+  Ok(RepeatedFieldValue { vals })
+}
+
+// Decodes the responses of a read_segment_column call, passing each live
+// row's index and value to the visitor in order.
+async fn visit_column_responses<S, F>(
+  responses: S,
+  column: &ColumnMeta,
+  is_deleted: &[bool],
+  scratch: &mut DecodeScratch,
+  visit: F,
+) -> ClientResult<()> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>> + Unpin,
+  F: FnMut(usize, FieldValue) -> ClientResult<()>,
+{
+  let gathered = gather_column_responses(responses, scratch).await?;
+  visit_gathered_column(&gathered, scratch, column, is_deleted, visit)
+}
+
+// What the responses of a read_segment_column call said besides their data,
+// which is gathered into a scratch.
+pub(super) struct GatheredColumn {
+  codec: String,
+  implicit_nulls_count: u32,
+}
+
+// Collects the raw data of a read_segment_column call into the scratch.
+pub(super) async fn gather_column_responses<S>(
+  mut responses: S,
+  scratch: &mut DecodeScratch,
+) -> ClientResult<GatheredColumn> where
+  S: Stream<Item=Result<ReadSegmentColumnResponse, Status>> + Unpin,
+{
+  let DecodeScratch { compressed_bytes, uncompressed_bytes } = scratch;
+  compressed_bytes.clear();
+  uncompressed_bytes.clear();
+  let mut codec = "".to_string();
+  let mut implicit_nulls_count = 0;
+  let mut n_stalled_responses = 0;
+  while let Some(resp_res) = responses.next().await {
+    let resp = resp_res?;
+    if resp.data.is_empty() && resp.implicit_nulls_count == implicit_nulls_count {
+      n_stalled_responses += 1;
+      if n_stalled_responses > MAX_STALLED_RESPONSES {
+        return Err(ClientError::other(format!(
+          "read responses stopped making progress after {} responses without new data",
+          n_stalled_responses,
+        )));
+      }
+    } else {
+      n_stalled_responses = 0;
+    }
+
+    if resp.codec.is_empty() {
+      uncompressed_bytes.extend(&resp.data);
+    } else {
+      compressed_bytes.extend(&resp.data);
+      codec = resp.codec.clone();
+    }
+    implicit_nulls_count = resp.implicit_nulls_count;
+  }
+  Ok(GatheredColumn { codec, implicit_nulls_count })
+}
+
+// Decodes a gathered column, passing each live row's index and value to the
+// visitor in order.
+// Each value is built only when it's visited, so the visitor can stop the
+// decode before the rest of the column is built.
+pub(super) fn visit_gathered_column<F>(
+  gathered: &GatheredColumn,
+  scratch: &DecodeScratch,
+  column: &ColumnMeta,
+  is_deleted: &[bool],
+  mut visit: F,
+) -> ClientResult<()> where
+  F: FnMut(usize, FieldValue) -> ClientResult<()>,
+{
+  let GatheredColumn { codec, implicit_nulls_count } = gathered;
+  let implicit_nulls_count = *implicit_nulls_count;
+  let DecodeScratch { compressed_bytes, uncompressed_bytes } = scratch;
+  let dtype = column.dtype();
+  let mut row_idx = 0;
+  if !compressed_bytes.is_empty() {
+    if implicit_nulls_count > 0 {
+      return Err(ClientError::other(
+        "contradictory read responses containing both compacted and implicit data received".to_string()
+      ));
+    }
+
+    // Segment metadata doesn't record the stored dtype, but the codec
+    // implies which dtypes it could have been, so we can at least catch
+    // mismatches it rules out before decoding garbage.
+    let decompressor = compression::new_codec(dtype, codec)
+      .map_err(|e| {
+        let other_dtype_supports = ALL_DTYPES.iter()
+          .any(|&other| other != dtype && compression::supports(other, codec));
+        if other_dtype_supports {
+          ClientError::other(format!(
+            "column was stored with codec {}, which cannot hold the requested data type {:?}; \
+            check the requested column meta against the table schema",
+            codec,
+            dtype,
+          ))
+        } else {
+          ClientError::from(e)
+        }
+      })?;
+    let fvs = decompressor.decompress_iter(
+      compressed_bytes,
+      column.nested_list_depth as u8,
+    )?;
+    for fv in fvs {
+      let fv = fv?;
+      if !is_row_deleted(is_deleted, row_idx) {
+        visit(row_idx, fv)?;
+      }
+      row_idx += 1
+    }
+  }
+
+  for _ in 0..implicit_nulls_count {
+    if !is_row_deleted(is_deleted, row_idx) {
+      visit(row_idx, FieldValue::default())?;
+    }
+    row_idx += 1;
+  }
+
+  if !uncompressed_bytes.is_empty() {
+    let decoder = encoding::new_field_value_decoder(
+      dtype,
+      column.nested_list_depth as u8,
+    );
+    for fv in decoder.decode_iter(uncompressed_bytes) {
+      let fv = fv?;
+      if !is_row_deleted(is_deleted, row_idx) {
+        visit(row_idx, fv)?;
+      }
+      row_idx += 1
+    }
+  }
+
+  Ok(())
+}
+
+// Tracks the estimated size of values decoded so far, shared by all columns
+// of a segment read.
+struct DecodeBudget {
+  max_bytes: usize,
+  used_bytes: AtomicUsize,
+}
+
+impl DecodeBudget {
+  fn new(max_bytes: usize) -> Self {
+    DecodeBudget {
+      max_bytes,
+      used_bytes: AtomicUsize::new(0),
+    }
+  }
+
+  fn unlimited() -> Self {
+    Self::new(usize::MAX)
+  }
+
+  fn charge(&self, fv: &FieldValue) -> ClientResult<()> {
+    if self.max_bytes == usize::MAX {
+      return Ok(());
+    }
+
+    let size = estimated_size(fv);
+    let used = self.used_bytes.fetch_add(size, Ordering::Relaxed) + size;
+    if used > self.max_bytes {
+      return Err(ClientError::other(format!(
+        "decoded values exceeded the limit of {} bytes",
+        self.max_bytes,
+      )));
+    }
+    Ok(())
+  }
+}
+
+// A rough estimate of the memory a decoded value holds: its own size plus
+// whatever it owns on the heap.
+fn estimated_size(fv: &FieldValue) -> usize {
+  let owned = match &fv.value {
+    None => 0,
+    Some(Value::BoolVal(_)) => 0,
+    Some(Value::Int64Val(_)) => 0,
+    Some(Value::Float32Val(_)) => 0,
+    Some(Value::Float64Val(_)) => 0,
+    Some(Value::TimestampVal(_)) => 0,
+    Some(Value::StringVal(s)) => s.len(),
+    Some(Value::BytesVal(b)) => b.len(),
+    Some(Value::ListVal(list)) => list.vals.iter().map(estimated_size).sum(),
+  };
+  mem::size_of::<FieldValue>() + owned
+}
+
+// The deletion bitmap only extends through the last deleted row, so any row
+// past its end is live.
+fn is_row_deleted(is_deleted: &[bool], row_idx: usize) -> bool {
+  is_deleted.get(row_idx).copied().unwrap_or(false)
+}
+
+fn partial_segment(column_results: Vec<(String, ClientResult<Vec<FieldValue>>)>) -> PartialSegment {
+  let mut column_values = Vec::new();
+  let mut column_errors = HashMap::new();
+  for (column_name, fvalues_result) in column_results {
+    match fvalues_result {
+      Ok(fvalues) => column_values.push((column_name, fvalues)),
+      Err(err) => {
+        column_errors.insert(column_name, err);
+      },
+    }
+  }
+
+  PartialSegment {
+    rows: assemble_rows(column_values),
+    column_errors,
+  }
+}
+
+// Rows past the end of the deletion data have not been deleted.
+fn flag_tombstones(rows: Vec<Row>, is_deleted: &[bool]) -> Vec<(bool, Row)> {
+  rows.into_iter()
+    .enumerate()
+    .map(|(row_idx, row)| (is_deleted.get(row_idx).copied().unwrap_or(false), row))
+    .collect()
+}
+
+// Unwraps each column's result, failing on the first column that failed.
+fn column_values(
+  column_results: Vec<(String, ClientResult<Vec<FieldValue>>)>,
+) -> ClientResult<Vec<(String, Vec<FieldValue>)>> {
+  column_results.into_iter()
+    .map(|(column_name, fvalues_result)| Ok((column_name, fvalues_result?)))
+    .collect()
+}
+
+// Truncates columns to the length of the shortest, as row assembly does.
+fn aligned_columns(column_values: Vec<(String, Vec<FieldValue>)>) -> HashMap<String, Vec<FieldValue>> {
+  let n = column_values.iter()
+    .map(|(_, fvalues)| fvalues.len())
+    .min()
+    .unwrap_or(0);
+  column_values.into_iter()
+    .map(|(column_name, mut fvalues)| {
+      fvalues.truncate(n);
+      (column_name, fvalues)
+    })
+    .collect()
+}
+
+// Zips decoded columns into rows, truncating to the shortest column.
+fn assemble_rows(column_values: Vec<(String, Vec<FieldValue>)>) -> Vec<Row> {
+  assemble_matching_rows(column_values, |_| true)
+}
+
+// Builds rows one at a time so rows failing the predicate are dropped
+// before the next is built.
+fn assemble_matching_rows<F>(
+  column_values: Vec<(String, Vec<FieldValue>)>,
+  predicate: F,
+) -> Vec<Row> where F: Fn(&Row) -> bool {
+  let n = column_values.iter()
+    .map(|(_, fvalues)| fvalues.len())
+    .min()
+    .unwrap_or(0);
+  let mut columns = column_values.into_iter()
+    .map(|(column_name, fvalues)| (column_name, fvalues.into_iter()))
+    .collect::<Vec<_>>();
+  let mut rows = Vec::new();
+  for _ in 0..n {
+    let mut row = Row::default();
+    for (column_name, fvalues) in &mut columns {
+      if let Some(fv) = fvalues.next() {
+        row.fields.insert(column_name.clone(), fv);
+      }
+    }
+    if predicate(&row) {
+      rows.push(row);
+    }
+  }
+  rows
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::{PartitionFieldValue, RepeatedFieldValue};
+
+  use crate::make_row;
+
+  use super::*;
+
+  fn int_fvs(xs: &[i64]) -> Vec<FieldValue> {
+    xs.iter()
+      .map(|&x| FieldValue {
+        value: Some(Value::Int64Val(x)),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_assemble_rows_order_independent() {
+    let a = ("a".to_string(), int_fvs(&[1, 2, 3]));
+    let b = ("b".to_string(), int_fvs(&[4, 5]));
+    let rows = assemble_rows(vec![a.clone(), b.clone()]);
+    assert_eq!(rows, assemble_rows(vec![b, a]));
+    assert_eq!(rows, vec![
+      make_row! { "a" => 1_i64, "b" => 4_i64 },
+      make_row! { "a" => 2_i64, "b" => 5_i64 },
+    ]);
+  }
+
+  #[test]
+  fn test_assemble_matching_rows() {
+    let a = ("a".to_string(), int_fvs(&[1, 2, 3, 4, 5]));
+    let b = ("b".to_string(), int_fvs(&[10, 20, 30, 40]));
+    let is_even = |row: &Row| matches!(
+      row.fields["a"].value,
+      Some(Value::Int64Val(x)) if x % 2 == 0
+    );
+    assert_eq!(assemble_matching_rows(vec![a, b], is_even), vec![
+      make_row! { "a" => 2_i64, "b" => 20_i64 },
+      make_row! { "a" => 4_i64, "b" => 40_i64 },
+    ]);
+  }
+
+  #[test]
+  fn test_aligned_columns_match_rows() {
+    let column_values = vec![
+      ("a".to_string(), int_fvs(&[1, 2, 3])),
+      ("b".to_string(), int_fvs(&[4, 5])),
+    ];
+    let columns = aligned_columns(column_values.clone());
+    assert_eq!(columns["a"], int_fvs(&[1, 2]));
+    assert_eq!(columns["b"], int_fvs(&[4, 5]));
+
+    let rows = assemble_rows(column_values);
+    let columnar_rows = assemble_rows(columns.into_iter().collect());
+    assert_eq!(columnar_rows, rows);
+  }
+
+  #[test]
+  fn test_partial_segment() {
+    let column_results = vec![
+      ("a".to_string(), Ok(int_fvs(&[1, 2]))),
+      ("b".to_string(), Err(ClientError::other("read failed".to_string()))),
+      ("c".to_string(), Ok(int_fvs(&[3, 4]))),
+    ];
+    let partial = partial_segment(column_results);
+    assert_eq!(partial.rows, vec![
+      make_row! { "a" => 1_i64, "c" => 3_i64 },
+      make_row! { "a" => 2_i64, "c" => 4_i64 },
+    ]);
+    assert_eq!(partial.column_errors.len(), 1);
+    assert_eq!(
+      partial.column_errors["b"],
+      ClientError::other("read failed".to_string()),
+    );
+  }
+
+  #[test]
+  fn test_flag_tombstones() {
+    let rows = assemble_rows(vec![("a".to_string(), int_fvs(&[1, 2, 3, 4]))]);
+    let flagged = flag_tombstones(rows, &[false, true, false]);
+    assert_eq!(flagged, vec![
+      (false, make_row! { "a" => 1_i64 }),
+      (true, make_row! { "a" => 2_i64 }),
+      (false, make_row! { "a" => 3_i64 }),
+      (false, make_row! { "a" => 4_i64 }),
+    ]);
+  }
+
+  #[tokio::test]
+  async fn test_decode_rows_with_deletions() -> ClientResult<()> {
+    let mut columns = HashMap::new();
+    columns.insert("a".to_string(), int_column());
+    columns.insert("b".to_string(), int_column());
+    let reads = std::sync::Mutex::new(Vec::new());
+    let read_column = |column_name: &str, _: &ColumnMeta| {
+      reads.lock().unwrap().push(column_name.to_string());
+      let xs = if column_name == "a" { [1, 2, 3] } else { [4, 5, 6] };
+      async move { Ok(int_fvs(&xs)) }
+    };
+
+    let rows = decode_rows_with_deletions(&columns, &[false, true], read_column).await?;
+    assert_eq!(rows, vec![
+      make_row! { "a" => 1_i64, "b" => 4_i64 },
+      make_row! { "a" => 3_i64, "b" => 6_i64 },
+    ]);
+    // one read per column and nothing else: no deletion read
+    let mut reads_made = reads.lock().unwrap().clone();
+    reads_made.sort();
+    assert_eq!(reads_made, vec!["a", "b"]);
+
+    let too_long = [false; 4];
+    assert!(decode_rows_with_deletions(&columns, &too_long, read_column).await.is_err());
+    Ok(())
+  }
+
+  fn int_column() -> ColumnMeta {
+    ColumnMeta {
+      dtype: DataType::Int64 as i32,
+      nested_list_depth: 0,
+    }
+  }
+
+  fn compressed_response(data: &[u8]) -> ReadSegmentColumnResponse {
+    ReadSegmentColumnResponse {
+      codec: compression::Q_COMPRESS.to_string(),
+      data: data.to_vec(),
+      ..Default::default()
+    }
+  }
+
+  async fn decode_canned(
+    responses: Vec<ReadSegmentColumnResponse>,
+    is_deleted: &[bool],
+  ) -> ClientResult<Vec<FieldValue>> {
+    let stream = futures::stream::iter(responses.into_iter().map(Ok));
+    decode_column_responses(
+      stream,
+      &int_column(),
+      is_deleted,
+      &mut DecodeScratch::default(),
+      &DecodeBudget::unlimited(),
+    ).await
+  }
+
+  #[tokio::test]
+  async fn test_decode_multi_response_compressed() -> ClientResult<()> {
+    let fvs = int_fvs(&[5, 6, 7, 8]);
+    let bytes = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?
+      .compress(&fvs, 0)?;
+    let (first, second) = bytes.split_at(bytes.len() / 2);
+    let responses = vec![compressed_response(first), compressed_response(second)];
+    assert_eq!(decode_canned(responses, &[false, true]).await?, int_fvs(&[5, 7, 8]));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_repeated_matches_vec() -> ClientResult<()> {
+    let fvs = int_fvs(&[5, 6, 7]);
+    let bytes = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?
+      .compress(&fvs, 0)?;
+    let responses = vec![
+      compressed_response(&bytes[..3]),
+      compressed_response(&bytes[3..]),
+    ];
+    let stream = futures::stream::iter(responses.clone().into_iter().map(Ok));
+    let repeated = decode_repeated_column_responses(stream, &int_column(), &[false, true]).await?;
+    assert_eq!(repeated.vals, int_fvs(&[5, 7]));
+    assert_eq!(repeated.vals, decode_canned(responses, &[false, true]).await?);
+
+    // the same shape as a nested list value, so it encodes as one
+    let list = FieldValue { value: Some(Value::ListVal(repeated)) };
+    let encoded = encoding::new_encoder(DataType::Int64, 1).encode(std::slice::from_ref(&list))?;
+    let round_tripped = encoding::new_field_value_decoder(DataType::Int64, 1).decode(&encoded)?;
+    assert_eq!(round_tripped, vec![list]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_uncompressed_after_implicit_nulls() -> ClientResult<()> {
+    let bytes = encoding::new_encoder(DataType::Int64, 0)
+      .encode(&int_fvs(&[1, 2]))?;
+    let responses = vec![
+      ReadSegmentColumnResponse {
+        implicit_nulls_count: 2,
+        ..Default::default()
+      },
+      ReadSegmentColumnResponse {
+        data: bytes,
+        implicit_nulls_count: 2,
+        ..Default::default()
+      },
+    ];
+    let mut expected = vec![FieldValue::default()];
+    expected.extend(int_fvs(&[1, 2]));
+    assert_eq!(decode_canned(responses, &[true]).await?, expected);
+    Ok(())
+  }
+
+  fn implicit_nulls_response(implicit_nulls_count: u32) -> ReadSegmentColumnResponse {
+    ReadSegmentColumnResponse {
+      implicit_nulls_count,
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_decode_implicit_nulls_with_deletions() -> ClientResult<()> {
+    let cases: Vec<(Vec<bool>, usize)> = vec![
+      (vec![], 5),
+      (vec![true, true], 3),
+      (vec![false, false, true, false, true], 3),
+      (vec![false, false, false, false, true], 4),
+      (vec![true; 5], 0),
+      // a deletion bitmap longer than the column is tolerated
+      (vec![false, true, false, false, false, false, true], 4),
+    ];
+    for (is_deleted, expected_count) in cases {
+      let fvs = decode_canned(vec![implicit_nulls_response(5)], &is_deleted).await?;
+      assert_eq!(fvs, vec![FieldValue::default(); expected_count], "{:?}", is_deleted);
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_deletions_spanning_implicit_nulls() -> ClientResult<()> {
+    let bytes = encoding::new_encoder(DataType::Int64, 0)
+      .encode(&int_fvs(&[1, 2, 3]))?;
+    let responses = vec![
+      implicit_nulls_response(3),
+      ReadSegmentColumnResponse {
+        data: bytes,
+        implicit_nulls_count: 3,
+        ..Default::default()
+      },
+    ];
+    // deletes the last implicit null and the first explicit value
+    let fvs = decode_canned(responses, &[false, false, true, true]).await?;
+    let mut expected = vec![FieldValue::default(); 2];
+    expected.extend(int_fvs(&[2, 3]));
+    assert_eq!(fvs, expected);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_contradictory_responses() {
+    let bytes = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)
+      .unwrap()
+      .compress(&int_fvs(&[1]), 0)
+      .unwrap();
+    let responses = vec![ReadSegmentColumnResponse {
+      implicit_nulls_count: 3,
+      ..compressed_response(&bytes)
+    }];
+    assert!(decode_canned(responses, &[]).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_decode_dtype_mismatch() {
+    let bytes = compression::new_codec(DataType::Bool, compression::BITPACK)
+      .unwrap()
+      .compress(&[FieldValue { value: Some(Value::BoolVal(true)) }], 0)
+      .unwrap();
+    let responses = vec![ReadSegmentColumnResponse {
+      codec: compression::BITPACK.to_string(),
+      ..compressed_response(&bytes)
+    }];
+    let err = decode_canned(responses, &[]).await.unwrap_err();
+    assert!(err.message.contains("Int64"), "{}", err.message);
+    assert!(err.message.contains(compression::BITPACK), "{}", err.message);
+
+    // codecs no data type supports keep their own error
+    for codec in ["nonexistent", "zstd:level=999"] {
+      let responses = vec![ReadSegmentColumnResponse {
+        codec: codec.to_string(),
+        ..compressed_response(&bytes)
+      }];
+      let err = decode_canned(responses, &[]).await.unwrap_err();
+      assert!(!err.message.contains("cannot hold"), "{}", err.message);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_decode_stream_error() {
+    let stream = futures::stream::iter(vec![Err(Status::unavailable("server gone"))]);
+    let res = decode_column_responses(
+      stream,
+      &int_column(),
+      &[],
+      &mut DecodeScratch::default(),
+      &DecodeBudget::unlimited(),
+    ).await;
+    assert!(res.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_read_segments_with_fresh_ids() -> ClientResult<()> {
+    let segment_keys = (0..3)
+      .map(|i| SegmentKey {
+        segment_id: format!("segment_{}", i),
+        ..Default::default()
+      })
+      .collect::<Vec<_>>();
+    // each segment's rows record the correlation ID it was read under
+    let rows = read_segments_with_fresh_ids(segment_keys.clone(), |segment_key, correlation_id| async move {
+      Ok(vec![
+        make_row! { "segment_id" => segment_key.segment_id.clone(), "correlation_id" => correlation_id.clone() },
+        make_row! { "segment_id" => segment_key.segment_id, "correlation_id" => correlation_id },
+      ])
+    }).await?;
+
+    let field = |row: &Row, name: &str| match &row.fields[name].value {
+      Some(Value::StringVal(s)) => s.clone(),
+      _ => panic!("missing {}", name),
+    };
+    let segment_ids = rows.iter().map(|row| field(row, "segment_id")).collect::<Vec<_>>();
+    assert_eq!(segment_ids, vec!["segment_0", "segment_0", "segment_1", "segment_1", "segment_2", "segment_2"]);
+    let mut ids = rows.iter().map(|row| field(row, "correlation_id")).collect::<Vec<_>>();
+    // both rows of a segment share its ID
+    for segment_ids in ids.chunks(2) {
+      assert_eq!(segment_ids[0], segment_ids[1]);
+      assert!(!segment_ids[0].is_empty());
+    }
+    ids.dedup();
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+
+    // a failed segment stops the read, so later segments aren't read
+    let n_reads = std::sync::atomic::AtomicUsize::new(0);
+    let res = read_segments_with_fresh_ids(segment_keys, |segment_key, _| {
+      n_reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      async move {
+        if segment_key.segment_id == "segment_1" {
+          Err(ClientError::other("oops".to_string()))
+        } else {
+          Ok(vec![])
+        }
+      }
+    }).await;
+    assert!(res.is_err());
+    assert_eq!(n_reads.into_inner(), 2);
+    Ok(())
+  }
+
+  fn timestamped_segment(segment_id: &str, seconds: Option<i64>) -> Segment {
+    let mut partition = HashMap::new();
+    partition.insert("region".to_string(), PartitionFieldValue {
+      value: Some(PartitionValue::StringVal("us".to_string())),
+    });
+    if let Some(seconds) = seconds {
+      partition.insert("day".to_string(), PartitionFieldValue {
+        value: Some(PartitionValue::TimestampVal(prost_types::Timestamp { seconds, nanos: 0 })),
+      });
+    }
+    Segment {
+      partition,
+      segment_id: segment_id.to_string(),
+      metadata: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn test_tail_rows() -> ClientResult<()> {
+    let segments = vec![
+      timestamped_segment("unpartitioned_0", None),
+      timestamped_segment("old", Some(100)),
+      timestamped_segment("new", Some(300)),
+      timestamped_segment("unpartitioned_1", None),
+      timestamped_segment("middle", Some(200)),
+    ];
+    let ordered = newest_first(segments.clone()).into_iter()
+      .map(|segment| segment.segment_id)
+      .collect::<Vec<_>>();
+    assert_eq!(ordered, vec!["new", "middle", "old", "unpartitioned_1", "unpartitioned_0"]);
+
+    let reads = std::sync::Mutex::new(Vec::new());
+    let read_segment = |segment: Segment| {
+      reads.lock().unwrap().push(segment.segment_id.clone());
+      async move {
+        Ok((0..3_i64)
+          .map(|i| make_row! { "segment" => segment.segment_id.clone(), "i" => i })
+          .collect())
+      }
+    };
+    let rows = tail_rows_of(segments.clone(), 4, read_segment).await?;
+    assert_eq!(rows, vec![
+      make_row! { "segment" => "new".to_string(), "i" => 2_i64 },
+      make_row! { "segment" => "new".to_string(), "i" => 1_i64 },
+      make_row! { "segment" => "new".to_string(), "i" => 0_i64 },
+      make_row! { "segment" => "middle".to_string(), "i" => 2_i64 },
+    ]);
+    // stopped as soon as enough rows were gathered
+    assert_eq!(*reads.lock().unwrap(), vec!["new", "middle"]);
+
+    reads.lock().unwrap().clear();
+    assert!(tail_rows_of(segments.clone(), 0, read_segment).await?.is_empty());
+    assert_eq!(tail_rows_of(segments, 100, read_segment).await?.len(), 15);
+    assert_eq!(reads.lock().unwrap().len(), 5);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_bails_on_stalled_stream() {
+    let stalled = ReadSegmentColumnResponse::default();
+    let stream = futures::stream::repeat(stalled).map(Ok);
+    let res = decode_column_responses(
+      stream,
+      &int_column(),
+      &[],
+      &mut DecodeScratch::default(),
+      &DecodeBudget::unlimited(),
+    ).await;
+    assert!(res.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_decode_tolerates_some_empty_responses() -> ClientResult<()> {
+    let bytes = encoding::new_encoder(DataType::Int64, 0)
+      .encode(&int_fvs(&[1]))?;
+    let mut responses = vec![ReadSegmentColumnResponse::default(); MAX_STALLED_RESPONSES];
+    responses.push(ReadSegmentColumnResponse {
+      data: bytes,
+      ..Default::default()
+    });
+    assert_eq!(decode_canned(responses, &[]).await?, int_fvs(&[1]));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_byte_limit() -> ClientResult<()> {
+    let fvs = int_fvs(&(0..100).collect::<Vec<_>>());
+    let bytes = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?
+      .compress(&fvs, 0)?;
+    let value_size = estimated_size(&fvs[0]);
+
+    let budget = DecodeBudget::new(100 * value_size);
+    let stream = futures::stream::iter(vec![Ok(compressed_response(&bytes))]);
+    let decoded = decode_column_responses(stream, &int_column(), &[], &mut DecodeScratch::default(), &budget).await?;
+    assert_eq!(decoded, fvs);
+
+    // the budget is shared, so a second column pushes it over
+    let budget = DecodeBudget::new(150 * value_size);
+    for expect_ok in [true, false] {
+      let stream = futures::stream::iter(vec![Ok(compressed_response(&bytes))]);
+      let res = decode_column_responses(stream, &int_column(), &[], &mut DecodeScratch::default(), &budget).await;
+      assert_eq!(res.is_ok(), expect_ok);
+    }
+    // decoding stopped at the first value past the limit
+    assert_eq!(budget.used_bytes.into_inner(), 151 * value_size);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_decode_byte_limit_single_column() -> ClientResult<()> {
+    let fvs = int_fvs(&(0..1000).collect::<Vec<_>>());
+    let value_size = estimated_size(&fvs[0]);
+    let compressed = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?
+      .compress(&fvs, 0)?;
+    let mut uncompressed = encoding::new_encoder(DataType::Int64, 0).encode(&fvs)?;
+    // a truncated final value would fail an eager decode before any value
+    // was charged
+    uncompressed.push(0);
+    let responses = [
+      compressed_response(&compressed),
+      ReadSegmentColumnResponse {
+        data: uncompressed,
+        ..Default::default()
+      },
+    ];
+
+    for response in responses {
+      let budget = DecodeBudget::new(10 * value_size);
+      let stream = futures::stream::iter(vec![Ok(response)]);
+      let err = decode_column_responses(stream, &int_column(), &[], &mut DecodeScratch::default(), &budget)
+        .await
+        .unwrap_err();
+      assert!(err.message.contains("limit"), "{}", err.message);
+      // the column's other values were never built
+      assert_eq!(budget.used_bytes.into_inner(), 11 * value_size);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_estimated_size() {
+    let base = mem::size_of::<FieldValue>();
+    let string_fv = FieldValue { value: Some(Value::StringVal("abcd".to_string())) };
+    let list_fv = FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue {
+        vals: vec![string_fv.clone(), FieldValue::default()],
+      })),
+    };
+    assert_eq!(estimated_size(&FieldValue::default()), base);
+    assert_eq!(estimated_size(&int_fvs(&[3])[0]), base);
+    assert_eq!(estimated_size(&string_fv), base + 4);
+    assert_eq!(estimated_size(&list_fv), 3 * base + 4);
+  }
+
+  #[tokio::test]
+  async fn test_decode_reusing_scratch() -> ClientResult<()> {
+    let codec = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?;
+    let columns = vec![
+      int_fvs(&(0..1000).collect::<Vec<_>>()),
+      int_fvs(&[7, 8, 9]),
+      int_fvs(&(0..500).map(|x| -x).collect::<Vec<_>>()),
+    ];
+    let mut scratch = DecodeScratch::default();
+    let mut max_capacity = 0;
+    for fvs in &columns {
+      let responses = vec![compressed_response(&codec.compress(fvs, 0)?)];
+      let stream = futures::stream::iter(responses.into_iter().map(Ok));
+      let decoded = decode_column_responses(
+        stream,
+        &int_column(),
+        &[],
+        &mut scratch,
+        &DecodeBudget::unlimited(),
+      ).await?;
+      assert_eq!(&decoded, fvs);
+      max_capacity = max_capacity.max(scratch.compressed_bytes.capacity());
+    }
+    // the largest column's buffer is kept for reuse
+    assert_eq!(scratch.compressed_bytes.capacity(), max_capacity);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_columns_match_serial() -> ClientResult<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let columns: HashMap<String, ColumnMeta> = ["a", "b", "c", "d"].iter()
+      .map(|column_name| (column_name.to_string(), int_column()))
+      .collect();
+    // later columns finish first, so completion order differs from name
+    // order
+    let canned = |column_name: &str| {
+      let idx = (column_name.as_bytes()[0] - b'a') as i64;
+      let delay = std::time::Duration::from_millis(5 * (4 - idx as u64));
+      (delay, int_fvs(&[idx, 10 * idx, 100 * idx]))
+    };
+    let in_flight = AtomicUsize::new(0);
+    let max_in_flight = AtomicUsize::new(0);
+    let read_column = |column_name: &str, _: &ColumnMeta| {
+      let (delay, fvalues) = canned(column_name);
+      let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+      async move {
+        let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        max_in_flight.fetch_max(n, Ordering::SeqCst);
+        tokio::time::sleep(delay).await;
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+        Ok(fvalues)
+      }
+    };
+
+    let concurrent = read_columns_with_retries(&columns, &RetryPolicy::no_retries(), read_column).await;
+    assert!(max_in_flight.load(Ordering::SeqCst) > 1);
+    let concurrent_rows = assemble_rows(column_values(concurrent)?);
+
+    let mut column_names = columns.keys().collect::<Vec<_>>();
+    column_names.sort_unstable();
+    let mut serial = Vec::new();
+    for column_name in column_names {
+      let fvalues = read_column(column_name, &columns[column_name]).await?;
+      serial.push((column_name.clone(), fvalues));
+    }
+    let serial_rows = assemble_rows(serial);
+
+    assert_eq!(concurrent_rows, serial_rows);
+    assert_eq!(serial_rows[1], make_row! { "a" => 0_i64, "b" => 10_i64, "c" => 20_i64, "d" => 30_i64 });
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_read_columns_retries_flaky_column() {
+    let columns: HashMap<String, ColumnMeta> = vec![
+      ("a".to_string(), int_column()),
+      ("b".to_string(), int_column()),
+    ].into_iter().collect();
+    let policy = RetryPolicy {
+      initial_backoff: std::time::Duration::from_millis(1),
+      ..Default::default()
+    };
+    let n_b_reads = std::sync::atomic::AtomicUsize::new(0);
+    let results = read_columns_with_retries(&columns, &policy, |column_name, _| {
+      let fail = column_name == "b" &&
+        n_b_reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0;
+      async move {
+        if fail {
+          Err(ClientError::from(Status::unavailable("flaky")))
+        } else {
+          Ok(int_fvs(&[1, 2]))
+        }
+      }
+    }).await;
 
-    Ok(rows[0..n].to_vec())
+    assert_eq!(n_b_reads.into_inner(), 2);
+    let segment = partial_segment(results);
+    assert!(segment.column_errors.is_empty());
+    assert_eq!(segment.rows, vec![
+      make_row! { "a" => 1_i64, "b" => 1_i64 },
+      make_row! { "a" => 2_i64, "b" => 2_i64 },
+    ]);
   }
 }
@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::errors::{ClientError, ClientResult};
+use crate::types::ShutdownReport;
+
+use super::Client;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Default)]
+struct WriteTrackerState {
+  shutting_down: bool,
+  n_in_flight: usize,
+  n_completed_since_shutdown: usize,
+}
+
+// Counts the writes in flight across all clones of a client, so shutting
+// down one clone drains them all.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WriteTracker {
+  state: Arc<Mutex<WriteTrackerState>>,
+}
+
+// Marks one write as in flight until dropped, including when the write's
+// future is dropped before finishing.
+pub(crate) struct InFlightWrite {
+  tracker: WriteTracker,
+}
+
+impl WriteTracker {
+  fn lock(&self) -> MutexGuard<'_, WriteTrackerState> {
+    // the state stays consistent even if a holder panicked
+    self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  pub(crate) fn start_write(&self) -> ClientResult<InFlightWrite> {
+    let mut state = self.lock();
+    if state.shutting_down {
+      return Err(ClientError::other(
+        "client is shut down and no longer accepts writes".to_string()
+      ));
+    }
+    state.n_in_flight += 1;
+    Ok(InFlightWrite { tracker: self.clone() })
+  }
+
+  async fn drain(&self, timeout: Duration) -> ShutdownReport {
+    self.lock().shutting_down = true;
+    // timing out just leaves some writes unfinished
+    let _ = crate::utils::poll_until(
+      || async { Ok(self.lock().n_in_flight == 0) },
+      DRAIN_POLL_INTERVAL,
+      timeout,
+      "in-flight writes to finish",
+    ).await;
+
+    let state = self.lock();
+    ShutdownReport {
+      n_completed: state.n_completed_since_shutdown,
+      n_unfinished: state.n_in_flight,
+    }
+  }
+}
+
+impl Drop for InFlightWrite {
+  fn drop(&mut self) {
+    let mut state = self.tracker.lock();
+    state.n_in_flight -= 1;
+    if state.shutting_down {
+      state.n_completed_since_shutdown += 1;
+    }
+  }
+}
+
+/// Lifecycle functionality.
+impl Client {
+  /// Stops accepting writes and waits for writes already in flight to
+  /// finish.
+  ///
+  /// This applies to every clone of the client, so concurrent writers
+  /// sharing it are drained together.
+  /// Once called, any write through the client fails immediately.
+  /// Waits at most `timeout`; writes still running after that are reported
+  /// as unfinished and left to complete or fail on their own.
+  /// Reads and other requests are unaffected.
+  pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+    self.write_tracker.drain(timeout).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn tracked_write(tracker: WriteTracker, duration: Duration) -> ClientResult<()> {
+    let _in_flight = tracker.start_write()?;
+    tokio::time::sleep(duration).await;
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_drain_waits_for_writes() {
+    let tracker = WriteTracker::default();
+    let handles = (1..=3_u64)
+      .map(|i| tokio::spawn(tracked_write(tracker.clone(), Duration::from_millis(20 * i))))
+      .collect::<Vec<_>>();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    let report = tracker.drain(Duration::from_secs(5)).await;
+    assert_eq!(report, ShutdownReport { n_completed: 3, n_unfinished: 0 });
+    for handle in handles {
+      assert!(handle.await.unwrap().is_ok());
+    }
+    assert!(tracker.start_write().is_err());
+  }
+
+  #[tokio::test]
+  async fn test_drain_timeout() {
+    let tracker = WriteTracker::default();
+    let fast = tokio::spawn(tracked_write(tracker.clone(), Duration::from_millis(50)));
+    let slow = tokio::spawn(tracked_write(tracker.clone(), Duration::from_secs(10)));
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    let report = tracker.drain(Duration::from_millis(300)).await;
+    assert_eq!(report, ShutdownReport { n_completed: 1, n_unfinished: 1 });
+    assert!(fast.await.unwrap().is_ok());
+    slow.abort();
+  }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use futures::{Stream, StreamExt};
+use pancake_db_idl::dml::ListSegmentsRequest;
+
+use crate::errors::ClientResult;
+use crate::segment_helpers::SegmentExt;
+use crate::types::{ColumnStorageInfo, SegmentColumnChunk, SegmentKey, TableSize};
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this for storage analysis.
+impl Client {
+  /// Reports how each of the segment's columns is stored, without decoding
+  /// them.
+  ///
+  /// The columns' data is still transferred, but only its size is kept.
+  /// All columns are read under one correlation ID.
+  pub async fn segment_column_codecs(
+    &mut self,
+    segment_key: &SegmentKey,
+    column_names: &[String],
+  ) -> ClientResult<HashMap<String, ColumnStorageInfo>> {
+    let correlation_id = crate::utils::new_correlation_id();
+    let mut res = HashMap::with_capacity(column_names.len());
+    for column_name in column_names {
+      let chunks = self.stream_segment_column_bytes(
+        segment_key,
+        column_name,
+        &correlation_id,
+      ).await?;
+      res.insert(column_name.clone(), storage_info(chunks).await?);
+    }
+    Ok(res)
+  }
+
+  /// Estimates the table's total storage, with a breakdown by column.
+  ///
+  /// Every column of every segment is read to measure its size, as in
+  /// [`segment_column_codecs`][Client::segment_column_codecs], so this
+  /// transfers the whole table.
+  /// The implicit `_row_id` column is counted too.
+  pub async fn table_size(&mut self, table_name: &str) -> ClientResult<TableSize> {
+    let column_names = self.columns_for(table_name, true).await?
+      .into_keys()
+      .collect::<Vec<_>>();
+    let segments = self.list_segments(ListSegmentsRequest {
+      table_name: table_name.to_string(),
+      include_metadata: true,
+      ..Default::default()
+    }).await?.segments;
+
+    let mut size = TableSize::default();
+    for segment in segments {
+      let row_count = segment.row_count().unwrap_or(0);
+      let segment_key = SegmentKey {
+        table_name: table_name.to_string(),
+        partition: segment.partition,
+        segment_id: segment.segment_id,
+      };
+      let column_infos = self.segment_column_codecs(&segment_key, &column_names).await?;
+      size.add_segment(row_count, &column_infos);
+    }
+    Ok(size)
+  }
+}
+
+async fn storage_info<S>(chunks: S) -> ClientResult<ColumnStorageInfo> where
+  S: Stream<Item=ClientResult<SegmentColumnChunk>>,
+{
+  futures::pin_mut!(chunks);
+  let mut info = ColumnStorageInfo::default();
+  while let Some(chunk) = chunks.next().await {
+    let chunk = chunk?;
+    if chunk.codec.is_empty() {
+      info.uncompressed_bytes += chunk.data.len();
+    } else {
+      info.compressed_bytes += chunk.data.len();
+      info.codec = chunk.codec;
+    }
+    info.implicit_nulls_count = chunk.implicit_nulls_count;
+  }
+  Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::errors::ClientError;
+
+  use super::*;
+
+  fn chunk(codec: &str, n_bytes: usize, implicit_nulls_count: u32) -> ClientResult<SegmentColumnChunk> {
+    Ok(SegmentColumnChunk {
+      codec: codec.to_string(),
+      data: vec![0; n_bytes],
+      implicit_nulls_count,
+    })
+  }
+
+  #[tokio::test]
+  async fn test_storage_info() -> ClientResult<()> {
+    let chunks = vec![
+      chunk("q_compress", 100, 0),
+      chunk("q_compress", 20, 0),
+      chunk("", 7, 0),
+    ];
+    let info = storage_info(futures::stream::iter(chunks)).await?;
+    assert_eq!(info, ColumnStorageInfo {
+      codec: "q_compress".to_string(),
+      compressed_bytes: 120,
+      uncompressed_bytes: 7,
+      implicit_nulls_count: 0,
+    });
+
+    let chunks = vec![chunk("", 0, 30), chunk("", 12, 30)];
+    let info = storage_info(futures::stream::iter(chunks)).await?;
+    assert_eq!(info, ColumnStorageInfo {
+      codec: "".to_string(),
+      compressed_bytes: 0,
+      uncompressed_bytes: 12,
+      implicit_nulls_count: 30,
+    });
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_storage_info_error() {
+    let chunks = vec![
+      chunk("zstd", 5, 0),
+      Err(ClientError::other("stream broke".to_string())),
+    ];
+    assert!(storage_info(futures::stream::iter(chunks)).await.is_err());
+  }
+}
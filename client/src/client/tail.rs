@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::time::Duration;
+
+use futures::Stream;
+use pancake_db_idl::dml::{ListSegmentsRequest, Segment};
+
+use crate::errors::ClientResult;
+use crate::segment_helpers::SegmentExt;
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this for consuming new data as it arrives.
+impl Client {
+  /// Polls the table's segments, yielding each segment that is new or has
+  /// grown since the previous poll.
+  ///
+  /// Every segment present on the first poll is yielded once, and from then
+  /// on a segment is yielded again whenever its row count increases.
+  /// A segment that disappears, e.g. by being replaced during compaction,
+  /// is forgotten, so its replacement is yielded as a new segment.
+  /// A failed poll yields its error, and polling continues after the next
+  /// interval.
+  /// The stream never ends on its own.
+  pub fn tail_table(
+    &self,
+    table_name: &str,
+    poll_interval: Duration,
+  ) -> impl Stream<Item=ClientResult<Segment>> {
+    let client = self.clone();
+    let table_name = table_name.to_string();
+    let list_segments = move || {
+      let mut client = client.clone();
+      let req = ListSegmentsRequest {
+        table_name: table_name.clone(),
+        include_metadata: true,
+        ..Default::default()
+      };
+      async move {
+        Ok(client.list_segments(req).await?.segments)
+      }
+    };
+    tail_segments(list_segments, poll_interval)
+  }
+}
+
+struct TailState<F> {
+  list_segments: F,
+  poll_interval: Duration,
+  // row counts of the segments seen on the last successful poll
+  row_counts: HashMap<String, Option<u32>>,
+  pending: VecDeque<Segment>,
+  is_first_poll: bool,
+}
+
+fn tail_segments<F, Fut>(
+  list_segments: F,
+  poll_interval: Duration,
+) -> impl Stream<Item=ClientResult<Segment>> where
+  F: Fn() -> Fut,
+  Fut: Future<Output=ClientResult<Vec<Segment>>>,
+{
+  let state = TailState {
+    list_segments,
+    poll_interval,
+    row_counts: HashMap::new(),
+    pending: VecDeque::new(),
+    is_first_poll: true,
+  };
+  futures::stream::unfold(state, |mut state| async move {
+    loop {
+      if let Some(segment) = state.pending.pop_front() {
+        return Some((Ok(segment), state));
+      }
+
+      if !state.is_first_poll {
+        tokio::time::sleep(state.poll_interval).await;
+      }
+      state.is_first_poll = false;
+
+      match (state.list_segments)().await {
+        Ok(segments) => {
+          let mut row_counts = HashMap::with_capacity(segments.len());
+          for segment in segments {
+            let row_count = segment.row_count();
+            let is_updated = match state.row_counts.get(&segment.segment_id) {
+              None => true,
+              Some(prev_row_count) => row_count > *prev_row_count,
+            };
+            row_counts.insert(segment.segment_id.clone(), row_count);
+            if is_updated {
+              state.pending.push_back(segment);
+            }
+          }
+          state.row_counts = row_counts;
+        },
+        Err(err) => return Some((Err(err), state)),
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use futures::StreamExt;
+  use pancake_db_idl::dml::SegmentMetadata;
+
+  use super::*;
+
+  fn segment(segment_id: &str, row_count: u32) -> Segment {
+    Segment {
+      segment_id: segment_id.to_string(),
+      metadata: Some(SegmentMetadata { row_count }),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_tail_segments() {
+    let polls = Mutex::new(VecDeque::from(vec![
+      vec![segment("a", 10)],
+      vec![segment("a", 10), segment("b", 5)],
+      vec![segment("a", 10), segment("b", 8)],
+      // a is compacted into c
+      vec![segment("b", 8), segment("c", 10)],
+      vec![segment("b", 8), segment("c", 10)],
+      vec![segment("b", 8), segment("c", 10), segment("a", 1)],
+    ]));
+    let list_segments = || {
+      let segments = polls.lock().unwrap().pop_front().unwrap_or_default();
+      async move { Ok(segments) }
+    };
+
+    let emitted = tail_segments(list_segments, Duration::from_millis(1))
+      .take(5)
+      .map(|res| {
+        let segment = res.unwrap();
+        (segment.segment_id.clone(), segment.row_count().unwrap())
+      })
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(emitted, vec![
+      ("a".to_string(), 10),
+      ("b".to_string(), 5),
+      ("b".to_string(), 8),
+      ("c".to_string(), 10),
+      ("a".to_string(), 1),
+    ]);
+  }
+}
@@ -0,0 +1,708 @@
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use futures::{Stream, StreamExt};
+use pancake_db_idl::ddl::GetSchemaRequest;
+use pancake_db_idl::dml::{ListSegmentsRequest, PartitionFieldValue, Row, WriteToPartitionRequest, WriteToPartitionResponse};
+use pancake_db_idl::schema::Schema;
+use prost::Message;
+use tonic::Code;
+
+use crate::errors::{ClientError, ClientErrorKind, ClientResult};
+use crate::row_helpers::{self, NonFinitePolicy};
+use crate::segment_helpers::SegmentExt;
+use crate::types::{IsolatedWriteSummary, PartitionedRow, PartitionRef, WriteManyOutcome, WriteSummary};
+
+use super::Client;
+
+/// Higher-level functionality.
+///
+/// Use this for bulk writes.
+impl Client {
+  /// Writes every row of a stream to a partition.
+  ///
+  /// Rows are buffered into requests of up to `batch_size` rows, and up to
+  /// `concurrency` requests are in flight at once.
+  /// The final partial batch is flushed once the stream ends.
+  /// A failed request does not stop the write; its error is recorded in the
+  /// returned [`WriteSummary`] and the remaining batches are still sent.
+  pub async fn write_stream<S>(
+    &mut self,
+    table_name: &str,
+    partition: &HashMap<String, PartitionFieldValue>,
+    rows: S,
+    batch_size: usize,
+    concurrency: usize,
+  ) -> ClientResult<WriteSummary> where S: Stream<Item=Row> {
+    let client = self.clone();
+    let write_batch = |batch: Vec<Row>| {
+      let mut client = client.clone();
+      let req = WriteToPartitionRequest {
+        table_name: table_name.to_string(),
+        partition: partition.clone(),
+        rows: batch,
+      };
+      async move {
+        client.write_to_partition(req).await?;
+        Ok(())
+      }
+    };
+    write_batches(rows, batch_size, concurrency, write_batch).await
+  }
+
+  /// Sends several write requests concurrently, e.g. to related tables.
+  ///
+  /// This is best effort, not a transaction: every request is sent even if
+  /// others fail, and the server cannot roll back the ones that succeeded.
+  /// The returned [`WriteManyOutcome`] reports each request's result;
+  /// use [`WriteManyOutcome::into_result`] to treat any failure as an error.
+  pub async fn write_many(&mut self, reqs: &[WriteToPartitionRequest]) -> WriteManyOutcome {
+    let client = self.clone();
+    write_concurrently(reqs, |req| {
+      let mut client = client.clone();
+      async move { client.write_to_partition(req).await }
+    }).await
+  }
+
+  /// Waits until the table's segments hold at least `min_row_count` rows.
+  ///
+  /// Write responses carry no commit status, so this instead polls
+  /// [`list_segments`][Client::list_segments] every `poll_interval`, summing
+  /// the segments' row counts, which include deleted rows.
+  /// Use it after writing a known number of rows instead of sleeping for an
+  /// arbitrary time.
+  /// Returns an error of kind [`ClientErrorKind::Timeout`] if the rows have
+  /// not all appeared after `timeout`.
+  pub async fn await_durable(
+    &mut self,
+    table_name: &str,
+    min_row_count: u64,
+    poll_interval: Duration,
+    timeout: Duration,
+  ) -> ClientResult<()> {
+    let client = self.clone();
+    let count_rows = || {
+      let mut client = client.clone();
+      async move {
+        let resp = client.list_segments(ListSegmentsRequest {
+          table_name: table_name.to_string(),
+          include_metadata: true,
+          ..Default::default()
+        }).await?;
+        Ok(resp.segments.iter().map(|segment| segment.row_count().unwrap_or(0) as u64).sum())
+      }
+    };
+    wait_for_row_count(table_name, min_row_count, count_rows, poll_interval, timeout).await
+  }
+
+  /// Writes rows to a partition after checking their field names against
+  /// the table's schema.
+  ///
+  /// Fetches the schema first, and returns an error listing every field name
+  /// that is not a column of the table without sending the write.
+  /// This catches misspelled field names that a plain
+  /// [`write_to_partition`][Client::write_to_partition] would not report.
+  pub async fn write_to_partition_checked(
+    &mut self,
+    req: WriteToPartitionRequest,
+  ) -> ClientResult<WriteToPartitionResponse> {
+    let schema_resp = self.get_schema(GetSchemaRequest {
+      table_name: req.table_name.clone(),
+    }).await?;
+    let schema = schema_resp.schema.unwrap_or_default();
+    check_field_names(&req.rows, &schema)?;
+    self.write_to_partition(req).await
+  }
+
+  /// Writes rows to a partition after applying a policy to their NaN and
+  /// infinite floats.
+  ///
+  /// See [`NonFinitePolicy`] for the options.
+  /// Under [`NonFinitePolicy::Reject`], nothing is written if any row
+  /// contains a non-finite float.
+  pub async fn write_to_partition_with_float_policy(
+    &mut self,
+    mut req: WriteToPartitionRequest,
+    policy: NonFinitePolicy,
+  ) -> ClientResult<WriteToPartitionResponse> {
+    row_helpers::apply_non_finite_policy(&mut req.rows, policy)?;
+    self.write_to_partition(req).await
+  }
+
+  /// Writes rows to a partition, first setting the timestamp column of any
+  /// row that omits it to the current time.
+  ///
+  /// Useful for ingestion-time columns, so callers don't need to add
+  /// `SystemTime::now()` to every row.
+  /// See [`row_helpers::fill_missing_timestamps`] for which rows are filled.
+  pub async fn write_to_partition_with_timestamp(
+    &mut self,
+    mut req: WriteToPartitionRequest,
+    timestamp_column: &str,
+  ) -> ClientResult<WriteToPartitionResponse> {
+    row_helpers::fill_missing_timestamps(&mut req.rows, timestamp_column, SystemTime::now());
+    self.write_to_partition(req).await
+  }
+
+  /// Writes rows to a partition, isolating any rows the server rejects.
+  ///
+  /// If the server rejects a request as invalid, the rows are split in half
+  /// and each half is retried, down to individual rows.
+  /// All valid rows get written, and each rejected row is reported in the
+  /// returned [`IsolatedWriteSummary`].
+  /// Any other kind of error, such as a connection failure, is returned
+  /// immediately, possibly after some rows have already been written.
+  pub async fn write_isolating_bad_rows(
+    &mut self,
+    table_name: &str,
+    partition: &HashMap<String, PartitionFieldValue>,
+    rows: Vec<Row>,
+  ) -> ClientResult<IsolatedWriteSummary> {
+    let client = self.clone();
+    let write_batch = |batch: Vec<Row>| {
+      let mut client = client.clone();
+      let req = WriteToPartitionRequest {
+        table_name: table_name.to_string(),
+        partition: partition.clone(),
+        rows: batch,
+      };
+      async move {
+        client.write_to_partition(req).await?;
+        Ok(())
+      }
+    };
+    write_bisecting(rows, write_batch).await
+  }
+
+  /// Writes rows that may belong to different partitions.
+  ///
+  /// Rows are grouped by partition into requests of up to `batch_size`
+  /// rows, keeping their relative order, and all requests are sent
+  /// concurrently.
+  /// As with [`write_many`][Client::write_many], a failed request does not
+  /// stop the others; its error is recorded in the returned
+  /// [`WriteSummary`].
+  pub async fn write_partitioned_rows(
+    &mut self,
+    table_name: &str,
+    rows: Vec<PartitionedRow>,
+    batch_size: usize,
+  ) -> ClientResult<WriteSummary> {
+    let reqs = partitioned_requests(table_name, rows, batch_size)?;
+    let outcome = self.write_many(&reqs).await;
+    Ok(write_summary(&reqs, outcome))
+  }
+
+  /// Writes rows to a partition, splitting them into requests that each
+  /// have at most `max_rows` rows and encode to at most `max_bytes` bytes.
+  ///
+  /// A row count limit alone doesn't keep requests of large string or
+  /// bytes values under the server's message size limit, so set
+  /// `max_bytes` to that limit.
+  /// Request sizes are computed exactly from the protobuf encoding before
+  /// anything is sent.
+  /// Returns an error without writing anything if a single row is too
+  /// large to fit a request on its own.
+  ///
+  /// All requests are sent concurrently, so as with
+  /// [`write_many`][Client::write_many], a failed request does not stop the
+  /// others; its error is recorded in the returned [`WriteSummary`].
+  pub async fn write_rows_within_byte_budget(
+    &mut self,
+    table_name: &str,
+    partition: &HashMap<String, PartitionFieldValue>,
+    rows: Vec<Row>,
+    max_rows: usize,
+    max_bytes: usize,
+  ) -> ClientResult<WriteSummary> {
+    let reqs = byte_budget_requests(table_name, partition, rows, max_rows, max_bytes)?;
+    let outcome = self.write_many(&reqs).await;
+    Ok(write_summary(&reqs, outcome))
+  }
+}
+
+// the tag of `WriteToPartitionRequest::rows`
+const ROWS_TAG: u32 = 3;
+
+// How many bytes a row adds to the encoding of a write request.
+fn encoded_row_len(row: &Row) -> usize {
+  let len = row.encoded_len();
+  prost::encoding::key_len(ROWS_TAG) + prost::length_delimiter_len(len) + len
+}
+
+fn byte_budget_requests(
+  table_name: &str,
+  partition: &HashMap<String, PartitionFieldValue>,
+  rows: Vec<Row>,
+  max_rows: usize,
+  max_bytes: usize,
+) -> ClientResult<Vec<WriteToPartitionRequest>> {
+  if max_rows == 0 {
+    return Err(ClientError::other("max rows must be positive".to_string()));
+  }
+
+  let empty_req = WriteToPartitionRequest {
+    table_name: table_name.to_string(),
+    partition: partition.clone(),
+    rows: vec![],
+  };
+  let base_len = empty_req.encoded_len();
+  let mut reqs = Vec::new();
+  let mut req = empty_req.clone();
+  let mut req_len = base_len;
+  for (row_idx, row) in rows.into_iter().enumerate() {
+    let row_len = encoded_row_len(&row);
+    if base_len + row_len > max_bytes {
+      return Err(ClientError::other(format!(
+        "row {} alone makes a request of {} bytes, exceeding the budget of {}",
+        row_idx,
+        base_len + row_len,
+        max_bytes,
+      )));
+    }
+    if req.rows.len() == max_rows || req_len + row_len > max_bytes {
+      reqs.push(std::mem::replace(&mut req, empty_req.clone()));
+      req_len = base_len;
+    }
+    req.rows.push(row);
+    req_len += row_len;
+  }
+  if !req.rows.is_empty() {
+    reqs.push(req);
+  }
+  Ok(reqs)
+}
+
+fn partitioned_requests(
+  table_name: &str,
+  rows: Vec<PartitionedRow>,
+  batch_size: usize,
+) -> ClientResult<Vec<WriteToPartitionRequest>> {
+  if batch_size == 0 {
+    return Err(ClientError::other("batch size must be positive".to_string()));
+  }
+
+  let mut group_idxs = HashMap::new();
+  let row_group_idxs = rows.iter()
+    .map(|partitioned_row| {
+      let n_groups = group_idxs.len();
+      *group_idxs.entry(PartitionRef(&partitioned_row.partition)).or_insert(n_groups)
+    })
+    .collect::<Vec<_>>();
+  let n_groups = group_idxs.len();
+  drop(group_idxs);
+
+  let mut partitions = vec![None; n_groups];
+  let mut group_rows = vec![Vec::new(); n_groups];
+  for (partitioned_row, group_idx) in rows.into_iter().zip(row_group_idxs) {
+    let PartitionedRow { partition, row } = partitioned_row;
+    partitions[group_idx].get_or_insert(partition);
+    group_rows[group_idx].push(row);
+  }
+
+  let mut reqs = Vec::new();
+  for (partition, rows) in partitions.into_iter().flatten().zip(group_rows) {
+    for batch in rows.chunks(batch_size) {
+      reqs.push(WriteToPartitionRequest {
+        table_name: table_name.to_string(),
+        partition: partition.clone(),
+        rows: batch.to_vec(),
+      });
+    }
+  }
+  Ok(reqs)
+}
+
+fn write_summary(reqs: &[WriteToPartitionRequest], outcome: WriteManyOutcome) -> WriteSummary {
+  let mut summary = WriteSummary::default();
+  for (req, res) in reqs.iter().zip(outcome.results) {
+    match res {
+      Ok(_) => summary.n_rows_written += req.rows.len(),
+      Err(err) => summary.errors.push(err),
+    }
+  }
+  summary
+}
+
+async fn write_batches<S, F, Fut>(
+  rows: S,
+  batch_size: usize,
+  concurrency: usize,
+  write_batch: F,
+) -> ClientResult<WriteSummary> where
+  S: Stream<Item=Row>,
+  F: Fn(Vec<Row>) -> Fut,
+  Fut: Future<Output=ClientResult<()>>,
+{
+  if batch_size == 0 || concurrency == 0 {
+    return Err(ClientError::other(
+      "batch size and concurrency must both be positive".to_string()
+    ));
+  }
+
+  let summary = rows.chunks(batch_size)
+    .map(|batch| {
+      let n_rows = batch.len();
+      let write_fut = write_batch(batch);
+      async move {
+        write_fut.await.map(|_| n_rows)
+      }
+    })
+    .buffer_unordered(concurrency)
+    .fold(WriteSummary::default(), |mut summary, res| async move {
+      match res {
+        Ok(n_rows) => summary.n_rows_written += n_rows,
+        Err(err) => summary.errors.push(err),
+      }
+      summary
+    })
+    .await;
+  Ok(summary)
+}
+
+async fn write_concurrently<F, Fut>(
+  reqs: &[WriteToPartitionRequest],
+  write: F,
+) -> WriteManyOutcome where
+  F: Fn(WriteToPartitionRequest) -> Fut,
+  Fut: Future<Output=ClientResult<WriteToPartitionResponse>>,
+{
+  let results = futures::future::join_all(reqs.iter().cloned().map(write)).await;
+  WriteManyOutcome { results }
+}
+
+async fn wait_for_row_count<F, Fut>(
+  table_name: &str,
+  min_row_count: u64,
+  count_rows: F,
+  poll_interval: Duration,
+  timeout: Duration,
+) -> ClientResult<()> where
+  F: Fn() -> Fut,
+  Fut: Future<Output=ClientResult<u64>>,
+{
+  crate::utils::poll_until(
+    || async { Ok(count_rows().await? >= min_row_count) },
+    poll_interval,
+    timeout,
+    &format!("table {} to contain {} rows", table_name, min_row_count),
+  ).await
+}
+
+fn check_field_names(rows: &[Row], schema: &Schema) -> ClientResult<()> {
+  let unknown = rows.iter()
+    .flat_map(|row| row.fields.keys())
+    .filter(|name| !schema.columns.contains_key(*name))
+    .collect::<BTreeSet<_>>();
+  if unknown.is_empty() {
+    Ok(())
+  } else {
+    Err(ClientError::other(format!(
+      "rows contain fields that are not columns of the table: {:?}",
+      unknown,
+    )))
+  }
+}
+
+fn is_rejection(err: &ClientError) -> bool {
+  err.kind == ClientErrorKind::Grpc { code: Code::InvalidArgument }
+}
+
+async fn write_bisecting<F, Fut>(
+  rows: Vec<Row>,
+  write_batch: F,
+) -> ClientResult<IsolatedWriteSummary> where
+  F: Fn(Vec<Row>) -> Fut,
+  Fut: Future<Output=ClientResult<()>>,
+{
+  let mut summary = IsolatedWriteSummary::default();
+  // batches still to write, with the next one last
+  let mut pending = vec![rows];
+  while let Some(batch) = pending.pop() {
+    if batch.is_empty() {
+      continue;
+    }
+
+    let n_rows = batch.len();
+    match write_batch(batch.clone()).await {
+      Ok(()) => summary.n_rows_written += n_rows,
+      Err(err) if is_rejection(&err) => {
+        if n_rows == 1 {
+          summary.rejected_rows.extend(batch.into_iter().map(|row| (row, err.clone())));
+        } else {
+          let mut first_half = batch;
+          let second_half = first_half.split_off(n_rows / 2);
+          pending.push(second_half);
+          pending.push(first_half);
+        }
+      },
+      Err(err) => return Err(err),
+    }
+  }
+  Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use crate::{make_partition, make_row};
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_write_batches() -> ClientResult<()> {
+    let rows = futures::stream::iter(0..1000_i64)
+      .map(|i| make_row! { "i" => i });
+    let batch_sizes = Mutex::new(Vec::new());
+    let summary = write_batches(rows, 256, 3, |batch| {
+      batch_sizes.lock().unwrap().push(batch.len());
+      async { Ok(()) }
+    }).await?;
+
+    let mut batch_sizes = batch_sizes.into_inner().unwrap();
+    batch_sizes.sort_unstable();
+    assert_eq!(batch_sizes, vec![232, 256, 256, 256]);
+    assert_eq!(summary, WriteSummary {
+      n_rows_written: 1000,
+      errors: vec![],
+    });
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_write_batches_with_errors() -> ClientResult<()> {
+    let rows = futures::stream::iter(0..10_i64)
+      .map(|i| make_row! { "i" => i });
+    let summary = write_batches(rows, 4, 1, |batch| {
+      let fails = batch.len() < 4;
+      async move {
+        if fails {
+          Err(ClientError::other("partial batch failed".to_string()))
+        } else {
+          Ok(())
+        }
+      }
+    }).await?;
+
+    assert_eq!(summary.n_rows_written, 8);
+    assert_eq!(summary.errors.len(), 1);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_write_batches_zero_batch_size() {
+    let rows = futures::stream::iter(Vec::<Row>::new());
+    let res = write_batches(rows, 0, 1, |_| async { Ok(()) }).await;
+    assert!(res.is_err());
+  }
+
+  fn reject_bad_rows(batch: &[Row]) -> ClientResult<()> {
+    let bad_row = make_row! { "i" => 13_i64 };
+    if batch.contains(&bad_row) {
+      Err(ClientError {
+        message: "row 13 is invalid".to_string(),
+        kind: ClientErrorKind::Grpc { code: Code::InvalidArgument },
+      })
+    } else {
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn test_write_bisecting_isolates_bad_row() -> ClientResult<()> {
+    let rows = (0..256_i64)
+      .map(|i| make_row! { "i" => i })
+      .collect::<Vec<_>>();
+    let written = Mutex::new(Vec::new());
+    let summary = write_bisecting(rows, |batch| {
+      let res = reject_bad_rows(&batch);
+      if res.is_ok() {
+        written.lock().unwrap().extend(batch);
+      }
+      async move { res }
+    }).await?;
+
+    assert_eq!(summary.n_rows_written, 255);
+    assert_eq!(summary.rejected_rows.len(), 1);
+    assert_eq!(summary.rejected_rows[0].0, make_row! { "i" => 13_i64 });
+    let expected_written = (0..256_i64)
+      .filter(|&i| i != 13)
+      .map(|i| make_row! { "i" => i })
+      .collect::<Vec<_>>();
+    assert_eq!(written.into_inner().unwrap(), expected_written);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_write_bisecting_fails_on_other_errors() {
+    let rows = vec![make_row! { "i" => 1_i64 }, make_row! { "i" => 2_i64 }];
+    let res = write_bisecting(rows, |_| async {
+      Err(ClientError::other("connection lost".to_string()))
+    }).await;
+    assert!(res.is_err());
+  }
+
+  #[test]
+  fn test_check_field_names() {
+    let mut schema = Schema::default();
+    for name in ["col_0", "col_1"] {
+      schema.columns.insert(name.to_string(), Default::default());
+    }
+    let good_rows = vec![
+      make_row! { "col_0" => 1_i64 },
+      make_row! { "col_0" => 2_i64, "col_1" => true },
+    ];
+    assert!(check_field_names(&good_rows, &schema).is_ok());
+
+    let mut bad_rows = good_rows;
+    bad_rows.push(make_row! { "col_O" => 3_i64 });
+    let err = check_field_names(&bad_rows, &schema).unwrap_err();
+    assert!(err.message.contains("col_O"));
+    assert!(!err.message.contains("col_1"));
+  }
+
+  #[tokio::test]
+  async fn test_write_concurrently_partial_failure() {
+    let reqs = ["a", "b", "c"].iter()
+      .map(|table_name| WriteToPartitionRequest {
+        table_name: table_name.to_string(),
+        ..Default::default()
+      })
+      .collect::<Vec<_>>();
+    let outcome = write_concurrently(&reqs, |req| async move {
+      if req.table_name == "b" {
+        Err(ClientError::other("table b is unavailable".to_string()))
+      } else {
+        Ok(WriteToPartitionResponse {})
+      }
+    }).await;
+
+    assert_eq!(outcome.succeeded(), vec![0, 2]);
+    let failed = outcome.failed();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, 1);
+    let err = outcome.into_result().unwrap_err();
+    assert!(err.message.contains("1 of 3 writes failed"));
+    assert!(err.message.contains("request 1"));
+  }
+
+  #[tokio::test]
+  async fn test_write_concurrently_success() -> ClientResult<()> {
+    let reqs = vec![WriteToPartitionRequest::default(); 2];
+    let outcome = write_concurrently(&reqs, |_| async { Ok(WriteToPartitionResponse {}) }).await;
+    assert_eq!(outcome.into_result()?.len(), 2);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_wait_for_row_count() -> ClientResult<()> {
+    let n_polls = std::sync::atomic::AtomicUsize::new(0);
+    let count_rows = || {
+      let n = n_polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      async move { Ok(if n < 2 { 50 } else { 100 }) }
+    };
+    wait_for_row_count(
+      "t",
+      100,
+      count_rows,
+      Duration::from_millis(1),
+      Duration::from_secs(10),
+    ).await?;
+    assert_eq!(n_polls.into_inner(), 3);
+
+    let res = wait_for_row_count(
+      "t",
+      100,
+      || async { Ok(99) },
+      Duration::from_millis(1),
+      Duration::from_millis(20),
+    ).await;
+    assert_eq!(res.unwrap_err().kind, ClientErrorKind::Timeout);
+    Ok(())
+  }
+
+  #[test]
+  fn test_partitioned_requests() -> ClientResult<()> {
+    let partitioned_row = |day: i64, i: i64| PartitionedRow {
+      partition: make_partition! { "day" => day },
+      row: make_row! { "i" => i },
+    };
+    let rows = vec![
+      partitioned_row(2, 0),
+      partitioned_row(1, 1),
+      partitioned_row(2, 2),
+      partitioned_row(2, 3),
+      partitioned_row(1, 4),
+    ];
+
+    let reqs = partitioned_requests("t", rows, 2)?;
+    let summarized = reqs.iter()
+      .map(|req| (req.table_name.as_str(), req.partition.clone(), req.rows.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(summarized, vec![
+      ("t", make_partition! { "day" => 2_i64 }, vec![make_row! { "i" => 0_i64 }, make_row! { "i" => 2_i64 }]),
+      ("t", make_partition! { "day" => 2_i64 }, vec![make_row! { "i" => 3_i64 }]),
+      ("t", make_partition! { "day" => 1_i64 }, vec![make_row! { "i" => 1_i64 }, make_row! { "i" => 4_i64 }]),
+    ]);
+
+    assert!(partitioned_requests("t", vec![], 0).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_byte_budget_requests() -> ClientResult<()> {
+    let partition = make_partition! { "day" => 1_i64 };
+    let big = |i: i64| make_row! { "i" => i, "blob" => vec![7_u8; 1000] };
+    let rows = vec![
+      make_row! { "i" => 0_i64 },
+      big(1),
+      big(2),
+      make_row! { "i" => 3_i64 },
+      big(4),
+      make_row! { "i" => 5_i64 },
+    ];
+
+    let max_bytes = 2500;
+    let reqs = byte_budget_requests("t", &partition, rows.clone(), 256, max_bytes)?;
+    let batch_sizes = reqs.iter().map(|req| req.rows.len()).collect::<Vec<_>>();
+    assert_eq!(batch_sizes, vec![4, 2]);
+    for req in &reqs {
+      assert!(req.encoded_len() <= max_bytes);
+      assert_eq!(req.table_name, "t");
+      assert_eq!(req.partition, partition);
+    }
+    assert_eq!(reqs.into_iter().flat_map(|req| req.rows).collect::<Vec<_>>(), rows);
+
+    // the row limit still applies to small rows
+    let reqs = byte_budget_requests("t", &partition, vec![make_row! { "i" => 0_i64 }; 5], 2, max_bytes)?;
+    let batch_sizes = reqs.iter().map(|req| req.rows.len()).collect::<Vec<_>>();
+    assert_eq!(batch_sizes, vec![2, 2, 1]);
+
+    assert!(byte_budget_requests("t", &partition, vec![big(0)], 256, 1000).is_err());
+    assert!(byte_budget_requests("t", &partition, vec![], 0, max_bytes).is_err());
+    assert!(byte_budget_requests("t", &partition, vec![], 256, max_bytes)?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_write_summary() {
+    let req = |n_rows: usize| WriteToPartitionRequest {
+      rows: vec![Row::default(); n_rows],
+      ..Default::default()
+    };
+    let reqs = vec![req(3), req(2), req(4)];
+    let outcome = WriteManyOutcome {
+      results: vec![
+        Ok(WriteToPartitionResponse {}),
+        Err(ClientError::other("nope".to_string())),
+        Ok(WriteToPartitionResponse {}),
+      ],
+    };
+    assert_eq!(write_summary(&reqs, outcome), WriteSummary {
+      n_rows_written: 7,
+      errors: vec![ClientError::other("nope".to_string())],
+    });
+  }
+}
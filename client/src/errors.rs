@@ -2,12 +2,15 @@ use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::string::FromUtf8Error;
 
+use tokio::time::error::Elapsed;
 use tonic::{Code, Status};
 
 trait OtherUpcastable: std::error::Error {}
 impl OtherUpcastable for FromUtf8Error {}
 #[cfg(feature = "read")]
 impl OtherUpcastable for pancake_db_core::errors::CoreError {}
+#[cfg(feature = "json")]
+impl OtherUpcastable for serde_json::Error {}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ClientError {
@@ -21,6 +24,7 @@ pub enum ClientErrorKind {
   Grpc {
     code: Code,
   },
+  Timeout,
   Other,
 }
 
@@ -29,6 +33,7 @@ impl Display for ClientErrorKind {
     let s = match &self {
       ClientErrorKind::Connection => "connection error".to_string(),
       ClientErrorKind::Grpc { code } => format!("GRPC error {}", code),
+      ClientErrorKind::Timeout => "timeout".to_string(),
       ClientErrorKind::Other => "client-side error".to_string(),
     };
     f.write_str(&s)
@@ -42,6 +47,24 @@ impl ClientError {
       kind: ClientErrorKind::Other,
     }
   }
+
+  /// Returns whether the failed request might succeed if sent again.
+  ///
+  /// Connection failures, timeouts, and the GRPC codes `Unavailable`,
+  /// `DeadlineExceeded`, and `ResourceExhausted` are retryable.
+  /// Other GRPC codes mean the server rejected the request itself, and
+  /// client-side errors won't change on a retry, so neither is retryable.
+  /// This is the same policy the client's own retries follow.
+  pub fn is_retryable(&self) -> bool {
+    match self.kind {
+      ClientErrorKind::Connection | ClientErrorKind::Timeout => true,
+      ClientErrorKind::Grpc { code } => matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted,
+      ),
+      ClientErrorKind::Other => false,
+    }
+  }
 }
 
 impl Display for ClientError {
@@ -73,6 +96,15 @@ impl From<tonic::transport::Error> for ClientError {
   }
 }
 
+impl From<Elapsed> for ClientError {
+  fn from(_: Elapsed) -> Self {
+    ClientError {
+      message: "deadline elapsed before the operation completed".to_string(),
+      kind: ClientErrorKind::Timeout,
+    }
+  }
+}
+
 impl From<Status> for ClientError {
   fn from(status: Status) -> Self {
     ClientError {
@@ -85,3 +117,44 @@ impl From<Status> for ClientError {
 impl std::error::Error for ClientError {}
 
 pub type ClientResult<T> = Result<T, ClientError>;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error_of_kind(kind: ClientErrorKind) -> ClientError {
+    ClientError {
+      message: "".to_string(),
+      kind,
+    }
+  }
+
+  #[test]
+  fn test_is_retryable() {
+    let retryable = [
+      ClientErrorKind::Connection,
+      ClientErrorKind::Timeout,
+      ClientErrorKind::Grpc { code: Code::Unavailable },
+      ClientErrorKind::Grpc { code: Code::DeadlineExceeded },
+      ClientErrorKind::Grpc { code: Code::ResourceExhausted },
+    ];
+    for kind in retryable {
+      assert!(error_of_kind(kind.clone()).is_retryable(), "{}", kind);
+    }
+
+    let not_retryable = [
+      ClientErrorKind::Other,
+      ClientErrorKind::Grpc { code: Code::InvalidArgument },
+      ClientErrorKind::Grpc { code: Code::NotFound },
+      ClientErrorKind::Grpc { code: Code::AlreadyExists },
+      ClientErrorKind::Grpc { code: Code::Internal },
+      ClientErrorKind::Grpc { code: Code::Ok },
+    ];
+    for kind in not_retryable {
+      assert!(!error_of_kind(kind.clone()).is_retryable(), "{}", kind);
+    }
+
+    assert!(ClientError::from(Status::unavailable("down")).is_retryable());
+    assert!(!ClientError::other("bad row".to_string()).is_retryable());
+  }
+}
@@ -0,0 +1,327 @@
+use pancake_db_idl::dml::{FieldValue, Row};
+use pancake_db_idl::dml::field_value::Value;
+use prost_types::Timestamp;
+use serde_json::{Map, Number};
+
+use crate::bytes_helpers::{encode_bytes_base64, encode_bytes_hex};
+use crate::errors::{ClientError, ClientResult};
+use crate::row_helpers::FieldValueConverter;
+
+/// Re-export for the purpose of [`row_to_json`].
+pub use serde_json::Value as JsonValue;
+
+/// A JSON document stored in a string column.
+///
+/// Pancake has no JSON data type, so documents are serialized into `String`
+/// columns and reuse the string codec.
+/// Use [`Json::parse`] to validate text before writing it, and
+/// [`parse_json_field`] to read documents back.
+///
+/// ```
+/// use pancake_db_client::make_row;
+/// use pancake_db_client::json_helpers::{parse_json_field, Json};
+///
+/// let doc = Json::parse(r#"{"a": [1, 2]}"#).unwrap();
+/// let row = make_row! { "doc" => doc.clone() };
+/// assert_eq!(parse_json_field(&row.fields["doc"]).unwrap(), Some(doc.0));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json(pub JsonValue);
+
+impl Json {
+  /// Parses JSON text, returning an error if it is malformed.
+  pub fn parse(text: &str) -> ClientResult<Json> {
+    Ok(Json(serde_json::from_str(text)?))
+  }
+}
+
+impl FieldValueConverter for Json {
+  fn to_value(self) -> Option<Value> {
+    Some(Value::StringVal(self.0.to_string()))
+  }
+}
+
+/// Reads a JSON document from a string field written with [`Json`].
+///
+/// Returns `None` for null fields and an error if the field is not a string
+/// or does not contain valid JSON.
+pub fn parse_json_field(fv: &FieldValue) -> ClientResult<Option<JsonValue>> {
+  match &fv.value {
+    None => Ok(None),
+    Some(Value::StringVal(s)) => Ok(Some(Json::parse(s)?.0)),
+    Some(_) => Err(ClientError::other(
+      "JSON fields must be stored as strings".to_string()
+    )),
+  }
+}
+
+/// How [`row_to_json`] represents null values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NullMode {
+  /// Leaves null fields out of the JSON object entirely.
+  ///
+  /// Null elements within lists cannot be omitted, so they become JSON
+  /// `null`s.
+  Omit,
+  /// Writes null fields and null list elements as JSON `null`.
+  Null,
+  /// Writes null fields and null list elements as the given JSON value.
+  Sentinel(JsonValue),
+}
+
+/// How [`row_to_json`] represents bytes values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesEncoding {
+  /// A standard, padded base64 string.
+  Base64,
+  /// A lowercase hex string.
+  Hex,
+  /// An array of integers from 0 to 255.
+  Array,
+}
+
+/// Options controlling [`row_to_json`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonOptions {
+  pub null_mode: NullMode,
+  pub bytes_encoding: BytesEncoding,
+}
+
+impl Default for JsonOptions {
+  fn default() -> Self {
+    JsonOptions {
+      null_mode: NullMode::Null,
+      bytes_encoding: BytesEncoding::Base64,
+    }
+  }
+}
+
+/// Converts a row into a JSON object keyed by column name.
+///
+/// Timestamps become RFC 3339 strings in UTC with microsecond precision.
+/// Non-finite floats, which JSON numbers cannot express, become the strings
+/// `"NaN"`, `"inf"`, and `"-inf"`.
+///
+/// ```
+/// use pancake_db_client::make_row;
+/// use pancake_db_client::json_helpers::{row_to_json, JsonOptions, NullMode};
+///
+/// let row = make_row! {
+///   "i" => 5_i64,
+///   "s" => Option::<String>::None,
+/// };
+/// let options = JsonOptions {
+///   null_mode: NullMode::Omit,
+///   ..Default::default()
+/// };
+/// assert_eq!(row_to_json(&row, &options).to_string(), r#"{"i":5}"#);
+/// ```
+pub fn row_to_json(row: &Row, options: &JsonOptions) -> JsonValue {
+  let mut keys = row.fields.keys().collect::<Vec<_>>();
+  keys.sort_unstable();
+
+  let mut res = Map::new();
+  for key in keys {
+    let fv = &row.fields[key];
+    if fv.value.is_none() && options.null_mode == NullMode::Omit {
+      continue;
+    }
+    res.insert(key.clone(), field_value_to_json(fv, options));
+  }
+  JsonValue::Object(res)
+}
+
+/// Converts a single field value into JSON.
+///
+/// See [`row_to_json`] for details on the representation.
+pub fn field_value_to_json(fv: &FieldValue, options: &JsonOptions) -> JsonValue {
+  match &fv.value {
+    None => match &options.null_mode {
+      NullMode::Sentinel(sentinel) => sentinel.clone(),
+      NullMode::Omit | NullMode::Null => JsonValue::Null,
+    },
+    Some(value) => value_to_json(value, options),
+  }
+}
+
+fn value_to_json(value: &Value, options: &JsonOptions) -> JsonValue {
+  match value {
+    Value::ListVal(repeated) => JsonValue::Array(
+      repeated.vals.iter()
+        .map(|fv| field_value_to_json(fv, options))
+        .collect()
+    ),
+    Value::StringVal(s) => JsonValue::String(s.clone()),
+    Value::BoolVal(b) => JsonValue::Bool(*b),
+    Value::BytesVal(bytes) => bytes_to_json(bytes, options.bytes_encoding),
+    Value::Int64Val(x) => JsonValue::Number(Number::from(*x)),
+    Value::Float32Val(x) => float_to_json(*x as f64),
+    Value::Float64Val(x) => float_to_json(*x),
+    Value::TimestampVal(t) => JsonValue::String(timestamp_to_rfc3339(t)),
+  }
+}
+
+fn float_to_json(x: f64) -> JsonValue {
+  match Number::from_f64(x) {
+    Some(number) => JsonValue::Number(number),
+    None if x.is_nan() => JsonValue::String("NaN".to_string()),
+    None if x > 0.0 => JsonValue::String("inf".to_string()),
+    None => JsonValue::String("-inf".to_string()),
+  }
+}
+
+fn bytes_to_json(bytes: &[u8], encoding: BytesEncoding) -> JsonValue {
+  match encoding {
+    BytesEncoding::Base64 => JsonValue::String(encode_bytes_base64(bytes)),
+    BytesEncoding::Hex => JsonValue::String(encode_bytes_hex(bytes)),
+    BytesEncoding::Array => JsonValue::Array(
+      bytes.iter()
+        .map(|&b| JsonValue::Number(Number::from(b)))
+        .collect()
+    ),
+  }
+}
+
+fn timestamp_to_rfc3339(t: &Timestamp) -> String {
+  let days = t.seconds.div_euclid(86400);
+  let secs_of_day = t.seconds.rem_euclid(86400);
+
+  // civil date from days since the epoch, per Howard Hinnant's algorithm
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let day_of_era = z.rem_euclid(146097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+  format!(
+    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+    year,
+    month,
+    day,
+    secs_of_day / 3600,
+    (secs_of_day / 60) % 60,
+    secs_of_day % 60,
+    t.nanos / 1000,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, UNIX_EPOCH};
+
+  use serde_json::json;
+
+  use crate::make_row;
+
+  use super::*;
+
+  fn test_row() -> Row {
+    make_row! {
+      "present" => 7_i64,
+      "absent" => Option::<i64>::None,
+      "list" => vec![Some(true), None],
+    }
+  }
+
+  #[test]
+  fn test_null_mode_omit() {
+    let options = JsonOptions {
+      null_mode: NullMode::Omit,
+      ..Default::default()
+    };
+    assert_eq!(
+      row_to_json(&test_row(), &options),
+      json!({"present": 7, "list": [true, null]}),
+    );
+  }
+
+  #[test]
+  fn test_null_mode_null() {
+    let options = JsonOptions::default();
+    assert_eq!(
+      row_to_json(&test_row(), &options),
+      json!({"present": 7, "absent": null, "list": [true, null]}),
+    );
+  }
+
+  #[test]
+  fn test_null_mode_sentinel() {
+    let options = JsonOptions {
+      null_mode: NullMode::Sentinel(json!("N/A")),
+      ..Default::default()
+    };
+    assert_eq!(
+      row_to_json(&test_row(), &options),
+      json!({"present": 7, "absent": "N/A", "list": [true, "N/A"]}),
+    );
+  }
+
+  #[test]
+  fn test_bytes_encodings() {
+    let row = make_row! {
+      "b" => vec![0_u8, 255, 77, 1],
+    };
+    let expectations = [
+      (BytesEncoding::Base64, json!({"b": "AP9NAQ=="})),
+      (BytesEncoding::Hex, json!({"b": "00ff4d01"})),
+      (BytesEncoding::Array, json!({"b": [0, 255, 77, 1]})),
+    ];
+    for (bytes_encoding, expected) in expectations {
+      let options = JsonOptions {
+        bytes_encoding,
+        ..Default::default()
+      };
+      assert_eq!(row_to_json(&row, &options), expected);
+    }
+  }
+
+  #[test]
+  fn test_json_round_trip() -> ClientResult<()> {
+    let doc = Json::parse(r#"{"name": "pancake", "tags": ["a", null], "n": 1.5}"#)?;
+    let row = make_row! {
+      "doc" => doc.clone(),
+      "missing" => Option::<Json>::None,
+    };
+    assert!(matches!(row.fields["doc"].value, Some(Value::StringVal(_))));
+    assert_eq!(parse_json_field(&row.fields["doc"])?, Some(doc.0));
+    assert_eq!(parse_json_field(&row.fields["missing"])?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_json_rejects_malformed() {
+    assert!(Json::parse(r#"{"unterminated": "#).is_err());
+    assert!(Json::parse("").is_err());
+    let fv = FieldValue {
+      value: Some(Value::StringVal("[1, 2".to_string())),
+    };
+    assert!(parse_json_field(&fv).is_err());
+    let fv = FieldValue {
+      value: Some(Value::Int64Val(1)),
+    };
+    assert!(parse_json_field(&fv).is_err());
+  }
+
+  #[test]
+  fn test_timestamps_and_floats() {
+    let row = make_row! {
+      "t" => UNIX_EPOCH + Duration::from_micros(951_782_400_123_456),
+      "pre_epoch" => UNIX_EPOCH - Duration::from_secs(1),
+      "nan" => f64::NAN,
+      "neg_inf" => f32::NEG_INFINITY,
+    };
+    assert_eq!(
+      row_to_json(&row, &JsonOptions::default()),
+      json!({
+        "t": "2000-02-29T00:00:00.123456Z",
+        "pre_epoch": "1969-12-31T23:59:59.000000Z",
+        "nan": "NaN",
+        "neg_inf": "-inf",
+      }),
+    );
+  }
+}
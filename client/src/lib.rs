@@ -26,15 +26,20 @@
 //!
 //! See [`Client`] for more details.
 #[doc = include_str!("../README.md")]
+pub use types::{ColumnStorageInfo, EnsureTableOutcome, IsolatedWriteSummary, PartialSegment, PartitionedRow, RetryPolicy, SchemaLookup, SchemaMigration, SegmentColumnChunk, SegmentKey, ShutdownReport, TableSize, WriteManyOutcome, WriteSummary};
+#[cfg(feature = "read")]
+pub use types::{DecodeScratch, SegmentDiff};
+pub use utils::{new_correlation_id, with_deadline};
 
-pub use types::SegmentKey;
-pub use utils::new_correlation_id;
-
+pub mod bytes_helpers;
 pub mod errors;
+#[cfg(feature = "json")]
+pub mod json_helpers;
 pub mod row_helpers;
 pub mod partition_helpers;
+pub mod segment_helpers;
 
-pub use client::Client;
+pub use client::{Client, ClientBuilder};
 
 mod types;
 mod utils;
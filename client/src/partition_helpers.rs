@@ -91,8 +91,6 @@ mod tests {
   use pancake_db_idl::dml::PartitionFieldValue;
   use prost_types::Timestamp;
 
-  use crate::make_partition;
-
   #[test]
   fn test_partition_macro() {
     let timestamp = SystemTime::now();
@@ -101,7 +99,7 @@ mod tests {
     let p2 = make_partition! {
       "i64" => 5_i64,
       "bool" => true,
-      "timestamp" => timestamp.clone(),
+      "timestamp" => timestamp,
       "string" => "asdf".to_string(),
     };
 
@@ -117,15 +115,13 @@ mod tests {
     }
     assert_val_eq(&p2, "i64", Value::Int64Val(5));
     assert_val_eq(&p2, "bool", Value::BoolVal(true));
-    assert_val_eq(&p2, "timestamp", Value::TimestampVal(Timestamp::from(timestamp.clone())));
+    assert_val_eq(&p2, "timestamp", Value::TimestampVal(Timestamp::from(timestamp)));
     assert_val_eq(&p2, "string", Value::StringVal("asdf".to_string()));
   }
 }
 
 #[cfg(test)]
 mod tests_no_imports {
-  use crate::make_partition;
-
   #[test]
   fn test_partition_macro() {
     println!("{:?}", make_partition! {});
@@ -1,9 +1,16 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
 use std::time::SystemTime;
 
 use pancake_db_idl::dml::field_value::Value;
 use pancake_db_idl::dml::RepeatedFieldValue;
 use prost_types::Timestamp;
 
+use crate::errors::{ClientError, ClientResult};
+
 /// Re-export for the purpose of [`make_row`].
 pub use pancake_db_idl::dml::{FieldValue, Row};
 
@@ -48,6 +55,24 @@ impl FieldValueConverter for String {
   }
 }
 
+impl FieldValueConverter for Cow<'_, str> {
+  fn to_value(self) -> Option<Value> {
+    Some(Value::StringVal(self.into_owned()))
+  }
+}
+
+impl FieldValueConverter for Box<str> {
+  fn to_value(self) -> Option<Value> {
+    Some(Value::StringVal(self.into_string()))
+  }
+}
+
+impl FieldValueConverter for Arc<str> {
+  fn to_value(self) -> Option<Value> {
+    Some(Value::StringVal(self.to_string()))
+  }
+}
+
 impl FieldValueConverter for Vec<u8> {
   fn to_value(self) -> Option<Value> {
     Some(Value::BytesVal(self))
@@ -74,6 +99,27 @@ impl<T: FieldValueConverter> FieldValueConverter for Vec<T> {
   }
 }
 
+/// A null list of `T`, for use in [`make_row`].
+///
+/// Converts to the same null field as `Option::<Vec<T>>::None`, but names
+/// the list's element type and reads unambiguously next to empty lists and
+/// lists with null elements.
+/// See [`make_row`] for how the three differ.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NullList<T>(PhantomData<T>);
+
+impl<T> NullList<T> {
+  pub fn new() -> Self {
+    NullList(PhantomData)
+  }
+}
+
+impl<T: FieldValueConverter> FieldValueConverter for NullList<T> {
+  fn to_value(self) -> Option<Value> {
+    None
+  }
+}
+
 /// Helper macro to support [`make_row`].
 #[macro_export]
 macro_rules! make_row_insert {
@@ -110,6 +156,27 @@ macro_rules! make_row_insert {
 /// Values can be any
 /// Rust type that corresponds to a Pancake type, or `Option`s or nested `Vec`s
 /// thereof.
+///
+/// List columns distinguish three shapes that are easy to mix up:
+/// * [`NullList::<T>::new()`][NullList] (or `Option::<Vec<T>>::None`) is a
+///   null field, with no list at all,
+/// * `Vec::<T>::new()` is a present but empty list, and
+/// * `vec![Some(x), None]` is a list with a null element.
+///
+/// PancakeDB stores the first two, but rejects lists with null elements, so
+/// the third shape is only useful for rows that are never written.
+///
+/// ```
+/// use pancake_db_client::make_row;
+/// use pancake_db_client::row_helpers::NullList;
+///
+/// let row = make_row! {
+///   "null_list" => NullList::<i64>::new(),
+///   "empty_list" => Vec::<i64>::new(),
+/// };
+/// assert!(row.fields["null_list"].value.is_none());
+/// assert!(row.fields["empty_list"].value.is_some());
+/// ```
 #[macro_export]
 macro_rules! make_row {
   {} => {
@@ -124,6 +191,280 @@ macro_rules! make_row {
   };
 }
 
+/// Returns the smallest non-null value of a column across rows.
+///
+/// Values are ordered according to their data type: numerically for ints
+/// and floats, lexicographically for strings and bytes, chronologically for
+/// timestamps, and `false < true` for bools.
+/// Nulls, missing fields, lists, and NaNs are ignored.
+/// Returns `None` if no orderable value is found.
+pub fn column_min(rows: &[Row], column_name: &str) -> Option<Value> {
+  column_extremum(rows, column_name, Ordering::Less)
+}
+
+/// Returns the largest non-null value of a column across rows.
+///
+/// See [`column_min`] for how values are ordered.
+pub fn column_max(rows: &[Row], column_name: &str) -> Option<Value> {
+  column_extremum(rows, column_name, Ordering::Greater)
+}
+
+fn column_extremum(rows: &[Row], column_name: &str, keep: Ordering) -> Option<Value> {
+  let mut res: Option<&Value> = None;
+  for row in rows {
+    let value = match row.fields.get(column_name).and_then(|fv| fv.value.as_ref()) {
+      Some(value) => value,
+      None => continue,
+    };
+    res = match res {
+      None if compare_values(value, value).is_some() => Some(value),
+      Some(current) if compare_values(value, current) == Some(keep) => Some(value),
+      other => other,
+    };
+  }
+  res.cloned()
+}
+
+fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+  match (a, b) {
+    (Value::StringVal(a), Value::StringVal(b)) => Some(a.cmp(b)),
+    (Value::BoolVal(a), Value::BoolVal(b)) => Some(a.cmp(b)),
+    (Value::BytesVal(a), Value::BytesVal(b)) => Some(a.cmp(b)),
+    (Value::Int64Val(a), Value::Int64Val(b)) => Some(a.cmp(b)),
+    (Value::Float32Val(a), Value::Float32Val(b)) => a.partial_cmp(b),
+    (Value::Float64Val(a), Value::Float64Val(b)) => a.partial_cmp(b),
+    (Value::TimestampVal(a), Value::TimestampVal(b)) => Some(
+      (a.seconds, a.nanos).cmp(&(b.seconds, b.nanos))
+    ),
+    _ => None,
+  }
+}
+
+/// Returns whether the field value is null.
+///
+/// A list is never null, even if it is empty or all its elements are null.
+pub fn is_null(fv: &FieldValue) -> bool {
+  fv.value.is_none()
+}
+
+/// Compares field values by meaning rather than structure.
+///
+/// Nulls equal each other, lists are compared element by element with the
+/// same rules, and NaN floats equal each other.
+/// A null never equals a list, even a list of nulls.
+pub fn fields_semantically_equal(a: &FieldValue, b: &FieldValue) -> bool {
+  match (&a.value, &b.value) {
+    (None, None) => true,
+    (Some(Value::ListVal(a)), Some(Value::ListVal(b))) => {
+      a.vals.len() == b.vals.len() &&
+        a.vals.iter().zip(&b.vals).all(|(a, b)| fields_semantically_equal(a, b))
+    },
+    (Some(Value::Float32Val(a)), Some(Value::Float32Val(b))) => a == b || (a.is_nan() && b.is_nan()),
+    (Some(Value::Float64Val(a)), Some(Value::Float64Val(b))) => a == b || (a.is_nan() && b.is_nan()),
+    (Some(a), Some(b)) => a == b,
+    _ => false,
+  }
+}
+
+/// Compares rows by meaning rather than structure.
+///
+/// A field missing from a row is treated the same as a field explicitly set
+/// to null, and fields are compared with [`fields_semantically_equal`].
+pub fn rows_semantically_equal(a: &Row, b: &Row) -> bool {
+  let null = FieldValue::default();
+  a.fields.keys()
+    .chain(b.fields.keys())
+    .all(|key| fields_semantically_equal(
+      a.fields.get(key).unwrap_or(&null),
+      b.fields.get(key).unwrap_or(&null),
+    ))
+}
+
+/// A hashable column value that rows can be indexed by.
+///
+/// Floats and lists can't be keys, since they have no exact equality.
+/// Convert native values with `From` to look rows up:
+///
+/// ```
+/// use pancake_db_client::make_row;
+/// use pancake_db_client::row_helpers::{index_rows_by, RowKey};
+///
+/// let rows = vec![
+///   make_row! { "id" => 7_i64, "name" => "a".to_string() },
+///   make_row! { "id" => 8_i64, "name" => "b".to_string() },
+/// ];
+/// let expected = rows[1].clone();
+/// let by_id = index_rows_by(rows, "id").unwrap();
+/// assert_eq!(by_id[&RowKey::from(8)], expected);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RowKey {
+  Bool(bool),
+  Int64(i64),
+  String(String),
+  Bytes(Vec<u8>),
+  /// Seconds and nanoseconds since the Unix epoch.
+  Timestamp(i64, i32),
+}
+
+impl RowKey {
+  /// Converts a value to a key, returning `None` for floats and lists.
+  pub fn from_value(value: &Value) -> Option<RowKey> {
+    match value {
+      Value::BoolVal(b) => Some(RowKey::Bool(*b)),
+      Value::Int64Val(x) => Some(RowKey::Int64(*x)),
+      Value::StringVal(s) => Some(RowKey::String(s.clone())),
+      Value::BytesVal(b) => Some(RowKey::Bytes(b.clone())),
+      Value::TimestampVal(t) => Some(RowKey::Timestamp(t.seconds, t.nanos)),
+      Value::Float32Val(_) | Value::Float64Val(_) | Value::ListVal(_) => None,
+    }
+  }
+}
+
+impl From<bool> for RowKey {
+  fn from(b: bool) -> Self {
+    RowKey::Bool(b)
+  }
+}
+
+impl From<i64> for RowKey {
+  fn from(x: i64) -> Self {
+    RowKey::Int64(x)
+  }
+}
+
+impl From<String> for RowKey {
+  fn from(s: String) -> Self {
+    RowKey::String(s)
+  }
+}
+
+impl From<&str> for RowKey {
+  fn from(s: &str) -> Self {
+    RowKey::String(s.to_string())
+  }
+}
+
+impl From<Vec<u8>> for RowKey {
+  fn from(b: Vec<u8>) -> Self {
+    RowKey::Bytes(b)
+  }
+}
+
+impl From<SystemTime> for RowKey {
+  fn from(t: SystemTime) -> Self {
+    let t = Timestamp::from(t);
+    RowKey::Timestamp(t.seconds, t.nanos)
+  }
+}
+
+/// Moves rows into a map keyed by the value of one column, e.g. for joins.
+///
+/// Returns an error if any row's key is null or missing, is not a valid
+/// [`RowKey`], or duplicates another row's key.
+pub fn index_rows_by(rows: Vec<Row>, key_column: &str) -> ClientResult<HashMap<RowKey, Row>> {
+  let mut res = HashMap::with_capacity(rows.len());
+  for (row_idx, row) in rows.into_iter().enumerate() {
+    let value = row.fields.get(key_column)
+      .and_then(|fv| fv.value.as_ref())
+      .ok_or_else(|| ClientError::other(format!(
+        "row {} has a null key in column {}",
+        row_idx,
+        key_column,
+      )))?;
+    let key = RowKey::from_value(value).ok_or_else(|| ClientError::other(format!(
+      "row {} has a value in column {} that can't be a key: {:?}",
+      row_idx,
+      key_column,
+      value,
+    )))?;
+    if res.contains_key(&key) {
+      return Err(ClientError::other(format!(
+        "row {} duplicates key {:?} in column {}",
+        row_idx,
+        key,
+        key_column,
+      )));
+    }
+    res.insert(key, row);
+  }
+  Ok(res)
+}
+
+/// What to do with NaN and infinite floats before writing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+  /// Write them as they are.
+  Allow,
+  /// Fail, naming the first offending field.
+  Reject,
+  /// Replace each field containing one with null.
+  ///
+  /// Lists can't hold nulls, so a list containing a non-finite float is
+  /// nulled as a whole.
+  Nullify,
+}
+
+/// Applies the policy to every NaN or infinite float in the rows.
+///
+/// Under [`NonFinitePolicy::Reject`], the rows are left unchanged if any
+/// field violates the policy.
+pub fn apply_non_finite_policy(rows: &mut [Row], policy: NonFinitePolicy) -> ClientResult<()> {
+  match policy {
+    NonFinitePolicy::Allow => (),
+    NonFinitePolicy::Reject => {
+      for (row_idx, row) in rows.iter().enumerate() {
+        for (name, fv) in &row.fields {
+          if has_non_finite(fv) {
+            return Err(ClientError::other(format!(
+              "field {} of row {} contains a NaN or infinite float: {:?}",
+              name,
+              row_idx,
+              fv,
+            )));
+          }
+        }
+      }
+    },
+    NonFinitePolicy::Nullify => {
+      for row in rows {
+        for fv in row.fields.values_mut() {
+          if has_non_finite(fv) {
+            fv.value = None;
+          }
+        }
+      }
+    },
+  }
+  Ok(())
+}
+
+/// Sets the timestamp column of every row that omits it.
+///
+/// A row omits the column if the field is missing or null; rows that
+/// already have a value keep it.
+/// All filled rows get the same timestamp.
+pub fn fill_missing_timestamps(rows: &mut [Row], column_name: &str, timestamp: SystemTime) {
+  let timestamp = FieldValue {
+    value: timestamp.to_value(),
+  };
+  for row in rows {
+    let fv = row.fields.entry(column_name.to_string()).or_default();
+    if is_null(fv) {
+      *fv = timestamp.clone();
+    }
+  }
+}
+
+fn has_non_finite(fv: &FieldValue) -> bool {
+  match &fv.value {
+    Some(Value::Float32Val(x)) => !x.is_finite(),
+    Some(Value::Float64Val(x)) => !x.is_finite(),
+    Some(Value::ListVal(l)) => l.vals.iter().any(has_non_finite),
+    _ => false,
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use std::time::SystemTime;
@@ -132,8 +473,6 @@ mod tests {
   use pancake_db_idl::dml::field_value::Value;
   use prost_types::Timestamp;
 
-  use crate::make_row;
-
   #[test]
   fn test_row_macro() {
     let timestamp = SystemTime::now();
@@ -143,7 +482,7 @@ mod tests {
       "f32" => 3.3_f32,
       "i64" => 4_i64,
       "bool" => false,
-      "timestamp" => timestamp.clone(),
+      "timestamp" => timestamp,
       "present" => Some("asdf".to_string()),
       "absent" => Option::<String>::None,
       "bytes" => vec![0_u8, 1_u8],
@@ -161,7 +500,7 @@ mod tests {
     assert_val_eq(&row2, "f32", Some(Value::Float32Val(3.3)));
     assert_val_eq(&row2, "i64", Some(Value::Int64Val(4)));
     assert_val_eq(&row2, "bool", Some(Value::BoolVal(false)));
-    assert_val_eq(&row2, "timestamp", Some(Value::TimestampVal(Timestamp::from(timestamp.clone()))));
+    assert_val_eq(&row2, "timestamp", Some(Value::TimestampVal(Timestamp::from(timestamp))));
     assert_val_eq(&row2, "present", Some(Value::StringVal("asdf".to_string())));
     assert_val_eq(&row2, "absent", None);
     assert_val_eq(&row2, "bytes", Some(Value::BytesVal(vec![0, 1])));
@@ -170,9 +509,287 @@ mod tests {
 }
 
 #[cfg(test)]
-mod tests_no_imports {
-  use crate::make_row;
+mod tests_string_types {
+  use std::borrow::Cow;
+  use std::sync::Arc;
+
+  #[test]
+  fn test_string_like_values() {
+    let expected = make_row! {
+      "borrowed" => "a".to_string(),
+      "owned" => "b".to_string(),
+      "boxed" => "c".to_string(),
+      "shared" => "d".to_string(),
+      "list" => vec!["e".to_string()],
+    };
+    let row = make_row! {
+      "borrowed" => Cow::Borrowed("a"),
+      "owned" => Cow::<str>::Owned("b".to_string()),
+      "boxed" => Box::<str>::from("c"),
+      "shared" => Arc::<str>::from("d"),
+      "list" => vec![Box::<str>::from("e")],
+    };
+    assert_eq!(row, expected);
+  }
+}
+
+#[cfg(test)]
+mod tests_null_semantics {
+  use super::*;
+
+  fn null() -> FieldValue {
+    FieldValue::default()
+  }
+
+  fn list(vals: Vec<FieldValue>) -> FieldValue {
+    FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+    }
+  }
+
+  fn int(x: i64) -> FieldValue {
+    FieldValue {
+      value: Some(Value::Int64Val(x)),
+    }
+  }
+
+  #[test]
+  fn test_list_shapes() {
+    let row = crate::make_row! {
+      "null_list" => NullList::<i64>::new(),
+      "empty_list" => Vec::<i64>::new(),
+      "null_element" => vec![Some(1_i64), None],
+      "null_nested" => vec![NullList::<i64>::new()],
+    };
+    assert_eq!(row.fields["null_list"], null());
+    assert_eq!(row.fields["empty_list"], list(vec![]));
+    assert_eq!(row.fields["null_element"], list(vec![int(1), null()]));
+    assert_eq!(row.fields["null_nested"], list(vec![null()]));
+    assert_eq!(
+      row.fields["null_list"],
+      FieldValue { value: Option::<Vec<i64>>::None.to_value() },
+    );
+  }
+
+  #[test]
+  fn test_top_level_null() {
+    assert!(is_null(&null()));
+    assert!(fields_semantically_equal(&null(), &null()));
+    assert!(!fields_semantically_equal(&null(), &int(0)));
+  }
+
+  #[test]
+  fn test_list_of_nulls() {
+    let nulls = list(vec![null(), null()]);
+    assert!(!is_null(&nulls));
+    assert!(!is_null(&list(vec![])));
+    assert!(fields_semantically_equal(&nulls, &list(vec![null(), null()])));
+    assert!(!fields_semantically_equal(&nulls, &list(vec![null()])));
+    assert!(!fields_semantically_equal(&nulls, &null()));
+    assert!(!fields_semantically_equal(&nulls, &list(vec![null(), int(1)])));
+  }
+
+  #[test]
+  fn test_present_values() {
+    assert!(fields_semantically_equal(&int(3), &int(3)));
+    assert!(!fields_semantically_equal(&int(3), &int(4)));
+    let nan = FieldValue {
+      value: Some(Value::Float64Val(f64::NAN)),
+    };
+    assert!(fields_semantically_equal(&nan, &nan.clone()));
+  }
+
+  #[test]
+  fn test_absent_equals_explicit_null() {
+    let absent = make_row! { "a" => 1_i64 };
+    let explicit = make_row! { "a" => 1_i64, "b" => Option::<i64>::None };
+    let present = make_row! { "a" => 1_i64, "b" => 2_i64 };
+    assert!(rows_semantically_equal(&absent, &explicit));
+    assert!(rows_semantically_equal(&explicit, &absent));
+    assert!(!rows_semantically_equal(&absent, &present));
+    assert!(!rows_semantically_equal(&present, &explicit));
+  }
+}
+
+#[cfg(test)]
+mod tests_extrema {
+  use std::time::{Duration, SystemTime};
+
+  use pancake_db_idl::dml::field_value::Value;
+  use prost_types::Timestamp;
+
+  use super::*;
+
+  #[test]
+  fn test_int_extrema() {
+    let rows = vec![
+      make_row! { "x" => Option::<i64>::None },
+      make_row! { "x" => 5_i64 },
+      make_row! {},
+      make_row! { "x" => -3_i64 },
+      make_row! { "x" => 2_i64 },
+    ];
+    assert_eq!(column_min(&rows, "x"), Some(Value::Int64Val(-3)));
+    assert_eq!(column_max(&rows, "x"), Some(Value::Int64Val(5)));
+    assert_eq!(column_min(&rows, "y"), None);
+  }
+
+  #[test]
+  fn test_string_extrema() {
+    let rows = vec![
+      make_row! { "s" => "banana".to_string() },
+      make_row! { "s" => Option::<String>::None },
+      make_row! { "s" => "apple".to_string() },
+      make_row! { "s" => "cherry".to_string() },
+    ];
+    assert_eq!(column_min(&rows, "s"), Some(Value::StringVal("apple".to_string())));
+    assert_eq!(column_max(&rows, "s"), Some(Value::StringVal("cherry".to_string())));
+  }
+
+  #[test]
+  fn test_timestamp_extrema() {
+    let t0 = SystemTime::UNIX_EPOCH + Duration::from_micros(1_500_000);
+    let t1 = SystemTime::UNIX_EPOCH + Duration::from_micros(1_500_001);
+    let t2 = SystemTime::UNIX_EPOCH + Duration::from_secs(2);
+    let rows = vec![
+      make_row! { "t" => t1 },
+      make_row! { "t" => Option::<SystemTime>::None },
+      make_row! { "t" => t2 },
+      make_row! { "t" => t0 },
+    ];
+    assert_eq!(column_min(&rows, "t"), Some(Value::TimestampVal(Timestamp::from(t0))));
+    assert_eq!(column_max(&rows, "t"), Some(Value::TimestampVal(Timestamp::from(t2))));
+  }
+
+  #[test]
+  fn test_all_null() {
+    let rows = vec![
+      make_row! { "x" => Option::<i64>::None },
+    ];
+    assert_eq!(column_max(&rows, "x"), None);
+  }
+}
+
+#[cfg(test)]
+mod tests_index {
+  use super::*;
+
+  #[test]
+  fn test_unique_keys() -> ClientResult<()> {
+    let rows = vec![
+      crate::make_row! { "id" => "a".to_string(), "x" => 1_i64 },
+      crate::make_row! { "id" => "b".to_string(), "x" => 2_i64 },
+      crate::make_row! { "id" => "c".to_string() },
+    ];
+    let by_id = index_rows_by(rows.clone(), "id")?;
+    assert_eq!(by_id.len(), 3);
+    for row in &rows {
+      let key = RowKey::from_value(row.fields["id"].value.as_ref().unwrap()).unwrap();
+      assert_eq!(&by_id[&key], row);
+    }
+    assert_eq!(by_id[&RowKey::from("b")], rows[1]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_duplicate_key() {
+    let rows = vec![
+      crate::make_row! { "id" => 1_i64, "x" => true },
+      crate::make_row! { "id" => 1_i64, "x" => false },
+    ];
+    assert!(index_rows_by(rows, "id").is_err());
+  }
+
+  #[test]
+  fn test_null_key() {
+    let null_key = vec![
+      crate::make_row! { "id" => 1_i64 },
+      crate::make_row! { "id" => Option::<i64>::None },
+    ];
+    assert!(index_rows_by(null_key, "id").is_err());
+
+    let missing_key = vec![crate::make_row! { "x" => 1_i64 }];
+    assert!(index_rows_by(missing_key, "id").is_err());
+  }
+
+  #[test]
+  fn test_unkeyable_values() {
+    let float_key = vec![crate::make_row! { "id" => 1.5_f64 }];
+    assert!(index_rows_by(float_key, "id").is_err());
+    let list_key = vec![crate::make_row! { "id" => vec![1_i64] }];
+    assert!(index_rows_by(list_key, "id").is_err());
+  }
+}
 
+#[cfg(test)]
+mod tests_non_finite {
+  use super::*;
+
+  fn sample_rows() -> Vec<Row> {
+    vec![
+      make_row! { "f" => 1.5_f64, "g" => vec![0.0_f64] },
+      make_row! { "f" => f64::NAN, "g" => vec![1.0_f64, f64::INFINITY] },
+    ]
+  }
+
+  #[test]
+  fn test_reject() {
+    let mut rows = sample_rows();
+    let err = apply_non_finite_policy(&mut rows, NonFinitePolicy::Reject).unwrap_err();
+    assert!(err.message.contains("row 1"), "{}", err.message);
+    assert!(rows_semantically_equal(&rows[1], &sample_rows()[1]));
+
+    let mut finite_rows = sample_rows()[..1].to_vec();
+    assert!(apply_non_finite_policy(&mut finite_rows, NonFinitePolicy::Reject).is_ok());
+  }
+
+  #[test]
+  fn test_nullify() -> ClientResult<()> {
+    let mut rows = sample_rows();
+    apply_non_finite_policy(&mut rows, NonFinitePolicy::Nullify)?;
+    assert_eq!(rows[0], sample_rows()[0]);
+    assert!(rows_semantically_equal(&rows[1], &Row::default()));
+    Ok(())
+  }
+
+  #[test]
+  fn test_allow() -> ClientResult<()> {
+    let mut rows = sample_rows();
+    apply_non_finite_policy(&mut rows, NonFinitePolicy::Allow)?;
+    assert!(rows.iter().zip(sample_rows()).all(|(a, b)| rows_semantically_equal(a, &b)));
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests_timestamps {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[test]
+  fn test_fill_missing_timestamps() {
+    let set_time = SystemTime::UNIX_EPOCH + Duration::from_secs(7);
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mut null_row = make_row! { "i" => 2_i64 };
+    null_row.fields.insert("t".to_string(), FieldValue::default());
+    let mut rows = vec![
+      make_row! { "i" => 0_i64 },
+      make_row! { "i" => 1_i64, "t" => set_time },
+      null_row,
+    ];
+
+    fill_missing_timestamps(&mut rows, "t", now);
+    assert_eq!(rows, vec![
+      make_row! { "i" => 0_i64, "t" => now },
+      make_row! { "i" => 1_i64, "t" => set_time },
+      make_row! { "i" => 2_i64, "t" => now },
+    ]);
+  }
+}
+
+#[cfg(test)]
+mod tests_no_imports {
   #[test]
   fn test_row_macro() {
     println!("{:?}", make_row! {});
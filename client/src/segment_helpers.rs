@@ -0,0 +1,39 @@
+use pancake_db_idl::dml::Segment;
+
+/// Convenience accessors for [`Segment`]s returned by `list_segments`.
+///
+/// Segment metadata is only populated when the list segments request sets
+/// `include_metadata`, so each accessor returns `None` when it is absent.
+/// The current protocol's metadata carries only the row count; timestamps
+/// and compaction status are not reported by the server.
+pub trait SegmentExt {
+  /// The number of rows written to the segment, including deleted rows.
+  fn row_count(&self) -> Option<u32>;
+}
+
+impl SegmentExt for Segment {
+  fn row_count(&self) -> Option<u32> {
+    self.metadata.as_ref().map(|metadata| metadata.row_count)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::SegmentMetadata;
+
+  use super::*;
+
+  #[test]
+  fn test_row_count() {
+    let mut segment = Segment {
+      segment_id: "s".to_string(),
+      ..Default::default()
+    };
+    assert_eq!(segment.row_count(), None);
+
+    segment.metadata = Some(SegmentMetadata {
+      row_count: 77,
+    });
+    assert_eq!(segment.row_count(), Some(77));
+  }
+}
@@ -1,14 +1,451 @@
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
-use pancake_db_idl::dml::PartitionFieldValue;
+use pancake_db_idl::dml::{PartitionFieldValue, Row, WriteToPartitionResponse};
+use pancake_db_idl::dml::partition_field_value::Value as PartitionValue;
+use pancake_db_idl::schema::Schema;
+
+use crate::errors::{ClientError, ClientResult};
 
 /// A fully-specified segment.
 ///
 /// Consists of a table name, partition, and segment ID.
 /// Used in certain high-level client functionality.
+///
+/// Keys compare equal and hash the same whenever their fields match,
+/// regardless of the insertion order of partition entries, so they can be
+/// used as `HashMap` keys.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct SegmentKey {
   pub table_name: String,
   pub partition: HashMap<String, PartitionFieldValue>,
   pub segment_id: String,
 }
+
+// partition values contain no floats, so equality is reflexive
+impl Eq for SegmentKey {}
+
+impl Hash for SegmentKey {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.table_name.hash(state);
+    self.segment_id.hash(state);
+    hash_partition(&self.partition, state);
+  }
+}
+
+// Partition maps aren't hashable themselves, so we wrap them to use them as
+// keys.
+pub(crate) struct PartitionRef<'a>(pub &'a HashMap<String, PartitionFieldValue>);
+
+impl<'a> PartialEq for PartitionRef<'a> {
+  fn eq(&self, other: &Self) -> bool {
+    self.0 == other.0
+  }
+}
+
+impl<'a> Eq for PartitionRef<'a> {}
+
+impl<'a> Hash for PartitionRef<'a> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    hash_partition(self.0, state);
+  }
+}
+
+// Hashes partition entries in name order, so insertion order doesn't matter.
+pub(crate) fn hash_partition<H: Hasher>(
+  partition: &HashMap<String, PartitionFieldValue>,
+  state: &mut H,
+) {
+  let mut entries = partition.iter().collect::<Vec<_>>();
+  entries.sort_unstable_by_key(|(name, _)| *name);
+  state.write_usize(entries.len());
+  for (name, pfv) in entries {
+    name.hash(state);
+    match &pfv.value {
+      None => state.write_u8(0),
+      Some(PartitionValue::StringVal(s)) => {
+        state.write_u8(1);
+        s.hash(state);
+      },
+      Some(PartitionValue::BoolVal(b)) => {
+        state.write_u8(2);
+        b.hash(state);
+      },
+      Some(PartitionValue::Int64Val(x)) => {
+        state.write_u8(3);
+        x.hash(state);
+      },
+      Some(PartitionValue::TimestampVal(t)) => {
+        state.write_u8(4);
+        t.seconds.hash(state);
+        t.nanos.hash(state);
+      },
+    }
+  }
+}
+
+/// A row along with the partition to write it to.
+///
+/// Used with [`Client::write_partitioned_rows`][crate::Client::write_partitioned_rows]
+/// to write rows of many partitions at once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartitionedRow {
+  pub partition: HashMap<String, PartitionFieldValue>,
+  pub row: Row,
+}
+
+/// One response's worth of raw segment column data.
+///
+/// `data` is in the compressed format named by `codec`, or in the
+/// uncompressed encoding if `codec` is empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SegmentColumnChunk {
+  pub codec: String,
+  pub data: Vec<u8>,
+  pub implicit_nulls_count: u32,
+}
+
+/// Reusable buffers for decoding segment columns.
+///
+/// Passing the same scratch to successive calls of
+/// [`Client::decode_segment_column_with_scratch`][crate::Client::decode_segment_column_with_scratch]
+/// lets them reuse the memory that holds each column's raw bytes instead of
+/// allocating it anew for every column.
+#[cfg(feature = "read")]
+#[derive(Clone, Debug, Default)]
+pub struct DecodeScratch {
+  pub(crate) compressed_bytes: Vec<u8>,
+  pub(crate) uncompressed_bytes: Vec<u8>,
+}
+
+/// How a segment column is stored on the server.
+///
+/// `codec` is empty if none of the column's data is compressed yet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColumnStorageInfo {
+  pub codec: String,
+  pub compressed_bytes: usize,
+  pub uncompressed_bytes: usize,
+  pub implicit_nulls_count: u32,
+}
+
+/// The storage a table uses, summed over all its segments.
+///
+/// Byte counts include both compressed and not-yet-compressed data.
+/// `n_rows` includes deleted rows, since they still take up storage until
+/// compaction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableSize {
+  pub total_bytes: usize,
+  pub n_rows: u64,
+  pub column_bytes: HashMap<String, usize>,
+}
+
+impl TableSize {
+  pub(crate) fn add_segment(
+    &mut self,
+    row_count: u32,
+    column_infos: &HashMap<String, ColumnStorageInfo>,
+  ) {
+    self.n_rows += row_count as u64;
+    for (column_name, info) in column_infos {
+      let n_bytes = info.compressed_bytes + info.uncompressed_bytes;
+      self.total_bytes += n_bytes;
+      *self.column_bytes.entry(column_name.clone()).or_insert(0) += n_bytes;
+    }
+  }
+}
+
+/// The outcome of shutting down a client.
+///
+/// `n_completed` counts writes that finished while shutting down, whether
+/// they succeeded or failed.
+/// `n_unfinished` counts writes still running when the timeout passed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+  pub n_completed: usize,
+  pub n_unfinished: usize,
+}
+
+/// The outcome of a bulk write.
+///
+/// Bulk writes keep going when an individual request fails, so this reports
+/// how many rows were written along with every error encountered.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteSummary {
+  pub n_rows_written: usize,
+  pub errors: Vec<ClientError>,
+}
+
+/// The outcome of writing several requests at once.
+///
+/// Writes are not atomic: when some fail, the others may still have been
+/// applied.
+/// `results` holds each request's result, in the order the requests were
+/// given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteManyOutcome {
+  pub results: Vec<ClientResult<WriteToPartitionResponse>>,
+}
+
+impl WriteManyOutcome {
+  /// Returns the indices of the requests that succeeded.
+  pub fn succeeded(&self) -> Vec<usize> {
+    self.results.iter()
+      .enumerate()
+      .filter(|(_, res)| res.is_ok())
+      .map(|(i, _)| i)
+      .collect()
+  }
+
+  /// Returns the indices and errors of the requests that failed.
+  pub fn failed(&self) -> Vec<(usize, &ClientError)> {
+    self.results.iter()
+      .enumerate()
+      .filter_map(|(i, res)| res.as_ref().err().map(|err| (i, err)))
+      .collect()
+  }
+
+  /// Returns every response if all requests succeeded, or otherwise a
+  /// combined error describing each failure.
+  pub fn into_result(self) -> ClientResult<Vec<WriteToPartitionResponse>> {
+    let failures = self.failed().iter()
+      .map(|(i, err)| format!("request {}: {}", i, err))
+      .collect::<Vec<_>>();
+    if !failures.is_empty() {
+      return Err(ClientError::other(format!(
+        "{} of {} writes failed; the rest may have been applied. {}",
+        failures.len(),
+        self.results.len(),
+        failures.join("; "),
+      )));
+    }
+
+    self.results.into_iter().collect()
+  }
+}
+
+/// The outcome of a write that isolates rejected rows.
+///
+/// `rejected_rows` holds each row the server refused on its own, along with
+/// the error it was refused with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IsolatedWriteSummary {
+  pub n_rows_written: usize,
+  pub rejected_rows: Vec<(Row, ClientError)>,
+}
+
+/// The outcome of a segment read that tolerates per-column failures.
+///
+/// `rows` contains only the columns that were read successfully, and
+/// `column_errors` maps each failed column's name to its error.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartialSegment {
+  pub rows: Vec<Row>,
+  pub column_errors: HashMap<String, ClientError>,
+}
+
+/// The outcome of fetching several tables' schemas at once.
+///
+/// `schemas` maps each table whose schema was fetched to its schema, and
+/// `errors` maps each table whose request failed, e.g. because the table
+/// doesn't exist, to its error.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SchemaLookup {
+  pub schemas: HashMap<String, Schema>,
+  pub errors: HashMap<String, ClientError>,
+}
+
+impl SchemaLookup {
+  /// Returns every schema if all requests succeeded, or otherwise a
+  /// combined error describing each failure.
+  pub fn into_result(self) -> ClientResult<HashMap<String, Schema>> {
+    if self.errors.is_empty() {
+      return Ok(self.schemas);
+    }
+
+    let mut failures = self.errors.iter()
+      .map(|(table_name, err)| format!("table {}: {}", table_name, err))
+      .collect::<Vec<_>>();
+    failures.sort_unstable();
+    Err(ClientError::other(format!(
+      "unable to get {} of {} schemas. {}",
+      failures.len(),
+      failures.len() + self.schemas.len(),
+      failures.join("; "),
+    )))
+  }
+}
+
+/// How two segments' rows differ, as computed by
+/// [`Client::diff_segments`][crate::Client::diff_segments].
+///
+/// Each changed row appears as a pair of its row in the first segment and
+/// its row in the second.
+#[cfg(feature = "read")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SegmentDiff {
+  pub added: Vec<Row>,
+  pub removed: Vec<Row>,
+  pub changed: Vec<(Row, Row)>,
+}
+
+/// What [`Client::ensure_table`][crate::Client::ensure_table] did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EnsureTableOutcome {
+  /// The table did not exist and was created.
+  Created,
+  /// The table existed, and these columns were added to it.
+  ColumnsAdded(Vec<String>),
+  /// The table existed with all the schema's columns, so nothing changed.
+  AlreadyExists,
+}
+
+/// What [`Client::migrate_schema`][crate::Client::migrate_schema] changed.
+///
+/// Both lists are sorted by column name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchemaMigration {
+  /// Columns of the target schema that were added to the table.
+  pub added_columns: Vec<String>,
+  /// Columns of the table that the target schema lacks.
+  ///
+  /// PancakeDB can't drop columns, so these are left in place.
+  pub extra_columns: Vec<String>,
+}
+
+/// How to retry a failed request.
+///
+/// Only transient errors, like connection failures and timeouts, are
+/// retried.
+/// After each failed attempt, the wait before the next one grows from
+/// `initial_backoff` by `backoff_multiplier`, up to `max_backoff`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+  /// The total number of attempts, including the first; 1 disables retries.
+  pub max_attempts: usize,
+  pub initial_backoff: Duration,
+  pub max_backoff: Duration,
+  pub backoff_multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_attempts: 3,
+      initial_backoff: Duration::from_millis(100),
+      max_backoff: Duration::from_secs(2),
+      backoff_multiplier: 2.0,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// A policy that makes only one attempt.
+  pub fn no_retries() -> Self {
+    RetryPolicy {
+      max_attempts: 1,
+      ..Default::default()
+    }
+  }
+
+  /// Returns how long to wait after the given number of failed attempts.
+  pub fn backoff(&self, n_failed_attempts: usize) -> Duration {
+    let exponent = n_failed_attempts.saturating_sub(1).min(i32::MAX as usize) as i32;
+    let secs = self.initial_backoff.as_secs_f64() * self.backoff_multiplier.powi(exponent);
+    if secs.is_finite() && secs < self.max_backoff.as_secs_f64() {
+      Duration::from_secs_f64(secs)
+    } else {
+      self.max_backoff
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::hash_map::DefaultHasher;
+  use std::collections::HashSet;
+  use std::time::{Duration, UNIX_EPOCH};
+
+  use crate::make_partition;
+
+  use super::*;
+
+  fn hash_of(key: &SegmentKey) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+  }
+
+  fn segment_key(partition: HashMap<String, PartitionFieldValue>) -> SegmentKey {
+    SegmentKey {
+      table_name: "t".to_string(),
+      partition,
+      segment_id: "s".to_string(),
+    }
+  }
+
+  #[test]
+  fn test_hash_ignores_partition_order() {
+    let entries = (0..20_i64)
+      .map(|i| (format!("p{}", i), PartitionFieldValue {
+        value: Some(PartitionValue::Int64Val(i)),
+      }))
+      .collect::<Vec<_>>();
+    let forward = entries.iter().cloned().collect::<HashMap<_, _>>();
+    let backward = entries.iter().rev().cloned().collect::<HashMap<_, _>>();
+    let a = segment_key(forward);
+    let b = segment_key(backward);
+    assert_eq!(a, b);
+    assert_eq!(hash_of(&a), hash_of(&b));
+
+    let set = vec![a, b].into_iter().collect::<HashSet<_>>();
+    assert_eq!(set.len(), 1);
+  }
+
+  #[test]
+  fn test_hash_distinguishes_values() {
+    let a = segment_key(make_partition! { "p" => 1_i64 });
+    let b = segment_key(make_partition! { "p" => true });
+    let c = segment_key(make_partition! { "p" => UNIX_EPOCH + Duration::from_secs(1) });
+    assert_ne!(a, b);
+    let set = vec![a.clone(), b, c, a].into_iter().collect::<HashSet<_>>();
+    assert_eq!(set.len(), 3);
+  }
+
+  #[test]
+  fn test_backoff() {
+    let policy = RetryPolicy::default();
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(100), Duration::from_secs(2));
+  }
+
+  #[test]
+  fn test_table_size_add_segment() {
+    let info = |compressed_bytes, uncompressed_bytes| ColumnStorageInfo {
+      codec: "q_compress".to_string(),
+      compressed_bytes,
+      uncompressed_bytes,
+      implicit_nulls_count: 0,
+    };
+    let mut size = TableSize::default();
+    size.add_segment(100, &vec![
+      ("a".to_string(), info(40, 5)),
+      ("b".to_string(), info(10, 0)),
+    ].into_iter().collect());
+    size.add_segment(7, &vec![
+      ("a".to_string(), info(0, 3)),
+    ].into_iter().collect());
+
+    assert_eq!(size, TableSize {
+      total_bytes: 58,
+      n_rows: 107,
+      column_bytes: vec![
+        ("a".to_string(), 48),
+        ("b".to_string(), 10),
+      ].into_iter().collect(),
+    });
+  }
+}
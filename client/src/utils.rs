@@ -1,5 +1,13 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::Instant;
 use uuid::Uuid;
 
+use crate::errors::{ClientError, ClientErrorKind, ClientResult};
+#[cfg(feature = "read")]
+use crate::types::RetryPolicy;
+
 /// Generates a new random correlation ID for use in read requests.
 ///
 /// You must use the same correlation ID for all read segment column and
@@ -13,3 +21,124 @@ use uuid::Uuid;
 pub fn new_correlation_id() -> String {
   Uuid::new_v4().to_string()
 }
+
+/// Runs a client operation, failing with a timeout error if it does not
+/// finish by the deadline.
+///
+/// Any requests still in flight when the deadline passes are abandoned, so
+/// this can bound the total time of operations that make many requests,
+/// like [`Client::decode_segment`][crate::Client::decode_segment].
+pub async fn with_deadline<F, T>(deadline: Instant, operation: F) -> ClientResult<T> where
+  F: Future<Output=ClientResult<T>>,
+{
+  tokio::time::timeout_at(deadline, operation).await?
+}
+
+// Checks a condition every poll interval until it holds, failing with a
+// timeout error once the timeout passes.
+pub(crate) async fn poll_until<F, Fut>(
+  condition: F,
+  poll_interval: Duration,
+  timeout: Duration,
+  waiting_for: &str,
+) -> ClientResult<()> where
+  F: Fn() -> Fut,
+  Fut: Future<Output=ClientResult<bool>>,
+{
+  let poll = async {
+    while !condition().await? {
+      tokio::time::sleep(poll_interval).await;
+    }
+    Ok(())
+  };
+  match tokio::time::timeout(timeout, poll).await {
+    Ok(res) => res,
+    Err(_) => Err(ClientError {
+      message: format!("timed out after {:?} waiting for {}", timeout, waiting_for),
+      kind: ClientErrorKind::Timeout,
+    }),
+  }
+}
+
+// Runs an operation until it succeeds, fails with a non-transient error, or
+// runs out of attempts, backing off between attempts.
+#[cfg(feature = "read")]
+pub(crate) async fn retry<F, Fut, T>(policy: &RetryPolicy, operation: F) -> ClientResult<T> where
+  F: Fn() -> Fut,
+  Fut: Future<Output=ClientResult<T>>,
+{
+  let mut n_failed_attempts = 0;
+  loop {
+    match operation().await {
+      Ok(res) => return Ok(res),
+      Err(err) => {
+        n_failed_attempts += 1;
+        if n_failed_attempts >= policy.max_attempts || !err.is_retryable() {
+          return Err(err);
+        }
+        tokio::time::sleep(policy.backoff(n_failed_attempts)).await;
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #[cfg(feature = "read")]
+  use tonic::Code;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn test_with_deadline() {
+    let deadline = Instant::now() + Duration::from_millis(10);
+    let slow = async {
+      tokio::time::sleep(Duration::from_secs(10)).await;
+      Ok(())
+    };
+    let err = with_deadline(deadline, slow).await.unwrap_err();
+    assert_eq!(err.kind, ClientErrorKind::Timeout);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let fast = async { Ok(7) };
+    assert_eq!(with_deadline(deadline, fast).await, Ok(7));
+  }
+
+  #[cfg(feature = "read")]
+  fn fast_policy(max_attempts: usize) -> RetryPolicy {
+    RetryPolicy {
+      max_attempts,
+      initial_backoff: Duration::from_millis(1),
+      ..Default::default()
+    }
+  }
+
+  #[cfg(feature = "read")]
+  fn unavailable() -> ClientError {
+    ClientError {
+      message: "try again".to_string(),
+      kind: ClientErrorKind::Grpc { code: Code::Unavailable },
+    }
+  }
+
+  #[cfg(feature = "read")]
+  #[tokio::test]
+  async fn test_retry() {
+    let n_calls = std::sync::atomic::AtomicUsize::new(0);
+    let flaky = || {
+      let n = n_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      async move { if n < 2 { Err(unavailable()) } else { Ok(n) } }
+    };
+    assert_eq!(retry(&fast_policy(3), flaky).await, Ok(2));
+    n_calls.store(0, std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(retry(&fast_policy(2), flaky).await, Err(unavailable()));
+
+    let n_calls = std::sync::atomic::AtomicUsize::new(0);
+    let invalid = || {
+      n_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      async { Err::<(), _>(ClientError::other("bad".to_string())) }
+    };
+    assert!(retry(&fast_policy(3), invalid).await.is_err());
+    assert_eq!(n_calls.into_inner(), 1);
+  }
+}
@@ -0,0 +1,121 @@
+//! Measures what reserving the output buffer saves when encoding a large
+//! string column.
+//!
+//! One `encode` call over the whole column reserves its output from an
+//! estimate of the encoded size.
+//! Without the estimate, the same pass would start from an empty `Vec` and
+//! grow it as each value is written, so this appends the same encoded
+//! bytes, value by value, to an empty `Vec` and counts the reallocations
+//! that growth costs.
+//!
+//! Run with `cargo run --release --example encoder_alloc_bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use pancake_db_core::encoding;
+use pancake_db_core::errors::CoreResult;
+use pancake_db_idl::dml::FieldValue;
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dtype::DataType;
+
+const N_VALUES: usize = 1_000_000;
+
+struct CountingAlloc;
+
+static N_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+static N_REALLOCS: AtomicUsize = AtomicUsize::new(0);
+static REALLOC_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    N_REALLOCS.fetch_add(1, Ordering::Relaxed);
+    REALLOC_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+struct Counts {
+  allocs: usize,
+  reallocs: usize,
+  realloc_bytes: usize,
+}
+
+fn counts() -> Counts {
+  Counts {
+    allocs: N_ALLOCS.load(Ordering::Relaxed),
+    reallocs: N_REALLOCS.load(Ordering::Relaxed),
+    realloc_bytes: REALLOC_BYTES.load(Ordering::Relaxed),
+  }
+}
+
+fn counts_since(before: &Counts) -> Counts {
+  let now = counts();
+  Counts {
+    allocs: now.allocs - before.allocs,
+    reallocs: now.reallocs - before.reallocs,
+    realloc_bytes: now.realloc_bytes - before.realloc_bytes,
+  }
+}
+
+fn main() -> CoreResult<()> {
+  let fvs = (0..N_VALUES)
+    .map(|i| FieldValue {
+      value: if i % 17 == 0 {
+        None
+      } else {
+        Some(Value::StringVal(format!("user-{}-{}", i % 1000, i)))
+      },
+    })
+    .collect::<Vec<_>>();
+  let encoder = encoding::new_encoder(DataType::String, 0);
+
+  let before = counts();
+  let start = Instant::now();
+  let reserved = encoder.encode(&fvs)?;
+  let reserved_time = start.elapsed();
+  let reserved_counts = counts_since(&before);
+
+  let ranges = encoding::decode_byte_ranges(DataType::String, 0, &reserved)?;
+  assert_eq!(ranges.len(), N_VALUES);
+
+  let before = counts();
+  let start = Instant::now();
+  let mut grown = Vec::new();
+  for range in ranges {
+    grown.extend_from_slice(&reserved[range]);
+  }
+  let grown_time = start.elapsed();
+  let grown_counts = counts_since(&before);
+
+  assert_eq!(grown, reserved);
+  println!("{} values encoded to {} bytes", N_VALUES, reserved.len());
+  println!(
+    "encode, reserved: {} allocations, {} reallocations copying {} bytes in {:?}",
+    reserved_counts.allocs,
+    reserved_counts.reallocs,
+    reserved_counts.realloc_bytes,
+    reserved_time,
+  );
+  println!(
+    "output grown from empty: {} allocations, {} reallocations copying {} bytes in {:?}",
+    grown_counts.allocs,
+    grown_counts.reallocs,
+    grown_counts.realloc_bytes,
+    grown_time,
+  );
+  Ok(())
+}
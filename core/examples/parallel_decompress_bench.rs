@@ -0,0 +1,43 @@
+//! Compares decompressing a large multi-chunk q_compress column on one
+//! thread against decompressing its chunks in parallel.
+//!
+//! Run with `cargo run --release --example parallel_decompress_bench`.
+
+use std::time::Instant;
+
+use pancake_db_core::compression::q_codec;
+use pancake_db_core::errors::CoreResult;
+use q_compress::{Compressor, Decompressor};
+
+const N_NUMS: usize = 10_000_000;
+const N_ITERS: usize = 5;
+
+fn main() -> CoreResult<()> {
+  let mut state = 1_u64;
+  let nums = (0..N_NUMS)
+    .map(|_| {
+      state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      (state >> 40) as i64
+    })
+    .collect::<Vec<_>>();
+  let bytes = Compressor::<i64>::default().simple_compress(&nums);
+  println!("{} numbers compressed to {} bytes", N_NUMS, bytes.len());
+
+  let start = Instant::now();
+  for _ in 0..N_ITERS {
+    let decompressed = Decompressor::<i64>::default().simple_decompress(&bytes)?;
+    assert_eq!(decompressed.len(), N_NUMS);
+  }
+  let sequential_time = start.elapsed() / N_ITERS as u32;
+
+  let start = Instant::now();
+  for _ in 0..N_ITERS {
+    let decompressed = q_codec::decompress_parallel::<i64>(&bytes)?;
+    assert_eq!(decompressed.len(), N_NUMS);
+  }
+  let parallel_time = start.elapsed() / N_ITERS as u32;
+
+  println!("sequential: {:?} per decompress", sequential_time);
+  println!("parallel:   {:?} per decompress", parallel_time);
+  Ok(())
+}
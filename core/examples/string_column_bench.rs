@@ -0,0 +1,54 @@
+//! Compares decoding a large string column into owned field values against
+//! decoding it into a borrowing `StringColumn`.
+//!
+//! Run with `cargo run --release --example string_column_bench`.
+
+use std::time::Instant;
+
+use pancake_db_core::compression::{self, StringColumn, ZSTD};
+use pancake_db_core::errors::CoreResult;
+use pancake_db_idl::dml::FieldValue;
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dtype::DataType;
+
+const N_VALUES: usize = 1_000_000;
+const N_ITERS: usize = 5;
+
+fn main() -> CoreResult<()> {
+  let fvs = (0..N_VALUES)
+    .map(|i| FieldValue {
+      value: if i % 17 == 0 {
+        None
+      } else {
+        Some(Value::StringVal(format!("user-{}-{}", i % 1000, i)))
+      },
+    })
+    .collect::<Vec<_>>();
+  let codec = compression::new_codec(DataType::String, ZSTD)?;
+  let bytes = codec.compress(&fvs, 0)?;
+  println!("{} values compressed to {} bytes", N_VALUES, bytes.len());
+
+  let start = Instant::now();
+  let mut owned_total_len = 0;
+  for _ in 0..N_ITERS {
+    for fv in codec.decompress(&bytes, 0)? {
+      if let Some(Value::StringVal(s)) = fv.value {
+        owned_total_len += s.len();
+      }
+    }
+  }
+  let owned_time = start.elapsed() / N_ITERS as u32;
+
+  let start = Instant::now();
+  let mut borrowed_total_len = 0;
+  for _ in 0..N_ITERS {
+    let column = StringColumn::decompress(ZSTD, &bytes)?;
+    borrowed_total_len += column.iter().flatten().map(str::len).sum::<usize>();
+  }
+  let borrowed_time = start.elapsed() / N_ITERS as u32;
+
+  assert_eq!(owned_total_len, borrowed_total_len);
+  println!("owned:    {:?} per decode", owned_time);
+  println!("borrowed: {:?} per decode", borrowed_time);
+  Ok(())
+}
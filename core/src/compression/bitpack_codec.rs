@@ -0,0 +1,151 @@
+use crate::errors::{CoreError, CoreResult};
+use crate::utils;
+
+use super::Codec;
+
+/// Packs bools into a bitmap, 8 per byte.
+///
+/// The bitmap is preceded by the bool count as a big-endian u32.
+/// For dense boolean data, including the flattened atoms of boolean list
+/// columns, this is smaller than q_compress since there is no header or
+/// prefix metadata to store.
+#[derive(Clone, Debug, Default)]
+pub struct BoolBitpackCodec {}
+
+impl Codec for BoolBitpackCodec {
+  type P = bool;
+
+  fn compress_atoms(&self, atoms: &[bool]) -> CoreResult<Vec<u8>> {
+    let mut res = Vec::with_capacity(4 + atoms.len().div_ceil(8));
+    res.extend((atoms.len() as u32).to_be_bytes());
+    for chunk in atoms.chunks(8) {
+      let mut byte = 0_u8;
+      for (i, &b) in chunk.iter().enumerate() {
+        byte |= (b as u8) << i;
+      }
+      res.push(byte);
+    }
+    Ok(res)
+  }
+
+  fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<bool>> {
+    if bytes.len() < 4 {
+      return Err(CoreError::truncated("bitpacked bools missing count"));
+    }
+    let n = u32::from_be_bytes(utils::try_byte_array::<4>(&bytes[..4])?) as usize;
+    let bitmap = &bytes[4..];
+    if bitmap.len() != n.div_ceil(8) {
+      return Err(CoreError::corrupt(&format!(
+        "expected {} bytes of bitpacked bools but found {}",
+        n.div_ceil(8),
+        bitmap.len(),
+      )));
+    }
+
+    Ok((0..n)
+      .map(|i| (bitmap[i / 8] >> (i % 8)) & 1 == 1)
+      .collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression::{self, BITPACK, Q_COMPRESS};
+  use crate::encoding;
+
+  use super::*;
+
+  fn bool_list_fvs(n: usize) -> Vec<FieldValue> {
+    // deterministic pseudorandom bits
+    let mut state = 12345_u64;
+    let mut next_bit = || {
+      state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      (state >> 33) & 1 == 1
+    };
+    (0..n)
+      .map(|i| {
+        if i % 11 == 0 {
+          return FieldValue::default();
+        }
+        let vals = (0..(i % 17))
+          .map(|_| FieldValue {
+            value: Some(Value::BoolVal(next_bit())),
+          })
+          .collect();
+        FieldValue {
+          value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+        }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_atoms_round_trip() -> CoreResult<()> {
+    let codec = BoolBitpackCodec::default();
+    for n in [0_usize, 1, 7, 8, 9, 100] {
+      let atoms = (0..n).map(|i| i % 3 == 0).collect::<Vec<_>>();
+      let bytes = codec.compress_atoms(&atoms)?;
+      assert_eq!(bytes.len(), 4 + n.div_ceil(8));
+      assert_eq!(codec.decompress_atoms(&bytes)?, atoms);
+    }
+    assert!(codec.decompress_atoms(&[0, 0, 0, 9, 1]).is_err());
+    assert!(codec.decompress_atoms(&[0, 0]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_bool_list_column() -> CoreResult<()> {
+    let fvs = bool_list_fvs(2000);
+    let bitpack = compression::new_codec(DataType::Bool, BITPACK)?;
+    let bytes = bitpack.compress(&fvs, 1)?;
+    assert_eq!(bitpack.decompress(&bytes, 1)?, fvs);
+
+    let default_bytes = compression::new_codec(DataType::Bool, Q_COMPRESS)?
+      .compress(&fvs, 1)?;
+    assert!(
+      bytes.len() < default_bytes.len(),
+      "bitpack {} vs q_compress {}",
+      bytes.len(),
+      default_bytes.len(),
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_bool_list_states() -> CoreResult<()> {
+    let bool_fv = |b: bool| FieldValue { value: Some(Value::BoolVal(b)) };
+    let bool_list = |vals: Vec<FieldValue>| FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+    };
+    let fvs = vec![
+      bool_list(vec![bool_fv(true), bool_fv(false)]),
+      FieldValue::default(),
+      bool_list(vec![]),
+      bool_list(vec![bool_fv(false)]),
+      bool_list(vec![bool_fv(true)]),
+    ];
+    // lists can't hold null elements, so they must be rejected rather than
+    // read back as false or dropped
+    let null_element = vec![
+      bool_list(vec![bool_fv(true), FieldValue::default(), bool_fv(false)]),
+      FieldValue::default(),
+    ];
+
+    for codec_name in [Q_COMPRESS, BITPACK] {
+      let codec = compression::new_codec(DataType::Bool, codec_name)?;
+      let bytes = codec.compress(&fvs, 1)?;
+      assert_eq!(codec.decompress(&bytes, 1)?, fvs);
+      assert!(codec.compress(&null_element, 1).is_err());
+    }
+
+    let encoded = encoding::new_encoder(DataType::Bool, 1).encode(&fvs)?;
+    let decoder = encoding::new_field_value_decoder(DataType::Bool, 1);
+    assert_eq!(decoder.decode(&encoded)?, fvs);
+    assert!(encoding::new_encoder(DataType::Bool, 1).encode(&null_element).is_err());
+    Ok(())
+  }
+}
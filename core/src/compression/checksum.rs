@@ -0,0 +1,154 @@
+use crate::errors::{CoreError, CoreResult};
+
+// Checksummed data is this magic, the wrapped compressed data, and a
+// big-endian CRC32C of the wrapped data.
+// Like the stats header magic, it can't be confused with the start of
+// q_compress data.
+const CHECKSUM_MAGIC: [u8; 4] = *b"pck!";
+const CRC_SIZE: usize = 4;
+
+// reflected Castagnoli polynomial
+const CRC32C_POLY: u32 = 0x82f63b78;
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+  let mut table = [0_u32; 256];
+  let mut i = 0;
+  while i < 256 {
+    let mut crc = i as u32;
+    let mut bit = 0;
+    while bit < 8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ CRC32C_POLY } else { crc >> 1 };
+      bit += 1;
+    }
+    table[i] = crc;
+    i += 1;
+  }
+  table
+}
+
+/// Computes the CRC32C (Castagnoli) checksum of the bytes.
+pub fn crc32c(bytes: &[u8]) -> u32 {
+  let mut crc = !0_u32;
+  for &b in bytes {
+    crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+  }
+  !crc
+}
+
+/// Wraps compressed column data with a CRC32C checksum.
+///
+/// Codecs verify the checksum whenever they read the data, failing with a
+/// corrupt error if any byte has changed.
+/// Data without a checksum still reads as before.
+pub fn with_checksum(compressed: &[u8]) -> Vec<u8> {
+  let mut res = Vec::with_capacity(CHECKSUM_MAGIC.len() + compressed.len() + CRC_SIZE);
+  res.extend(CHECKSUM_MAGIC);
+  res.extend(compressed);
+  res.extend(crc32c(compressed).to_be_bytes());
+  res
+}
+
+// Returns the data wrapped by a checksum after verifying it, or the bytes
+// unchanged if they have no checksum.
+pub(crate) fn verify_checksum(bytes: &[u8]) -> CoreResult<&[u8]> {
+  if !bytes.starts_with(&CHECKSUM_MAGIC) {
+    return Ok(bytes);
+  }
+  let wrapped = strip_checksum(bytes)?;
+  let crc_bytes = &bytes[bytes.len() - CRC_SIZE..];
+  let mut expected = [0_u8; CRC_SIZE];
+  expected.copy_from_slice(crc_bytes);
+  let expected = u32::from_be_bytes(expected);
+  let actual = crc32c(wrapped);
+  if actual != expected {
+    return Err(CoreError::corrupt(&format!(
+      "checksum mismatch: expected {:08x} but data has {:08x}",
+      expected,
+      actual,
+    )));
+  }
+  Ok(wrapped)
+}
+
+// Returns the data wrapped by a checksum without verifying it, or the bytes
+// unchanged if they have no checksum.
+pub(crate) fn strip_checksum(bytes: &[u8]) -> CoreResult<&[u8]> {
+  if !bytes.starts_with(&CHECKSUM_MAGIC) {
+    return Ok(bytes);
+  }
+  if bytes.len() < CHECKSUM_MAGIC.len() + CRC_SIZE {
+    return Err(CoreError::corrupt("checksummed data is too short to hold its checksum"));
+  }
+  Ok(&bytes[CHECKSUM_MAGIC.len()..bytes.len() - CRC_SIZE])
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::FieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression::{self, Q_COMPRESS, ZSTD};
+  use crate::errors::CoreErrorKind;
+
+  use super::*;
+
+  fn string_fvs() -> Vec<FieldValue> {
+    vec![
+      FieldValue { value: Some(Value::StringVal("abc".to_string())) },
+      FieldValue::default(),
+      FieldValue { value: Some(Value::StringVal("".to_string())) },
+    ]
+  }
+
+  #[test]
+  fn test_crc32c() {
+    // standard check values
+    assert_eq!(crc32c(b""), 0);
+    assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    assert_eq!(crc32c(&[0; 32]), 0x8a9136aa);
+  }
+
+  #[test]
+  fn test_flipped_byte_detected() -> CoreResult<()> {
+    let codec = compression::new_codec(DataType::String, ZSTD)?;
+    let bytes = with_checksum(&codec.compress(&string_fvs(), 0)?);
+    assert_eq!(codec.decompress(&bytes, 0)?, string_fvs());
+
+    for i in CHECKSUM_MAGIC.len()..bytes.len() {
+      let mut corrupted = bytes.clone();
+      corrupted[i] ^= 0x10;
+      let err = codec.decompress(&corrupted, 0).unwrap_err();
+      assert_eq!(err.kind, CoreErrorKind::Corrupt, "byte {}", i);
+    }
+    assert!(codec.decompress(&bytes[..6], 0).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_without_checksum() -> CoreResult<()> {
+    let codec = compression::new_codec(DataType::String, ZSTD)?;
+    let bytes = codec.compress(&string_fvs(), 0)?;
+    assert_eq!(verify_checksum(&bytes)?, &bytes[..]);
+    assert_eq!(codec.decompress(&bytes, 0)?, string_fvs());
+    Ok(())
+  }
+
+  #[test]
+  fn test_checksum_with_stats() -> CoreResult<()> {
+    let fvs = vec![FieldValue { value: Some(Value::Int64Val(-4)) }];
+    let codec = compression::new_codec(DataType::Int64, Q_COMPRESS)?;
+    let bytes = with_checksum(&codec.compress_with_stats(&fvs, 0)?);
+    assert_eq!(codec.decompress(&bytes, 0)?, fvs);
+    assert_eq!(codec.read_stats_only(&bytes)?.unwrap().row_count, 1);
+
+    // reading stats skips verifying the whole column
+    let mut corrupted = bytes.clone();
+    let last_body_idx = corrupted.len() - 5;
+    corrupted[last_body_idx] ^= 1;
+    assert!(codec.decompress(&corrupted, 0).is_err());
+    assert_eq!(codec.read_stats_only(&corrupted)?.unwrap().row_count, 1);
+    Ok(())
+  }
+}
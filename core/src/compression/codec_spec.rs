@@ -0,0 +1,73 @@
+use crate::errors::{CoreError, CoreResult};
+
+/// A codec string split into the codec's name and its parameters.
+///
+/// Codec strings are either a bare name like `zstd`, or a name followed by
+/// a colon and comma-separated `key=value` parameters, like
+/// `zstd:level=3`.
+/// Parameters configure a codec beyond what its name alone determines;
+/// which ones each codec accepts is up to the codec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodecSpec<'a> {
+  pub name: &'a str,
+  pub params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> CodecSpec<'a> {
+  /// Parses a codec string, returning an error if it is malformed: an
+  /// empty name, a colon with no parameters after it, a parameter without
+  /// `=` or with an empty key or value, or a repeated key.
+  pub fn parse(codec: &'a str) -> CoreResult<Self> {
+    let (name, params_str) = match codec.split_once(':') {
+      Some((name, params_str)) => (name, Some(params_str)),
+      None => (codec, None),
+    };
+    if name.is_empty() {
+      return Err(CoreError::invalid(&format!(
+        "codec string {:?} has an empty name",
+        codec,
+      )));
+    }
+
+    let mut params: Vec<(&str, &str)> = Vec::new();
+    for param in params_str.into_iter().flat_map(|s| s.split(',')) {
+      let (key, value) = match param.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => (key, value),
+        _ => return Err(CoreError::invalid(&format!(
+          "codec string {:?} has malformed parameter {:?}; expected key=value",
+          codec,
+          param,
+        ))),
+      };
+      if params.iter().any(|&(k, _)| k == key) {
+        return Err(CoreError::invalid(&format!(
+          "codec string {:?} repeats parameter {}",
+          codec,
+          key,
+        )));
+      }
+      params.push((key, value));
+    }
+
+    Ok(CodecSpec { name, params })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() -> CoreResult<()> {
+    assert_eq!(CodecSpec::parse("zstd")?, CodecSpec { name: "zstd", params: vec![] });
+    assert_eq!(
+      CodecSpec::parse("zstd:level=3,window=20")?,
+      CodecSpec { name: "zstd", params: vec![("level", "3"), ("window", "20")] },
+    );
+
+    for malformed in ["", ":level=3", "zstd:", "zstd:level", "zstd:=3", "zstd:level=", "zstd:level=3,", "zstd:level=3,level=4"] {
+      assert!(CodecSpec::parse(malformed).is_err(), "{}", malformed);
+    }
+    Ok(())
+  }
+}
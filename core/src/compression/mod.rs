@@ -1,10 +1,28 @@
+pub use checksum::{crc32c, with_checksum};
+pub use codec_spec::CodecSpec;
+pub use stats_header::ColumnStats;
+pub use string_column::StringColumn;
+pub use timestamp_column::TimestampColumn;
 pub use traits::{Codec, ValueCodec};
-pub use utils::{choose_codec, new_codec};
+pub use utils::{choose_codec, choose_codec_for_values, compress_columns, decompress_shape, detect_codec, new_codec, supports};
 
+mod checksum;
+mod codec_spec;
+mod stats_header;
+mod string_column;
+mod timestamp_column;
 mod traits;
 mod utils;
+pub mod bitpack_codec;
 pub mod q_codec;
+pub mod raw_codec;
+pub mod sparse_codec;
 pub mod zstd_codec;
 
 pub const Q_COMPRESS: &str = "q_compress";
+pub const BITPACK: &str = "bitpack";
+pub const DELTA: &str = "delta";
 pub const ZSTD: &str = "zstd";
+pub const RAW_BE: &str = "raw_be";
+pub const RAW_LE: &str = "raw_le";
+pub const SPARSE: &str = "sparse";
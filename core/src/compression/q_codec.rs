@@ -1,18 +1,32 @@
+use std::marker::PhantomData;
+use std::ops::Range;
+
 use q_compress::Compressor as RawQCompressor;
 use q_compress::Decompressor as RawQDecompressor;
-use q_compress::CompressorConfig;
+use q_compress::{BitReader, BitWords, ChunkMetadata, CompressorConfig, Flags};
 use q_compress::data_types::{NumberLike, TimestampMicros};
 
 use crate::compression::Codec;
-use crate::errors::CoreResult;
+use crate::errors::{CoreError, CoreResult};
 use crate::primitives::Primitive;
 
 const Q_COMPRESSION_LEVEL: usize = 7;
+/// Compressed data smaller than this is always decompressed on the calling
+/// thread, since spawning threads would cost more than it saves.
+pub const PARALLEL_DECOMPRESS_MIN_BYTES: usize = 1 << 20;
 
 pub trait QCodec {
   type T: Primitive + NumberLike;
 }
 
+pub(crate) fn q_compress<T: NumberLike>(nums: &[T], delta_encoding_order: usize) -> Vec<u8> {
+  let compressor = RawQCompressor::<T>::from_config(CompressorConfig {
+    compression_level: Q_COMPRESSION_LEVEL,
+    delta_encoding_order,
+  });
+  compressor.simple_compress(nums)
+}
+
 macro_rules! qcompressor {
   ($struct_name:ident, $primitive_type:ty) => {
     #[derive(Clone, Debug)]
@@ -22,23 +36,435 @@ macro_rules! qcompressor {
       type P = $primitive_type;
 
       fn compress_atoms(&self, primitives: &[$primitive_type]) -> CoreResult<Vec<u8>> {
-        let compressor = RawQCompressor::<$primitive_type>::from_config(CompressorConfig {
-          compression_level: Q_COMPRESSION_LEVEL,
-          ..Default::default()
-        });
-        Ok(compressor.simple_compress(primitives))
+        Ok(q_compress(primitives, 0))
       }
 
       fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<$primitive_type>> {
-        let decompressor = RawQDecompressor::<$primitive_type>::default();
-        Ok(decompressor.simple_decompress(bytes)?)
+        Ok(q_decompress(bytes)?.0)
       }
     }
   }
 }
 
+struct ChunkLocation<T: NumberLike> {
+  metadata: ChunkMetadata<T>,
+  body_start: usize,
+}
+
+// Decompresses q_compress data, returning its numbers and the byte index
+// just past it.
+// Large data with several chunks has its chunks decompressed in parallel.
+pub(crate) fn q_decompress<T>(bytes: &[u8]) -> CoreResult<(Vec<T>, usize)> where
+  T: NumberLike + Send + Sync,
+  T::Signed: Sync,
+{
+  let parallel = bytes.len() >= PARALLEL_DECOMPRESS_MIN_BYTES;
+  q_decompress_chunks(bytes, parallel)
+}
+
+/// Decompresses q_compress data, decompressing its chunks in parallel.
+///
+/// Chunks are located by their metadata first, then split between up to
+/// one thread per available core; the numbers are returned in order.
+/// Codecs do this automatically for data of at least
+/// [`PARALLEL_DECOMPRESS_MIN_BYTES`].
+pub fn decompress_parallel<T>(bytes: &[u8]) -> CoreResult<Vec<T>> where
+  T: NumberLike + Send + Sync,
+  T::Signed: Sync,
+{
+  Ok(q_decompress_chunks(bytes, true)?.0)
+}
+
+fn q_decompress_chunks<T>(bytes: &[u8], parallel: bool) -> CoreResult<(Vec<T>, usize)> where
+  T: NumberLike + Send + Sync,
+  T::Signed: Sync,
+{
+  let decompressor = RawQDecompressor::<T>::default();
+  let words = BitWords::from(bytes);
+  let mut reader = BitReader::from(&words);
+  let flags = decompressor.header(&mut reader)?;
+  if !parallel {
+    let mut nums = Vec::new();
+    while let Some(chunk) = decompressor.chunk(&mut reader, &flags)? {
+      nums.extend(chunk.nums);
+    }
+    return Ok((nums, reader.aligned_byte_idx()?));
+  }
+
+  let mut locations = Vec::new();
+  while let Some(metadata) = decompressor.chunk_metadata(&mut reader, &flags)? {
+    let body_start = reader.aligned_byte_idx()?;
+    if body_start + metadata.compressed_body_size > bytes.len() {
+      return Err(CoreError::corrupt("q_compress chunk body extends past end of data"));
+    }
+    reader.seek(8 * metadata.compressed_body_size);
+    locations.push(ChunkLocation { metadata, body_start });
+  }
+  let end = reader.aligned_byte_idx()?;
+
+  let n_threads = std::thread::available_parallelism()
+    .map(|n| n.get())
+    .unwrap_or(1)
+    .clamp(1, locations.len().max(1));
+  let group_size = locations.len().div_ceil(n_threads).max(1);
+  let flags = &flags;
+  let group_results = std::thread::scope(|scope| {
+    let handles = locations.chunks(group_size)
+      .map(|group| scope.spawn(move || decompress_chunk_bodies(bytes, flags, group)))
+      .collect::<Vec<_>>();
+    handles.into_iter()
+      .map(|handle| handle.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload)))
+      .collect::<Vec<_>>()
+  });
+
+  let mut nums = Vec::with_capacity(locations.iter().map(|loc| loc.metadata.n).sum());
+  for group_nums in group_results {
+    nums.extend(group_nums?);
+  }
+  Ok((nums, end))
+}
+
+fn decompress_chunk_bodies<T: NumberLike>(
+  bytes: &[u8],
+  flags: &Flags,
+  locations: &[ChunkLocation<T>],
+) -> CoreResult<Vec<T>> {
+  let decompressor = RawQDecompressor::<T>::default();
+  let mut nums = Vec::new();
+  for ChunkLocation { metadata, body_start } in locations {
+    let body = &bytes[*body_start..body_start + metadata.compressed_body_size];
+    let words = BitWords::from(body);
+    let mut reader = BitReader::from(&words);
+    nums.extend(decompressor.chunk_body(&mut reader, flags, metadata)?);
+  }
+  Ok(nums)
+}
+
+/// A chunk of q_compress data that [`decompress_lenient`] skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedChunk {
+  /// The indices the chunk's numbers would have had among all the data's
+  /// numbers.
+  pub range: Range<usize>,
+  pub error: CoreError,
+}
+
+/// What [`decompress_lenient`] recovered from q_compress data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LenientNums<T> {
+  /// The numbers of every chunk that decompressed, in order.
+  pub nums: Vec<T>,
+  /// The chunks whose bodies were corrupt, in order.
+  pub skipped: Vec<SkippedChunk>,
+  /// The error that ended decompression before the data's footer, if any.
+  /// Nothing after it could be recovered.
+  pub stopped_by: Option<CoreError>,
+}
+
+/// Decompresses as much of partially corrupt q_compress data as possible,
+/// for forensic recovery.
+///
+/// Each chunk's metadata records how many numbers it holds and how long its
+/// body is, so a chunk whose body is corrupt can be skipped and the next
+/// chunk still found.
+/// A body counts as corrupt if it fails to decompress or its numbers don't
+/// end exactly at its recorded length; corruption that still decodes
+/// cleanly can't be detected.
+/// If a chunk's metadata is corrupt, the chunks after it can't be found, so
+/// decompression stops there.
+///
+/// Returns an error only if the data's header is corrupt.
+pub fn decompress_lenient<T: NumberLike>(bytes: &[u8]) -> CoreResult<LenientNums<T>> {
+  let decompressor = RawQDecompressor::<T>::default();
+  let words = BitWords::from(bytes);
+  let mut reader = BitReader::from(&words);
+  let flags = decompressor.header(&mut reader)?;
+
+  let mut res = LenientNums {
+    nums: Vec::new(),
+    skipped: Vec::new(),
+    stopped_by: None,
+  };
+  let mut n_seen = 0;
+  loop {
+    let location = decompressor.chunk_metadata(&mut reader, &flags)
+      .and_then(|maybe_metadata| match maybe_metadata {
+        Some(metadata) => Ok(Some((metadata, reader.aligned_byte_idx()?))),
+        None => Ok(None),
+      });
+    let (metadata, body_start) = match location {
+      Ok(Some(location)) => location,
+      Ok(None) => break,
+      Err(err) => {
+        res.stopped_by = Some(err.into());
+        break;
+      },
+    };
+    let body_end = body_start + metadata.compressed_body_size;
+    if body_end > bytes.len() {
+      res.stopped_by = Some(CoreError::corrupt("q_compress chunk body extends past end of data"));
+      break;
+    }
+
+    let range = n_seen..n_seen + metadata.n;
+    match decompress_exact_chunk_body(&bytes[body_start..body_end], &flags, &metadata) {
+      Ok(nums) => res.nums.extend(nums),
+      Err(error) => res.skipped.push(SkippedChunk { range, error }),
+    }
+    n_seen += metadata.n;
+    reader.seek(8 * metadata.compressed_body_size);
+  }
+  Ok(res)
+}
+
+fn decompress_exact_chunk_body<T: NumberLike>(
+  body: &[u8],
+  flags: &Flags,
+  metadata: &ChunkMetadata<T>,
+) -> CoreResult<Vec<T>> {
+  let words = BitWords::from(body);
+  let mut reader = BitReader::from(&words);
+  let nums = RawQDecompressor::<T>::default().chunk_body(&mut reader, flags, metadata)?;
+  if reader.aligned_byte_idx()? != body.len() {
+    return Err(CoreError::corrupt("q_compress chunk body does not end at its recorded length"));
+  }
+  Ok(nums)
+}
+
 qcompressor!(I64QCodec, i64);
 qcompressor!(BoolQCodec, bool);
 qcompressor!(F32QCodec, f32);
 qcompressor!(F64QCodec, f64);
 qcompressor!(TimestampMicrosQCodec, TimestampMicros);
+
+// The delta encoding order is recorded in the q_compress header, so these
+// decompress exactly like the plain codecs above.
+macro_rules! delta_qcompressor {
+  ($struct_name:ident, $primitive_type:ty) => {
+    #[derive(Clone, Debug)]
+    pub struct $struct_name {}
+
+    impl Codec for $struct_name {
+      type P = $primitive_type;
+
+      fn compress_atoms(&self, primitives: &[$primitive_type]) -> CoreResult<Vec<u8>> {
+        // deltas only pay off for sorted or smooth data, so we keep whichever
+        // output is smaller
+        let deltas = q_compress(primitives, 1);
+        let plain = q_compress(primitives, 0);
+        if deltas.len() < plain.len() {
+          Ok(deltas)
+        } else {
+          Ok(plain)
+        }
+      }
+
+      fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<$primitive_type>> {
+        Ok(q_decompress(bytes)?.0)
+      }
+    }
+  }
+}
+
+delta_qcompressor!(I64DeltaQCodec, i64);
+delta_qcompressor!(TimestampMicrosDeltaQCodec, TimestampMicros);
+
+/// Compresses `i128` or `u128` atoms with q_compress.
+///
+/// No data type holds 128-bit integers yet, so this works on atoms directly
+/// rather than as a [`Codec`] of a primitive.
+/// Wider logical types like decimals and large IDs can build their codecs
+/// on it.
+#[derive(Clone, Debug, Default)]
+pub struct WideIntQCodec<T> {
+  _phantom: PhantomData<T>,
+}
+
+impl<T> WideIntQCodec<T> where
+  T: NumberLike + Send + Sync,
+  T::Signed: Sync,
+{
+  pub fn compress_atoms(&self, atoms: &[T]) -> CoreResult<Vec<u8>> {
+    Ok(q_compress(atoms, 0))
+  }
+
+  pub fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<T>> {
+    Ok(q_decompress(bytes)?.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pseudorandom_ints(n: usize) -> Vec<i64> {
+    let mut state = 1_u64;
+    (0..n)
+      .map(|_| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as i64
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_delta_monotonic() -> CoreResult<()> {
+    let nums = (0..10000_i64).map(|i| 1_000_000 + 3 * i + i % 2).collect::<Vec<_>>();
+    let plain = I64QCodec {}.compress_atoms(&nums)?;
+    let deltas = I64DeltaQCodec {}.compress_atoms(&nums)?;
+    assert!(deltas.len() < plain.len());
+    assert_eq!(I64DeltaQCodec {}.decompress_atoms(&deltas)?, nums);
+    Ok(())
+  }
+
+  #[test]
+  fn test_delta_random() -> CoreResult<()> {
+    let nums = pseudorandom_ints(10000);
+    let plain = I64QCodec {}.compress_atoms(&nums)?;
+    let deltas = I64DeltaQCodec {}.compress_atoms(&nums)?;
+    // deltas of random data are larger, so the codec falls back to the
+    // plain encoding
+    assert!(q_compress(&nums, 1).len() > plain.len());
+    assert_eq!(deltas, plain);
+    assert_eq!(I64DeltaQCodec {}.decompress_atoms(&deltas)?, nums);
+    Ok(())
+  }
+
+  #[test]
+  fn test_wide_int_boundaries() -> CoreResult<()> {
+    let signed = vec![i128::MIN, -1, 0, 1, i128::MAX, i128::MIN, 1 << 100];
+    let codec = WideIntQCodec::<i128>::default();
+    assert_eq!(codec.decompress_atoms(&codec.compress_atoms(&signed)?)?, signed);
+
+    let unsigned = vec![u128::MIN, 1, u128::MAX, u128::MAX - 1, 1 << 127];
+    let codec = WideIntQCodec::<u128>::default();
+    assert_eq!(codec.decompress_atoms(&codec.compress_atoms(&unsigned)?)?, unsigned);
+    assert!(codec.decompress_atoms(&codec.compress_atoms(&[])?)?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_delta_timestamps() -> CoreResult<()> {
+    let timestamps = (0..1000_i64)
+      .map(|i| TimestampMicros::from_secs_and_nanos(1_600_000_000 + i, 0))
+      .collect::<Vec<_>>();
+    let codec = TimestampMicrosDeltaQCodec {};
+    let bytes = codec.compress_atoms(&timestamps)?;
+    assert_eq!(codec.decompress_atoms(&bytes)?, timestamps);
+    Ok(())
+  }
+
+  // simple_compress only starts a new chunk every million numbers, so this
+  // writes small chunks directly
+  fn chunked_compress(nums: &[i64], chunk_size: usize) -> Vec<u8> {
+    let compressor = RawQCompressor::<i64>::default();
+    let mut writer = q_compress::BitWriter::default();
+    compressor.header(&mut writer).unwrap();
+    for chunk in nums.chunks(chunk_size) {
+      compressor.chunk(chunk, &mut writer).unwrap();
+    }
+    compressor.footer(&mut writer).unwrap();
+    writer.bytes()
+  }
+
+  #[test]
+  fn test_parallel_matches_sequential() -> CoreResult<()> {
+    let nums = pseudorandom_ints(10000);
+    for chunk_size in [1, 7, 1000, 10000, 20000] {
+      let bytes = chunked_compress(&nums, chunk_size);
+      assert_eq!(decompress_parallel::<i64>(&bytes)?, nums);
+      assert_eq!(q_decompress_chunks::<i64>(&bytes, false)?, (nums.clone(), bytes.len()));
+    }
+    assert_eq!(decompress_parallel::<i64>(&chunked_compress(&[], 1))?, Vec::<i64>::new());
+    Ok(())
+  }
+
+  #[test]
+  fn test_empty_and_tiny_streams() -> CoreResult<()> {
+    for delta_order in [0, 1, 2] {
+      for nums in [vec![], vec![5_i64], vec![5_i64, -3]] {
+        let bytes = q_compress(&nums, delta_order);
+        assert_eq!(q_decompress::<i64>(&bytes)?, (nums.clone(), bytes.len()));
+        assert_eq!(decompress_parallel::<i64>(&bytes)?, nums);
+        let lenient = decompress_lenient::<i64>(&bytes)?;
+        assert_eq!(lenient.nums, nums);
+        assert!(lenient.skipped.is_empty() && lenient.stopped_by.is_none());
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_parallel_trailing_bytes() -> CoreResult<()> {
+    let nums = pseudorandom_ints(100);
+    let mut bytes = chunked_compress(&nums, 30);
+    let end = bytes.len();
+    bytes.extend([1, 2, 3]);
+    assert_eq!(q_decompress_chunks::<i64>(&bytes, true)?, (nums, end));
+    Ok(())
+  }
+
+  #[test]
+  fn test_parallel_truncated() {
+    let bytes = chunked_compress(&pseudorandom_ints(100), 30);
+    for len in [bytes.len() / 2, bytes.len() - 1] {
+      assert!(decompress_parallel::<i64>(&bytes[..len]).is_err());
+    }
+  }
+
+  // the byte range of each chunk body, found through the chunk metadata
+  fn chunk_body_ranges(bytes: &[u8]) -> Vec<Range<usize>> {
+    let decompressor = RawQDecompressor::<i64>::default();
+    let words = BitWords::from(bytes);
+    let mut reader = BitReader::from(&words);
+    let flags = decompressor.header(&mut reader).unwrap();
+    let mut res = Vec::new();
+    while let Some(metadata) = decompressor.chunk_metadata(&mut reader, &flags).unwrap() {
+      let body_start = reader.aligned_byte_idx().unwrap();
+      res.push(body_start..body_start + metadata.compressed_body_size);
+      reader.seek(8 * metadata.compressed_body_size);
+    }
+    res
+  }
+
+  #[test]
+  fn test_lenient_skips_corrupt_middle_chunk() -> CoreResult<()> {
+    let nums = pseudorandom_ints(300);
+    let mut bytes = chunked_compress(&nums, 100);
+    let lenient = decompress_lenient::<i64>(&bytes)?;
+    assert_eq!(lenient.nums, nums);
+    assert!(lenient.skipped.is_empty());
+    assert_eq!(lenient.stopped_by, None);
+
+    let body_ranges = chunk_body_ranges(&bytes);
+    assert_eq!(body_ranges.len(), 3);
+    for b in &mut bytes[body_ranges[1].clone()] {
+      *b = 0xff;
+    }
+    assert!(q_decompress::<i64>(&bytes).is_err());
+
+    let lenient = decompress_lenient::<i64>(&bytes)?;
+    assert_eq!(lenient.nums, [&nums[..100], &nums[200..]].concat());
+    assert_eq!(lenient.skipped.len(), 1);
+    assert_eq!(lenient.skipped[0].range, 100..200);
+    assert_eq!(lenient.stopped_by, None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_lenient_stops_at_corrupt_metadata() -> CoreResult<()> {
+    let nums = pseudorandom_ints(300);
+    let mut bytes = chunked_compress(&nums, 100);
+    // the byte just past the first body is the second chunk's magic byte
+    let second_chunk_start = chunk_body_ranges(&bytes)[0].end;
+    bytes[second_chunk_start] ^= 0xff;
+
+    let lenient = decompress_lenient::<i64>(&bytes)?;
+    assert_eq!(lenient.nums, &nums[..100]);
+    assert!(lenient.skipped.is_empty());
+    assert!(lenient.stopped_by.is_some());
+
+    assert!(decompress_lenient::<i64>(&bytes[..2]).is_err());
+    Ok(())
+  }
+}
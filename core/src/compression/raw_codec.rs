@@ -0,0 +1,130 @@
+use std::marker::PhantomData;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::primitives::{Atom, Primitive};
+
+use super::{Codec, RAW_BE, RAW_LE};
+
+/// Byte order used by [`RawCodec`] to store each atom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+  Big,
+  Little,
+}
+
+/// Stores atoms uncompressed at their fixed byte size.
+///
+/// The byte order is part of the codec string (`raw_be` or `raw_le`), so
+/// data written in either order decodes correctly on any host.
+/// Little-endian storage lets little-endian hosts read large columns without
+/// swapping bytes.
+#[derive(Clone, Debug)]
+pub struct RawCodec<P: Primitive> {
+  endianness: Endianness,
+  _phantom: PhantomData<P>,
+}
+
+impl<P: Primitive> RawCodec<P> {
+  pub fn new(endianness: Endianness) -> Self {
+    RawCodec {
+      endianness,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<P: Primitive> Codec for RawCodec<P> {
+  type P = P;
+
+  fn compress_atoms(&self, atoms: &[P::A]) -> CoreResult<Vec<u8>> {
+    let mut res = Vec::with_capacity(atoms.len() * P::A::BYTE_SIZE);
+    for atom in atoms {
+      match self.endianness {
+        Endianness::Big => res.extend(atom.to_bytes()),
+        Endianness::Little => res.extend(atom.to_bytes_le()),
+      }
+    }
+    Ok(res)
+  }
+
+  fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<P::A>> {
+    if bytes.len() % P::A::BYTE_SIZE != 0 {
+      return Err(CoreError::corrupt(&format!(
+        "raw byte count {} is not a multiple of atom size {}",
+        bytes.len(),
+        P::A::BYTE_SIZE,
+      )));
+    }
+
+    bytes.chunks(P::A::BYTE_SIZE)
+      .map(|atom_bytes| match self.endianness {
+        Endianness::Big => P::A::try_from_bytes(atom_bytes),
+        Endianness::Little => P::A::try_from_bytes_le(atom_bytes),
+      })
+      .collect()
+  }
+}
+
+pub fn new_raw_codec<P: Primitive>(codec: &str) -> Option<Box<dyn Codec<P=P>>> {
+  let endianness = if codec == RAW_BE {
+    Endianness::Big
+  } else if codec == RAW_LE {
+    Endianness::Little
+  } else {
+    return None;
+  };
+  Some(Box::new(RawCodec::<P>::new(endianness)))
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::FieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression;
+
+  use super::*;
+
+  #[test]
+  fn test_le_layout() -> CoreResult<()> {
+    let codec = RawCodec::<i64>::new(Endianness::Little);
+    let bytes = codec.compress_atoms(&[1, -2])?;
+    assert_eq!(
+      bytes,
+      vec![1, 0, 0, 0, 0, 0, 0, 0, 254, 255, 255, 255, 255, 255, 255, 255],
+    );
+    assert_eq!(codec.decompress_atoms(&bytes)?, vec![1, -2]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_be_layout() -> CoreResult<()> {
+    let codec = RawCodec::<f32>::new(Endianness::Big);
+    let bytes = codec.compress_atoms(&[1.0])?;
+    assert_eq!(bytes, vec![63, 128, 0, 0]);
+    assert_eq!(codec.decompress_atoms(&bytes)?, vec![1.0]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_value_round_trip() -> CoreResult<()> {
+    let fvs = vec![
+      FieldValue { value: Some(Value::Float64Val(f64::MIN)) },
+      FieldValue::default(),
+      FieldValue { value: Some(Value::Float64Val(-0.5)) },
+    ];
+    for codec in [RAW_BE, RAW_LE] {
+      let value_codec = compression::new_codec(DataType::Float64, codec)?;
+      let bytes = value_codec.compress(&fvs, 0)?;
+      assert_eq!(value_codec.decompress(&bytes, 0)?, fvs);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_truncated() {
+    let codec = RawCodec::<i64>::new(Endianness::Little);
+    assert!(codec.decompress_atoms(&[0, 0, 0]).is_err());
+  }
+}
@@ -0,0 +1,373 @@
+use std::io::Write;
+
+use pancake_db_idl::dml::FieldValue;
+use pancake_db_idl::dtype::DataType;
+use q_compress::data_types::TimestampMicros;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::primitives::Primitive;
+use crate::rep_levels::RepLevelsAndBytes;
+use crate::utils;
+
+use super::{checksum, choose_codec, q_codec, stats_header};
+use super::{Codec, ColumnStats, ValueCodec};
+
+// Sparse data is this magic, the big-endian count of all values, the
+// q_compressed positions of the present values, and then the present values
+// compressed by the inner codec.
+const SPARSE_MAGIC: [u8; 4] = *b"sps!";
+const COUNT_SIZE: usize = 8;
+// the most nulls sparse data may stand for; nulls take no bytes, so a
+// corrupt count can't be checked against the input.
+// Compression rejects columns with more, and `choose_codec_for_values`
+// doesn't pick this codec for them.
+pub(crate) const MAX_NULLS: u64 = 1 << 24;
+
+/// Compresses mostly-null columns by storing only the positions and values
+/// of present entries.
+///
+/// Other codecs store a repetition level for every value, null or not.
+/// q_compress run-length encodes long runs of null levels, so they stay
+/// small, but every level must still be decompressed and walked, which
+/// dominates the time to read a column with a handful of values among
+/// millions of nulls.
+/// This instead stores the present values' positions as q_compressed
+/// deltas, and the present values themselves with the data type's default
+/// codec.
+/// On such columns it is about the same size as the default codec but
+/// several times faster to compress and decompress.
+/// It loses to the default codec once many values are present; see
+/// [`choose_codec_for_values`][super::choose_codec_for_values].
+///
+/// A column may have at most 2^24 nulls; compressing more fails.
+pub struct SparseCodec<P: Primitive> {
+  inner: Box<dyn Codec<P=P>>,
+  max_nulls: u64,
+}
+
+impl<P: Primitive> SparseCodec<P> {
+  /// Creates a sparse codec that compresses the present values with
+  /// `inner`.
+  pub fn new(inner: Box<dyn Codec<P=P>>) -> Self {
+    SparseCodec { inner, max_nulls: MAX_NULLS }
+  }
+}
+
+struct SparseParts<'a> {
+  count: usize,
+  positions: Vec<u64>,
+  inner_bytes: &'a [u8],
+}
+
+fn split_sparse(bytes: &[u8], max_nulls: u64) -> CoreResult<SparseParts<'_>> {
+  let bytes = checksum::verify_checksum(bytes)?;
+  let (_, bytes) = stats_header::split_header(bytes)?;
+  let bytes = bytes.strip_prefix(&SPARSE_MAGIC)
+    .ok_or_else(|| CoreError::corrupt("sparse data is missing its magic"))?;
+  let count_bytes = bytes.get(..COUNT_SIZE)
+    .ok_or_else(|| CoreError::truncated("sparse data ended before its value count"))?;
+  let count = u64::from_be_bytes(utils::try_byte_array::<COUNT_SIZE>(count_bytes)?);
+  let (positions, byte_idx) = q_codec::q_decompress::<u64>(&bytes[COUNT_SIZE..])?;
+  if count.saturating_sub(positions.len() as u64) > max_nulls {
+    return Err(CoreError::corrupt(&format!(
+      "sparse data claims {} values but only {} are present",
+      count,
+      positions.len(),
+    )));
+  }
+  let count = count as usize;
+
+  let in_order = positions.windows(2).all(|w| w[0] < w[1]);
+  if !in_order || positions.last().is_some_and(|&pos| pos as usize >= count) {
+    return Err(CoreError::corrupt(&format!(
+      "sparse positions are not increasing positions among {} values",
+      count,
+    )));
+  }
+  Ok(SparseParts {
+    count,
+    positions,
+    inner_bytes: &bytes[COUNT_SIZE + byte_idx..],
+  })
+}
+
+pub(crate) fn is_sparse(bytes: &[u8]) -> bool {
+  checksum::verify_checksum(bytes)
+    .and_then(stats_header::split_header)
+    .map(|(_, bytes)| bytes.starts_with(&SPARSE_MAGIC))
+    .unwrap_or(false)
+}
+
+impl<P: Primitive> ValueCodec for SparseCodec<P> {
+  fn dtype(&self) -> DataType {
+    P::DTYPE
+  }
+
+  fn compress(&self, values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>> {
+    let mut res = Vec::new();
+    self.compress_to_writer(values, nested_list_depth, &mut res)?;
+    Ok(res)
+  }
+
+  fn compress_to_writer(
+    &self,
+    values: &[FieldValue],
+    nested_list_depth: u8,
+    writer: &mut dyn Write,
+  ) -> CoreResult<()> {
+    let mut positions = Vec::new();
+    let mut present = Vec::new();
+    for (i, fv) in values.iter().enumerate() {
+      if fv.value.is_some() {
+        positions.push(i as u64);
+        present.push(fv.clone());
+      }
+    }
+    let n_nulls = (values.len() - present.len()) as u64;
+    if n_nulls > self.max_nulls {
+      return Err(CoreError::invalid(&format!(
+        "sparse data can hold at most {} nulls, but the column has {}",
+        self.max_nulls,
+        n_nulls,
+      )));
+    }
+
+    writer.write_all(&SPARSE_MAGIC)?;
+    writer.write_all(&(values.len() as u64).to_be_bytes())?;
+    writer.write_all(&q_codec::q_compress(&positions, 1))?;
+    self.inner.compress_to_writer(&present, nested_list_depth, writer)
+  }
+
+  fn compress_with_stats(&self, values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>> {
+    let stats = stats_header::column_stats::<P>(values)?;
+    let mut res = Vec::new();
+    stats_header::write_header::<P>(&stats, &mut res)?;
+    res.extend(self.compress(values, nested_list_depth)?);
+    Ok(res)
+  }
+
+  fn read_stats_only(&self, bytes: &[u8]) -> CoreResult<Option<ColumnStats>> {
+    let bytes = checksum::strip_checksum(bytes)?;
+    match stats_header::split_header(bytes)?.0 {
+      Some(body) => Ok(Some(stats_header::parse_body::<P>(body)?)),
+      None => Ok(None),
+    }
+  }
+
+  // Every present value's levels end with the first level 1, so the full
+  // levels are the present values' levels with a null level at each gap.
+  fn decompress_rep_levels(&self, bytes: &[u8]) -> CoreResult<RepLevelsAndBytes> {
+    let SparseParts { count, positions, inner_bytes } = split_sparse(bytes, self.max_nulls)?;
+    let RepLevelsAndBytes { levels: present_levels, remaining_bytes } = self.inner
+      .decompress_rep_levels(inner_bytes)?;
+
+    let mut levels = Vec::with_capacity(count - positions.len() + present_levels.len());
+    let mut present_levels = present_levels.into_iter();
+    let mut next_idx = 0;
+    for pos in positions {
+      let pos = pos as usize;
+      levels.resize(levels.len() + pos - next_idx, 0);
+      loop {
+        let level = present_levels.next()
+          .ok_or_else(|| CoreError::corrupt("fewer repetition levels than sparse positions"))?;
+        levels.push(level);
+        if level == 1 {
+          break;
+        }
+      }
+      next_idx = pos + 1;
+    }
+    if present_levels.next().is_some() {
+      return Err(CoreError::corrupt("more repetition levels than sparse positions"));
+    }
+    levels.resize(levels.len() + count - next_idx, 0);
+
+    Ok(RepLevelsAndBytes { levels, remaining_bytes })
+  }
+
+  fn decompress(&self, bytes: &[u8], nested_list_depth: u8) -> CoreResult<Vec<FieldValue>> {
+    let SparseParts { count, positions, inner_bytes } = split_sparse(bytes, self.max_nulls)?;
+    let present = self.inner.decompress(inner_bytes, nested_list_depth)?;
+    if present.len() != positions.len() {
+      return Err(CoreError::corrupt(&format!(
+        "sparse data has {} positions but {} present values",
+        positions.len(),
+        present.len(),
+      )));
+    }
+
+    let mut res = vec![FieldValue::default(); count];
+    for (pos, fv) in positions.into_iter().zip(present) {
+      res[pos as usize] = fv;
+    }
+    Ok(res)
+  }
+}
+
+fn boxed_sparse_codec<P: Primitive>() -> Option<Box<dyn ValueCodec>> {
+  let inner = P::new_codec(&choose_codec(P::DTYPE))?;
+  Some(Box::new(SparseCodec::new(inner)))
+}
+
+// Uses the data type's default codec for the present values.
+pub(crate) fn new_sparse_codec(dtype: DataType) -> Option<Box<dyn ValueCodec>> {
+  match dtype {
+    DataType::String => boxed_sparse_codec::<String>(),
+    DataType::Int64 => boxed_sparse_codec::<i64>(),
+    DataType::Bytes => boxed_sparse_codec::<Vec<u8>>(),
+    DataType::Bool => boxed_sparse_codec::<bool>(),
+    DataType::Float32 => boxed_sparse_codec::<f32>(),
+    DataType::Float64 => boxed_sparse_codec::<f64>(),
+    DataType::TimestampMicros => boxed_sparse_codec::<TimestampMicros>(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::RepeatedFieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+
+  use crate::compression::{self, Q_COMPRESS, SPARSE, ZSTD};
+  use crate::errors::CoreErrorKind;
+
+  use super::*;
+
+  fn fv(value: Value) -> FieldValue {
+    FieldValue { value: Some(value) }
+  }
+
+  #[test]
+  fn test_million_rows_hundred_present() -> CoreResult<()> {
+    let n = 1_000_000;
+    let mut fvs = vec![FieldValue::default(); n];
+    // scattered irregularly, so neither codec benefits from even spacing
+    let mut state = 7_u64;
+    for i in 0..100 {
+      state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      fvs[(state >> 33) as usize % n] = fv(Value::Int64Val(i - 50));
+    }
+    assert_eq!(compression::choose_codec_for_values(DataType::Int64, &fvs), SPARSE);
+
+    let sparse = compression::new_codec(DataType::Int64, SPARSE)?;
+    let dense = compression::new_codec(DataType::Int64, Q_COMPRESS)?;
+    let sparse_bytes = sparse.compress(&fvs, 0)?;
+    let dense_bytes = dense.compress(&fvs, 0)?;
+    // There's no size win: both are a few hundred bytes, far below a bit
+    // per row, since q_compress run-length encodes the dense null levels.
+    // Sparse data is within a tenth of the dense size; what it saves is
+    // walking a million levels.
+    assert!(sparse_bytes.len() < 500, "{}", sparse_bytes.len());
+    assert!(
+      sparse_bytes.len() * 10 < dense_bytes.len() * 11,
+      "sparse {} vs dense {}",
+      sparse_bytes.len(),
+      dense_bytes.len(),
+    );
+    assert_eq!(sparse.decompress(&sparse_bytes, 0)?, fvs);
+    assert_eq!(
+      sparse.decompress_rep_levels(&sparse_bytes)?.levels,
+      dense.decompress_rep_levels(&dense_bytes)?.levels,
+    );
+    assert_eq!(
+      compression::detect_codec(DataType::Int64, &sparse_bytes),
+      Some(SPARSE.to_string()),
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_nested_matches_dense() -> CoreResult<()> {
+    let string_list = |strings: &[&str]| fv(Value::ListVal(RepeatedFieldValue {
+      vals: strings.iter()
+        .map(|s| fv(Value::StringVal(s.to_string())))
+        .collect(),
+    }));
+    let fvs = vec![
+      FieldValue::default(),
+      string_list(&["abc", ""]),
+      FieldValue::default(),
+      FieldValue::default(),
+      string_list(&[]),
+      string_list(&["d"]),
+      FieldValue::default(),
+    ];
+    let sparse = compression::new_codec(DataType::String, SPARSE)?;
+    let dense = compression::new_codec(DataType::String, ZSTD)?;
+    let bytes = sparse.compress(&fvs, 1)?;
+    assert_eq!(sparse.decompress(&bytes, 1)?, fvs);
+    assert_eq!(
+      sparse.decompress_rep_levels(&bytes)?.levels,
+      dense.decompress_rep_levels(&dense.compress(&fvs, 1)?)?.levels,
+    );
+
+    let appended = sparse.append(&bytes, &fvs[..2], 1)?;
+    assert_eq!(sparse.decompress(&appended, 1)?, [&fvs[..], &fvs[..2]].concat());
+
+    let with_stats = sparse.compress_with_stats(&fvs, 1)?;
+    assert_eq!(sparse.read_stats_only(&with_stats)?.unwrap().null_count, 4);
+    assert_eq!(sparse.decompress(&with_stats, 1)?, fvs);
+    Ok(())
+  }
+
+  #[test]
+  fn test_corrupt_count() -> CoreResult<()> {
+    let sparse = compression::new_codec(DataType::Int64, SPARSE)?;
+    let fvs = vec![FieldValue::default(), fv(Value::Int64Val(3)), FieldValue::default()];
+    let bytes = sparse.compress(&fvs, 0)?;
+    let count_range = SPARSE_MAGIC.len()..SPARSE_MAGIC.len() + COUNT_SIZE;
+    assert_eq!(bytes[count_range.clone()], 3_u64.to_be_bytes());
+
+    for count in [u64::MAX, 1 << 40] {
+      let mut corrupted = bytes.clone();
+      corrupted[count_range.clone()].copy_from_slice(&count.to_be_bytes());
+      assert_eq!(sparse.decompress(&corrupted, 0).unwrap_err().kind, CoreErrorKind::Corrupt);
+      assert_eq!(sparse.decompress_rep_levels(&corrupted).unwrap_err().kind, CoreErrorKind::Corrupt);
+    }
+    // a count too small for the positions is caught too
+    let mut corrupted = bytes;
+    corrupted[count_range].copy_from_slice(&1_u64.to_be_bytes());
+    assert!(sparse.decompress(&corrupted, 0).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_max_nulls_boundary() -> CoreResult<()> {
+    let sparse = SparseCodec {
+      inner: i64::new_codec(Q_COMPRESS).unwrap(),
+      max_nulls: 4,
+    };
+    let mut fvs = vec![FieldValue::default(); 4];
+    fvs.insert(2, fv(Value::Int64Val(-7)));
+    let bytes = sparse.compress(&fvs, 0)?;
+    assert_eq!(sparse.decompress(&bytes, 0)?, fvs);
+    assert_eq!(sparse.decompress_rep_levels(&bytes)?.levels, vec![0, 0, 1, 0, 0]);
+
+    fvs.push(FieldValue::default());
+    let err = sparse.compress(&fvs, 0).unwrap_err();
+    assert_eq!(err.kind, CoreErrorKind::Invalid);
+    let mut writer = Vec::new();
+    assert!(sparse.compress_to_writer(&fvs, 0, &mut writer).is_err());
+    assert!(writer.is_empty());
+
+    // data written without the cap reads back as corrupt
+    let uncapped = SparseCodec { max_nulls: 5, ..sparse };
+    let bytes = uncapped.compress(&fvs, 0)?;
+    let sparse = SparseCodec { max_nulls: 4, ..uncapped };
+    assert_eq!(sparse.decompress(&bytes, 0).unwrap_err().kind, CoreErrorKind::Corrupt);
+    Ok(())
+  }
+
+  #[test]
+  fn test_empty_and_all_null() -> CoreResult<()> {
+    let sparse = compression::new_codec(DataType::Float64, SPARSE)?;
+    for fvs in [vec![], vec![FieldValue::default(); 3]] {
+      let bytes = sparse.compress(&fvs, 0)?;
+      assert_eq!(sparse.decompress(&bytes, 0)?, fvs);
+      assert_eq!(sparse.decompress_rep_levels(&bytes)?.levels, vec![0; fvs.len()]);
+    }
+    let dense_bytes = compression::new_codec(DataType::Float64, Q_COMPRESS)?
+      .compress(&[], 0)?;
+    assert!(sparse.decompress(&dense_bytes, 0).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,199 @@
+use pancake_db_idl::dml::FieldValue;
+use pancake_db_idl::dml::field_value::Value;
+
+use crate::encoding::{Decoder, DecoderImpl, Encoder, EncoderImpl};
+use crate::errors::{CoreError, CoreResult};
+use crate::primitives::{self, Primitive};
+use crate::utils;
+
+// A stats header is this magic, a big-endian u32 body length, and the body:
+// big-endian u32 row and null counts, then the min and max as two values in
+// the uncompressed encoding at depth 0 (null if the column has no eligible
+// value).
+// Compressed data without a header starts with q_compress's own "qco!"
+// magic, so the two can't be confused.
+const STATS_MAGIC: [u8; 4] = *b"pcs!";
+
+/// Summary statistics of a column, stored ahead of its compressed data.
+///
+/// `min` and `max` range over every primitive value in the column,
+/// including those nested in lists, and follow
+/// [`StatsOrd`][crate::primitives::StatsOrd].
+/// `null_count` counts only top-level nulls.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnStats {
+  pub row_count: u32,
+  pub null_count: u32,
+  pub min: Option<FieldValue>,
+  pub max: Option<FieldValue>,
+}
+
+pub(crate) fn column_stats<P: Primitive>(values: &[FieldValue]) -> CoreResult<ColumnStats> {
+  let mut null_count = 0;
+  let mut leaves = Vec::new();
+  let mut stack = values.iter().collect::<Vec<_>>();
+  while let Some(fv) = stack.pop() {
+    match &fv.value {
+      None => null_count += 1,
+      Some(Value::ListVal(repeated)) => stack.extend(repeated.vals.iter()),
+      Some(v) => leaves.push(P::try_from_value(v)?),
+    }
+  }
+
+  let (min, max) = match primitives::min_max(&leaves) {
+    Some((min, max)) => (Some(primitive_fv(min)), Some(primitive_fv(max))),
+    None => (None, None),
+  };
+  Ok(ColumnStats {
+    row_count: values.len() as u32,
+    null_count,
+    min,
+    max,
+  })
+}
+
+fn primitive_fv<P: Primitive>(p: &P) -> FieldValue {
+  FieldValue {
+    value: Some(p.to_value()),
+  }
+}
+
+pub(crate) fn write_header<P: Primitive>(stats: &ColumnStats, res: &mut Vec<u8>) -> CoreResult<()> {
+  let mut body = Vec::new();
+  body.extend(stats.row_count.to_be_bytes());
+  body.extend(stats.null_count.to_be_bytes());
+  let extrema = [
+    stats.min.clone().unwrap_or_default(),
+    stats.max.clone().unwrap_or_default(),
+  ];
+  body.extend(EncoderImpl::<P>::new(0).encode(&extrema)?);
+
+  res.extend(STATS_MAGIC);
+  res.extend((body.len() as u32).to_be_bytes());
+  res.extend(body);
+  Ok(())
+}
+
+// Splits compressed bytes into the stats header body, if any, and the
+// compressed data after it.
+pub(crate) fn split_header(bytes: &[u8]) -> CoreResult<(Option<&[u8]>, &[u8])> {
+  if !bytes.starts_with(&STATS_MAGIC) {
+    return Ok((None, bytes));
+  }
+
+  let truncated = || CoreError::corrupt("truncated column stats header");
+  let len_bytes = bytes.get(4..8).ok_or_else(truncated)?;
+  let body_len = u32::from_be_bytes(utils::try_byte_array::<4>(len_bytes)?) as usize;
+  let body = bytes.get(8..8 + body_len).ok_or_else(truncated)?;
+  Ok((Some(body), &bytes[8 + body_len..]))
+}
+
+pub(crate) fn parse_body<P: Primitive>(body: &[u8]) -> CoreResult<ColumnStats> {
+  let truncated = || CoreError::corrupt("truncated column stats header");
+  let row_count = u32::from_be_bytes(utils::try_byte_array::<4>(body.get(0..4).ok_or_else(truncated)?)?);
+  let null_count = u32::from_be_bytes(utils::try_byte_array::<4>(body.get(4..8).ok_or_else(truncated)?)?);
+  let extrema = DecoderImpl::<P, FieldValue>::new(0).decode(&body[8..])?;
+  if extrema.len() != 2 {
+    return Err(CoreError::corrupt(&format!(
+      "expected min and max in column stats header but found {} values",
+      extrema.len(),
+    )));
+  }
+
+  let mut extrema = extrema.into_iter()
+    .map(|fv| fv.value.is_some().then_some(fv));
+  Ok(ColumnStats {
+    row_count,
+    null_count,
+    min: extrema.next().flatten(),
+    max: extrema.next().flatten(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::RepeatedFieldValue;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression::{self, Q_COMPRESS, ZSTD};
+
+  use super::*;
+
+  fn fv(value: Value) -> FieldValue {
+    FieldValue {
+      value: Some(value),
+    }
+  }
+
+  fn float_list(xs: &[f64]) -> FieldValue {
+    fv(Value::ListVal(RepeatedFieldValue {
+      vals: xs.iter().map(|&x| fv(Value::Float64Val(x))).collect(),
+    }))
+  }
+
+  #[test]
+  fn test_stats_match_decode() -> CoreResult<()> {
+    let fvs = vec![
+      float_list(&[1.5, f64::NAN]),
+      FieldValue::default(),
+      float_list(&[]),
+      float_list(&[-2.0, 7.25, 0.0]),
+      FieldValue::default(),
+    ];
+    let codec = compression::new_codec(DataType::Float64, Q_COMPRESS)?;
+    let bytes = codec.compress_with_stats(&fvs, 1)?;
+
+    let decoded = codec.decompress(&bytes, 1)?;
+    assert_eq!(format!("{:?}", decoded), format!("{:?}", fvs));
+    let stats = codec.read_stats_only(&bytes)?.unwrap();
+    assert_eq!(stats, column_stats::<f64>(&decoded)?);
+    assert_eq!(stats, ColumnStats {
+      row_count: 5,
+      null_count: 2,
+      min: Some(fv(Value::Float64Val(-2.0))),
+      max: Some(fv(Value::Float64Val(7.25))),
+    });
+    Ok(())
+  }
+
+  #[test]
+  fn test_stats_without_values() -> CoreResult<()> {
+    let fvs = vec![FieldValue::default(), FieldValue::default()];
+    let codec = compression::new_codec(DataType::String, ZSTD)?;
+    let bytes = codec.compress_with_stats(&fvs, 0)?;
+    assert_eq!(codec.decompress(&bytes, 0)?, fvs);
+    assert_eq!(codec.read_stats_only(&bytes)?, Some(ColumnStats {
+      row_count: 2,
+      null_count: 2,
+      min: None,
+      max: None,
+    }));
+    Ok(())
+  }
+
+  #[test]
+  fn test_headerless_data() -> CoreResult<()> {
+    let fvs = vec![fv(Value::Int64Val(3)), FieldValue::default()];
+    let codec = compression::new_codec(DataType::Int64, Q_COMPRESS)?;
+    let bytes = codec.compress(&fvs, 0)?;
+    assert_eq!(codec.read_stats_only(&bytes)?, None);
+    assert_eq!(codec.decompress(&bytes, 0)?, fvs);
+
+    // appending keeps the data readable but drops the stale header
+    let with_stats = codec.compress_with_stats(&fvs, 0)?;
+    let appended = codec.append(&with_stats, &fvs, 0)?;
+    assert_eq!(codec.read_stats_only(&appended)?, None);
+    assert_eq!(codec.decompress(&appended, 0)?.len(), 4);
+    Ok(())
+  }
+
+  #[test]
+  fn test_truncated_header() -> CoreResult<()> {
+    let codec = compression::new_codec(DataType::Int64, Q_COMPRESS)?;
+    let bytes = codec.compress_with_stats(&[fv(Value::Int64Val(3))], 0)?;
+    for len in [5, 10, 20] {
+      assert!(codec.read_stats_only(&bytes[..len]).is_err());
+    }
+    Ok(())
+  }
+}
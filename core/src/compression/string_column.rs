@@ -0,0 +1,138 @@
+use std::ops::Range;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::primitives::Primitive;
+use crate::rep_levels::RepLevelsAndBytes;
+
+use super::ValueCodec;
+
+/// A decompressed, non-nested string column that hands out borrowed
+/// `&str`s instead of allocating a `String` per value.
+///
+/// All values share one buffer, which the column owns, so the strings live
+/// as long as the column does.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StringColumn {
+  buffer: String,
+  ranges: Vec<Option<Range<usize>>>,
+}
+
+impl StringColumn {
+  /// Decompresses a string column of nested list depth 0 that was
+  /// compressed with the given codec.
+  ///
+  /// Every value is checked to be valid UTF-8 here, so reading values
+  /// afterward cannot fail.
+  pub fn decompress(codec: &str, bytes: &[u8]) -> CoreResult<Self> {
+    let codec = String::new_codec(codec)
+      .ok_or_else(|| CoreError::invalid(&format!(
+        "compression codec {} unavailable for strings",
+        codec,
+      )))?;
+    let RepLevelsAndBytes { remaining_bytes, levels } = codec.decompress_rep_levels(bytes)?;
+    let atoms = codec.decompress_atoms(&remaining_bytes)?;
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    for level in levels {
+      match level {
+        0 if start == end => ranges.push(None),
+        1 => {
+          if end > atoms.len() {
+            return Err(CoreError::corrupt("fewer atoms than repetition levels indicate"));
+          }
+          std::str::from_utf8(&atoms[start..end])
+            .map_err(|e| CoreError::corrupt(&format!("invalid UTF-8 string: {}", e)))?;
+          ranges.push(Some(start..end));
+          start = end;
+        },
+        2 => end += 1,
+        _ => return Err(CoreError::corrupt(&format!(
+          "unexpected repetition level {} for a non-nested string column",
+          level,
+        ))),
+      }
+    }
+    if end != atoms.len() || start != end {
+      return Err(CoreError::corrupt("repetition levels and atoms disagree in length"));
+    }
+
+    // each value is valid UTF-8, so their concatenation is too, and every
+    // range boundary falls on a char boundary
+    let buffer = String::from_utf8(atoms)?;
+    Ok(StringColumn { buffer, ranges })
+  }
+
+  /// Returns the number of values, including nulls.
+  pub fn len(&self) -> usize {
+    self.ranges.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ranges.is_empty()
+  }
+
+  /// Returns the value at `idx`, or `None` if it is null or out of bounds.
+  pub fn get(&self, idx: usize) -> Option<&str> {
+    self.ranges.get(idx)?
+      .as_ref()
+      .map(|range| &self.buffer[range.clone()])
+  }
+
+  /// Iterates over the values, yielding `None` for nulls.
+  pub fn iter(&self) -> impl Iterator<Item=Option<&str>> {
+    self.ranges.iter()
+      .map(move |maybe_range| maybe_range.as_ref().map(|range| &self.buffer[range.clone()]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression::{self, ZSTD};
+
+  use super::*;
+
+  #[test]
+  fn test_matches_owned_decode() -> CoreResult<()> {
+    let strings = vec![Some("abc"), None, Some(""), Some("ünïcødé ✓"), None, Some("z")];
+    let fvs = strings.iter()
+      .map(|maybe_s| FieldValue {
+        value: maybe_s.map(|s| Value::StringVal(s.to_string())),
+      })
+      .collect::<Vec<_>>();
+    let codec = compression::new_codec(DataType::String, ZSTD)?;
+    let bytes = codec.compress(&fvs, 0)?;
+
+    let column = StringColumn::decompress(ZSTD, &bytes)?;
+    assert_eq!(column.len(), strings.len());
+    assert_eq!(column.iter().collect::<Vec<_>>(), strings);
+    let owned = codec.decompress(&bytes, 0)?;
+    for (idx, fv) in owned.iter().enumerate() {
+      let expected = match &fv.value {
+        Some(Value::StringVal(s)) => Some(s.as_str()),
+        _ => None,
+      };
+      assert_eq!(column.get(idx), expected);
+    }
+    assert_eq!(column.get(strings.len()), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_rejects_nested() -> CoreResult<()> {
+    let fvs = vec![FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue {
+        vals: vec![FieldValue { value: Some(Value::StringVal("a".to_string())) }],
+      })),
+    }];
+    let bytes = compression::new_codec(DataType::String, ZSTD)?.compress(&fvs, 1)?;
+    assert!(StringColumn::decompress(ZSTD, &bytes).is_err());
+    assert!(StringColumn::decompress("nonexistent", &bytes).is_err());
+    Ok(())
+  }
+}
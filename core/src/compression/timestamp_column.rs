@@ -0,0 +1,204 @@
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use q_compress::data_types::TimestampMicros;
+
+use crate::errors::{CoreError, CoreResult};
+use crate::primitives::Primitive;
+use crate::rep_levels::RepLevelsAndBytes;
+
+use super::ValueCodec;
+
+/// A decompressed, non-nested timestamp column that hands out microseconds
+/// since the Unix epoch or `SystemTime`s, skipping the
+/// `prost_types::Timestamp` of each field value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimestampColumn {
+  micros: Vec<Option<i64>>,
+}
+
+impl TimestampColumn {
+  /// Decompresses a timestamp column of nested list depth 0 that was
+  /// compressed with the given codec.
+  ///
+  /// Every value is checked to fit in an `i64` of microseconds and a
+  /// `SystemTime` here, so reading values afterward cannot fail.
+  pub fn decompress(codec: &str, bytes: &[u8]) -> CoreResult<Self> {
+    let codec = TimestampMicros::new_codec(codec)
+      .ok_or_else(|| CoreError::invalid(&format!(
+        "compression codec {} unavailable for timestamps",
+        codec,
+      )))?;
+    let RepLevelsAndBytes { remaining_bytes, levels } = codec.decompress_rep_levels(bytes)?;
+    let atoms = codec.decompress_atoms(&remaining_bytes)?;
+
+    let mut micros = Vec::with_capacity(levels.len());
+    let mut atoms_iter = atoms.into_iter();
+    for level in levels {
+      match level {
+        0 => micros.push(None),
+        1 => {
+          let t = atoms_iter.next()
+            .ok_or_else(|| CoreError::corrupt("fewer atoms than repetition levels indicate"))?;
+          micros.push(Some(checked_micros(t)?));
+        },
+        _ => return Err(CoreError::corrupt(&format!(
+          "unexpected repetition level {} for a non-nested timestamp column",
+          level,
+        ))),
+      }
+    }
+    if atoms_iter.next().is_some() {
+      return Err(CoreError::corrupt("repetition levels and atoms disagree in length"));
+    }
+
+    Ok(TimestampColumn { micros })
+  }
+
+  /// Returns the number of values, including nulls.
+  pub fn len(&self) -> usize {
+    self.micros.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.micros.is_empty()
+  }
+
+  /// Returns the value at `idx` in microseconds since the Unix epoch, or
+  /// `None` if it is null or out of bounds.
+  pub fn get_micros(&self, idx: usize) -> Option<i64> {
+    *self.micros.get(idx)?
+  }
+
+  /// Returns the value at `idx` as a `SystemTime`, or `None` if it is null
+  /// or out of bounds.
+  pub fn get_system_time(&self, idx: usize) -> Option<SystemTime> {
+    self.get_micros(idx).map(micros_to_system_time)
+  }
+
+  /// Returns the values in microseconds since the Unix epoch, with `None`
+  /// for nulls.
+  pub fn micros(&self) -> &[Option<i64>] {
+    &self.micros
+  }
+
+  /// Iterates over the values as `SystemTime`s, yielding `None` for nulls.
+  pub fn iter_system_times(&self) -> impl Iterator<Item=Option<SystemTime>> + '_ {
+    self.micros.iter()
+      .map(|maybe_micros| maybe_micros.map(micros_to_system_time))
+  }
+}
+
+fn checked_micros(t: TimestampMicros) -> CoreResult<i64> {
+  let micros = i64::try_from(t.to_total_parts())
+    .map_err(|_| CoreError::corrupt(&format!(
+      "timestamp of {} microseconds does not fit in an i64",
+      t.to_total_parts(),
+    )))?;
+  let magnitude = Duration::from_micros(micros.unsigned_abs());
+  let fits = if micros >= 0 {
+    UNIX_EPOCH.checked_add(magnitude).is_some()
+  } else {
+    UNIX_EPOCH.checked_sub(magnitude).is_some()
+  };
+  if fits {
+    Ok(micros)
+  } else {
+    Err(CoreError::corrupt(&format!(
+      "timestamp of {} microseconds is out of range for this platform",
+      micros,
+    )))
+  }
+}
+
+// only called on micros that passed `checked_micros`
+fn micros_to_system_time(micros: i64) -> SystemTime {
+  let magnitude = Duration::from_micros(micros.unsigned_abs());
+  if micros >= 0 {
+    UNIX_EPOCH + magnitude
+  } else {
+    UNIX_EPOCH - magnitude
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression::{self, DELTA, Q_COMPRESS};
+
+  use super::*;
+
+  fn micros_fv(maybe_micros: Option<i64>) -> FieldValue {
+    FieldValue {
+      value: maybe_micros.map(|micros| {
+        TimestampMicros::new(micros as i128).unwrap().to_value()
+      }),
+    }
+  }
+
+  #[test]
+  fn test_micros_match_input() -> CoreResult<()> {
+    let micros = vec![
+      Some(1_645_000_000_123_456),
+      None,
+      Some(0),
+      Some(-1),
+      Some(-86_400_000_001),
+      None,
+      Some(-2_208_988_800_000_000),
+    ];
+    let fvs = micros.iter().cloned().map(micros_fv).collect::<Vec<_>>();
+    for codec_name in [Q_COMPRESS, DELTA] {
+      let codec = compression::new_codec(DataType::TimestampMicros, codec_name)?;
+      let bytes = codec.compress(&fvs, 0)?;
+
+      let column = TimestampColumn::decompress(codec_name, &bytes)?;
+      assert_eq!(column.len(), micros.len());
+      assert_eq!(column.micros(), micros.as_slice(), "{}", codec_name);
+      // the owned decode agrees
+      assert_eq!(
+        column.micros().iter().cloned().map(micros_fv).collect::<Vec<_>>(),
+        codec.decompress(&bytes, 0)?,
+      );
+    }
+
+    let bytes = compression::new_codec(DataType::TimestampMicros, Q_COMPRESS)?.compress(&fvs, 0)?;
+    let column = TimestampColumn::decompress(Q_COMPRESS, &bytes)?;
+    assert_eq!(column.get_system_time(2), Some(UNIX_EPOCH));
+    assert_eq!(column.get_system_time(3), Some(UNIX_EPOCH - Duration::from_micros(1)));
+    assert_eq!(
+      column.get_system_time(0),
+      Some(UNIX_EPOCH + Duration::from_micros(1_645_000_000_123_456)),
+    );
+    assert_eq!(column.get_system_time(1), None);
+    assert_eq!(column.get_micros(micros.len()), None);
+    assert_eq!(column.iter_system_times().filter(Option::is_some).count(), 5);
+    Ok(())
+  }
+
+  #[test]
+  fn test_rejects_nested() -> CoreResult<()> {
+    let fvs = vec![FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue {
+        vals: vec![micros_fv(Some(5))],
+      })),
+    }];
+    let bytes = compression::new_codec(DataType::TimestampMicros, Q_COMPRESS)?.compress(&fvs, 1)?;
+    assert!(TimestampColumn::decompress(Q_COMPRESS, &bytes).is_err());
+    assert!(TimestampColumn::decompress("nonexistent", &bytes).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_rejects_micros_overflowing_i64() -> CoreResult<()> {
+    let fvs = vec![FieldValue {
+      value: Some(TimestampMicros::from_secs_and_nanos(i64::MAX / 10, 0).to_value()),
+    }];
+    let bytes = compression::new_codec(DataType::TimestampMicros, Q_COMPRESS)?.compress(&fvs, 0)?;
+    assert!(TimestampColumn::decompress(Q_COMPRESS, &bytes).is_err());
+    Ok(())
+  }
+}
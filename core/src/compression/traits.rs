@@ -1,5 +1,7 @@
+use std::io::Write;
+
 use pancake_db_idl::dml::FieldValue;
-use q_compress::{BitReader, BitWords, Decompressor};
+use pancake_db_idl::dtype::DataType;
 
 use crate::errors::CoreResult;
 use crate::primitives::Primitive;
@@ -7,6 +9,10 @@ use crate::rep_levels;
 use crate::rep_levels::{RepLevelsAndAtoms, RepLevelsAndBytes};
 use crate::rep_levels::AtomNester;
 
+use super::checksum;
+use super::q_codec;
+use super::stats_header::{self, ColumnStats};
+
 pub trait Codec: Send + Sync {
   type P: Primitive;
 
@@ -15,52 +21,270 @@ pub trait Codec: Send + Sync {
 }
 
 pub trait ValueCodec: Send + Sync {
+  /// Returns the data type of the primitive this compresses.
+  fn dtype(&self) -> DataType;
+
   fn compress(&self, values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>>;
 
+  /// Like [`compress`][ValueCodec::compress], but writes the compressed
+  /// bytes to `writer` as each block is compressed rather than building
+  /// the whole output first.
+  ///
+  /// The bytes written are exactly those `compress` returns.
+  /// The provided implementation builds the whole output with `compress`
+  /// and writes it at once.
+  fn compress_to_writer(
+    &self,
+    values: &[FieldValue],
+    nested_list_depth: u8,
+    writer: &mut dyn Write,
+  ) -> CoreResult<()> {
+    writer.write_all(&self.compress(values, nested_list_depth)?)?;
+    Ok(())
+  }
+
+  /// Like [`compress`][ValueCodec::compress], but prepends a header of
+  /// [`ColumnStats`] that [`read_stats_only`][ValueCodec::read_stats_only]
+  /// can return without decompressing anything.
+  ///
+  /// Decompression skips the header, so the result reads back the same as
+  /// data without one.
+  fn compress_with_stats(&self, values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>>;
+
+  /// Returns the stats header of compressed data, or `None` if it was
+  /// compressed without one.
+  ///
+  /// This doesn't verify any checksum, since that would read the whole
+  /// column.
+  fn read_stats_only(&self, bytes: &[u8]) -> CoreResult<Option<ColumnStats>>;
+
   fn decompress_rep_levels(&self, bytes: &[u8]) -> CoreResult<RepLevelsAndBytes>;
   fn decompress(&self, bytes: &[u8], nested_list_depth: u8) -> CoreResult<Vec<FieldValue>>;
+
+  /// Like [`decompress`][ValueCodec::decompress], but builds each field
+  /// value only when the iterator reaches it.
+  ///
+  /// The repetition levels and atoms are still decompressed up front, but
+  /// they take much less memory than the field values built from them.
+  /// The provided implementation decompresses all the field values first.
+  fn decompress_iter<'a>(
+    &'a self,
+    bytes: &[u8],
+    nested_list_depth: u8,
+  ) -> CoreResult<Box<dyn Iterator<Item=CoreResult<FieldValue>> + 'a>> {
+    let values = self.decompress(bytes, nested_list_depth)?;
+    Ok(Box::new(values.into_iter().map(Ok)))
+  }
+
+  /// Returns compressed bytes holding the values of `bytes` followed by
+  /// `values`.
+  ///
+  /// This is not incremental: the existing values are decompressed and
+  /// everything is compressed again with this codec.
+  /// Any stats header is dropped, since its stats would no longer hold, and
+  /// so is any checksum.
+  ///
+  /// The provided implementation rebuilds the existing field values;
+  /// codecs can skip that by recombining repetition levels and atoms.
+  fn append(
+    &self,
+    bytes: &[u8],
+    values: &[FieldValue],
+    nested_list_depth: u8,
+  ) -> CoreResult<Vec<u8>> {
+    let mut all_values = self.decompress(bytes, nested_list_depth)?;
+    all_values.extend_from_slice(values);
+    self.compress(&all_values, nested_list_depth)
+  }
 }
 
 impl<P: Primitive> ValueCodec for Box<dyn Codec<P=P>> {
+  fn dtype(&self) -> DataType {
+    P::DTYPE
+  }
+
   fn compress(&self, field_values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>> {
+    let mut res = Vec::new();
+    self.compress_to_writer(field_values, nested_list_depth, &mut res)?;
+    Ok(res)
+  }
+
+  fn compress_to_writer(
+    &self,
+    field_values: &[FieldValue],
+    nested_list_depth: u8,
+    writer: &mut dyn Write,
+  ) -> CoreResult<()> {
     let RepLevelsAndAtoms { levels, atoms } = rep_levels::extract_levels_and_atoms::<P>(
       field_values,
       nested_list_depth,
     )?;
-    let mut res = rep_levels::compress_rep_levels(levels)?;
-    res.extend(self.compress_atoms(&atoms)?);
+    writer.write_all(&rep_levels::compress_rep_levels(levels)?)?;
+    writer.write_all(&self.compress_atoms(&atoms)?)?;
+    Ok(())
+  }
+
+  fn compress_with_stats(&self, field_values: &[FieldValue], nested_list_depth: u8) -> CoreResult<Vec<u8>> {
+    let stats = stats_header::column_stats::<P>(field_values)?;
+    let mut res = Vec::new();
+    stats_header::write_header::<P>(&stats, &mut res)?;
+    res.extend(self.compress(field_values, nested_list_depth)?);
     Ok(res)
   }
 
-  fn decompress_rep_levels(&self, bytes: &[u8]) -> CoreResult<RepLevelsAndBytes> {
-    let decompressor = Decompressor::<u32>::default();
-    let words = BitWords::from(bytes);
-    let mut reader = BitReader::from(&words);
-    let flags = decompressor.header(&mut reader)?;
-    let mut rep_levels = Vec::new();
-    while let Some(chunk) = decompressor.chunk(&mut reader, &flags)? {
-      rep_levels.extend(
-        chunk.nums
-          .iter()
-          .map(|&l| l as u8)
-      );
+  fn read_stats_only(&self, bytes: &[u8]) -> CoreResult<Option<ColumnStats>> {
+    let bytes = checksum::strip_checksum(bytes)?;
+    match stats_header::split_header(bytes)?.0 {
+      Some(body) => Ok(Some(stats_header::parse_body::<P>(body)?)),
+      None => Ok(None),
     }
+  }
 
-    let byte_idx = reader.aligned_byte_idx()?;
+  fn decompress_rep_levels(&self, bytes: &[u8]) -> CoreResult<RepLevelsAndBytes> {
+    let bytes = checksum::verify_checksum(bytes)?;
+    let (_, bytes) = stats_header::split_header(bytes)?;
+    let (levels, byte_idx) = q_codec::q_decompress::<u32>(bytes)?;
     Ok(RepLevelsAndBytes {
-      remaining_bytes: reader.read_aligned_bytes(bytes.len() - byte_idx)?.to_vec(),
-      levels: rep_levels,
+      remaining_bytes: bytes[byte_idx..].to_vec(),
+      levels: levels.into_iter().map(|l| l as u8).collect(),
     })
   }
 
   fn decompress(&self, bytes: &[u8], nested_list_depth: u8) -> CoreResult<Vec<FieldValue>> {
+    self.decompress_iter(bytes, nested_list_depth)?.collect()
+  }
+
+  fn decompress_iter<'a>(
+    &'a self,
+    bytes: &[u8],
+    nested_list_depth: u8,
+  ) -> CoreResult<Box<dyn Iterator<Item=CoreResult<FieldValue>> + 'a>> {
     let RepLevelsAndBytes { remaining_bytes, levels } = self.decompress_rep_levels(bytes)?;
     let atoms: Vec<P::A> = self.decompress_atoms(&remaining_bytes)?;
-    let mut nester = AtomNester::<P>::from_levels_and_values(
+    Ok(Box::new(AtomNester::<P>::from_levels_and_values(
       levels,
       atoms,
       nested_list_depth,
-    );
-    nester.nested_field_values()
+    )))
+  }
+
+  fn append(
+    &self,
+    bytes: &[u8],
+    values: &[FieldValue],
+    nested_list_depth: u8,
+  ) -> CoreResult<Vec<u8>> {
+    let RepLevelsAndBytes { remaining_bytes, mut levels } = self.decompress_rep_levels(bytes)?;
+    let mut atoms: Vec<P::A> = self.decompress_atoms(&remaining_bytes)?;
+    // each top-level value's levels are self-contained, so appending levels
+    // appends values
+    let appended = rep_levels::extract_levels_and_atoms::<P>(
+      values,
+      nested_list_depth,
+    )?;
+    levels.extend(appended.levels);
+    atoms.extend(appended.atoms);
+
+    let mut res = rep_levels::compress_rep_levels(levels)?;
+    res.extend(self.compress_atoms(&atoms)?);
+    Ok(res)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::RepeatedFieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+  use pancake_db_idl::dtype::DataType;
+
+  use crate::compression;
+
+  use super::*;
+
+  fn fv(value: Value) -> FieldValue {
+    FieldValue {
+      value: Some(value),
+    }
+  }
+
+  fn string_list(strings: &[&str]) -> FieldValue {
+    fv(Value::ListVal(RepeatedFieldValue {
+      vals: strings.iter().map(|s| fv(Value::StringVal(s.to_string()))).collect(),
+    }))
+  }
+
+  #[test]
+  fn test_decompress_iter() -> CoreResult<()> {
+    let fvs = vec![string_list(&["ab", ""]), FieldValue::default(), string_list(&[])];
+    for codec_name in [compression::ZSTD, compression::SPARSE] {
+      let codec = compression::new_codec(DataType::String, codec_name)?;
+      let bytes = codec.compress(&fvs, 1)?;
+      let decompressed = codec.decompress_iter(&bytes, 1)?.collect::<CoreResult<Vec<_>>>()?;
+      assert_eq!(decompressed, fvs, "{}", codec_name);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_append_ints() -> CoreResult<()> {
+    let codec = compression::new_codec(DataType::Int64, compression::Q_COMPRESS)?;
+    let existing = vec![fv(Value::Int64Val(1)), FieldValue::default()];
+    let appended = vec![fv(Value::Int64Val(-3)), fv(Value::Int64Val(4))];
+    let bytes = codec.compress(&existing, 0)?;
+
+    let bytes = codec.append(&bytes, &appended, 0)?;
+    let mut expected = existing;
+    expected.extend(appended);
+    assert_eq!(codec.decompress(&bytes, 0)?, expected);
+    Ok(())
+  }
+
+  #[test]
+  fn test_append_nested_strings() -> CoreResult<()> {
+    let codec = compression::new_codec(DataType::String, compression::ZSTD)?;
+    let existing = vec![string_list(&["ab", ""]), FieldValue::default(), string_list(&[])];
+    let appended = vec![string_list(&[]), string_list(&["cde"]), FieldValue::default()];
+    let bytes = codec.compress(&existing, 1)?;
+
+    let bytes = codec.append(&bytes, &appended, 1)?;
+    let mut expected = existing;
+    expected.extend(appended);
+    assert_eq!(codec.decompress(&bytes, 1)?, expected);
+
+    assert!(codec.append(&bytes, &[string_list(&["x"])], 0).is_err());
+    Ok(())
+  }
+
+  // records the bytes of each write call separately
+  #[derive(Default)]
+  struct ChunkWriter(Vec<Vec<u8>>);
+
+  impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.push(buf.to_vec());
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_compress_to_writer_nested() -> CoreResult<()> {
+    let fvs = vec![string_list(&["ab", ""]), FieldValue::default(), string_list(&[]), string_list(&["cde"])];
+    for codec_name in [compression::ZSTD, compression::SPARSE] {
+      let codec = compression::new_codec(DataType::String, codec_name)?;
+      let mut writer = ChunkWriter::default();
+      codec.compress_to_writer(&fvs, 1, &mut writer)?;
+      // written block by block, not as one finished buffer
+      assert!(writer.0.len() > 1, "{}", codec_name);
+      assert_eq!(writer.0.concat(), codec.compress(&fvs, 1)?, "{}", codec_name);
+    }
+
+    let codec = compression::new_codec(DataType::String, compression::ZSTD)?;
+    let mut full: &mut [u8] = &mut [0; 4];
+    assert!(codec.compress_to_writer(&fvs, 1, &mut full).is_err());
+    Ok(())
   }
 }
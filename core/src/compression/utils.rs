@@ -1,17 +1,29 @@
+use pancake_db_idl::dml::FieldValue;
 use pancake_db_idl::dtype::DataType;
 use q_compress::data_types::TimestampMicros;
 
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::Primitive;
 
-use super::{Q_COMPRESS, ZSTD};
-use super::ValueCodec;
+use super::{BITPACK, Q_COMPRESS, SPARSE, ZSTD};
+use super::{CodecSpec, ValueCodec};
+use super::sparse_codec;
+use super::zstd_codec::ZstdCodec;
 
-pub fn new_codec(
-  dtype: DataType,
-  codec: &str,
-) -> CoreResult<Box<dyn ValueCodec>> {
-  let maybe_res: Option<Box<dyn ValueCodec>> = match dtype {
+// the format magic at the start of compressed atoms
+const Q_COMPRESS_MAGIC: [u8; 4] = *b"qco!";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// the largest fraction of present values for which the sparse codec is
+// chosen; below it, sparse data is about as small as the default codecs'
+// and much faster to read
+const SPARSE_MAX_DENSITY: f64 = 0.05;
+
+fn value_codec(dtype: DataType, codec: &str) -> Option<Box<dyn ValueCodec>> {
+  if codec == SPARSE {
+    return sparse_codec::new_sparse_codec(dtype);
+  }
+  match dtype {
     DataType::String => String::new_value_codec(codec),
     DataType::Int64 => i64::new_value_codec(codec),
     DataType::Bytes => Vec::<u8>::new_value_codec(codec),
@@ -19,18 +31,170 @@ pub fn new_codec(
     DataType::Float32 => f32::new_value_codec(codec),
     DataType::Float64 => f64::new_value_codec(codec),
     DataType::TimestampMicros => TimestampMicros::new_value_codec(codec),
-  };
+  }
+}
 
-  match maybe_res {
+/// Returns whether [`new_codec`] can construct the codec for the data type.
+pub fn supports(dtype: DataType, codec: &str) -> bool {
+  value_codec(dtype, codec).is_some()
+}
+
+/// Constructs the codec for the data type from a codec string.
+///
+/// The codec string may carry parameters after the codec's name, like
+/// `zstd:level=3`; see [`CodecSpec`] for the format.
+/// Returns an error if the codec string is malformed, the codec doesn't
+/// accept its parameters, or the codec is unavailable for the data type.
+pub fn new_codec(
+  dtype: DataType,
+  codec: &str,
+) -> CoreResult<Box<dyn ValueCodec>> {
+  let spec = CodecSpec::parse(codec)?;
+  match value_codec(dtype, codec) {
     Some(res) => Ok(res),
-    None => Err(CoreError::invalid(&format!(
-      "compression codec {} unavailable for data type {:?}",
-      codec,
-      dtype,
+    None => {
+      if supports(dtype, spec.name) {
+        check_params(&spec)?;
+      }
+      Err(CoreError::invalid(&format!(
+        "compression codec {} unavailable for data type {:?}",
+        codec,
+        dtype,
+      )))
+    }
+  }
+}
+
+// Explains why a codec rejected its parameters.
+fn check_params(spec: &CodecSpec) -> CoreResult<()> {
+  if spec.params.is_empty() {
+    Ok(())
+  } else if spec.name == ZSTD {
+    ZstdCodec::<Vec<u8>>::from_params(&spec.params).map(|_| ())
+  } else {
+    Err(CoreError::invalid(&format!(
+      "compression codec {} takes no parameters",
+      spec.name,
     )))
   }
 }
 
+/// Compresses several columns, returning each column's bytes in order.
+///
+/// Each column is given by its data type, nested list depth, values, and
+/// codec.
+/// The output is exactly what compressing each column on its own would
+/// produce, but columns are compressed concurrently across the available
+/// cores, which speeds up bulk ingestion of wide tables.
+/// Every codec is checked before any compression starts.
+pub fn compress_columns(
+  columns: &[(DataType, u8, &[FieldValue], &str)],
+) -> CoreResult<Vec<Vec<u8>>> {
+  let n_threads = std::thread::available_parallelism()
+    .map(|n| n.get())
+    .unwrap_or(1);
+  compress_columns_on(columns, n_threads)
+}
+
+fn compress_columns_on(
+  columns: &[(DataType, u8, &[FieldValue], &str)],
+  n_threads: usize,
+) -> CoreResult<Vec<Vec<u8>>> {
+  let codecs = columns.iter()
+    .map(|&(dtype, _, _, codec)| new_codec(dtype, codec))
+    .collect::<CoreResult<Vec<_>>>()?;
+  let jobs = codecs.iter()
+    .zip(columns)
+    .map(|(codec, &(_, nested_list_depth, values, _))| (codec, nested_list_depth, values))
+    .collect::<Vec<_>>();
+
+  let n_threads = n_threads.clamp(1, jobs.len().max(1));
+  if n_threads == 1 {
+    return jobs.iter()
+      .map(|(codec, nested_list_depth, values)| codec.compress(values, *nested_list_depth))
+      .collect();
+  }
+
+  let group_size = jobs.len().div_ceil(n_threads);
+  let group_results = std::thread::scope(|scope| {
+    let handles = jobs.chunks(group_size)
+      .map(|group| scope.spawn(move || {
+        group.iter()
+          .map(|(codec, nested_list_depth, values)| codec.compress(values, *nested_list_depth))
+          .collect::<CoreResult<Vec<_>>>()
+      }))
+      .collect::<Vec<_>>();
+    handles.into_iter()
+      .map(|handle| handle.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload)))
+      .collect::<Vec<_>>()
+  });
+
+  let mut res = Vec::with_capacity(columns.len());
+  for group_bytes in group_results {
+    res.extend(group_bytes?);
+  }
+  Ok(res)
+}
+
+/// Decompresses only a column's repetition levels, leaving its atoms
+/// compressed.
+///
+/// The levels describe the column's shape: which values are null, and how
+/// long each list and non-atomic value is.
+/// See [`rep_levels`][crate::rep_levels] for how to read them.
+pub fn decompress_shape(dtype: DataType, codec: &str, bytes: &[u8]) -> CoreResult<Vec<u8>> {
+  Ok(new_codec(dtype, codec)?.decompress_rep_levels(bytes)?.levels)
+}
+
+/// Guesses which codec compressed a column of the data type.
+///
+/// This is best-effort, for recovering or inspecting data whose codec string
+/// was lost.
+/// Every codec stores the same repetition levels first, so this looks past
+/// them at the compressed atoms: q_compress and zstd are recognized by their
+/// format magic, and bitpacked bools by their length.
+/// Sparse data is recognized by its own magic.
+/// Delta-encoded data is reported as q_compress, which decodes it the same
+/// way.
+/// Returns `None` for raw data, since it has no magic and either byte order
+/// would decode, and for anything unrecognized.
+pub fn detect_codec(dtype: DataType, bytes: &[u8]) -> Option<String> {
+  if sparse_codec::is_sparse(bytes) {
+    return Some(SPARSE.to_string());
+  }
+  let atom_bytes = value_codec(dtype, &choose_codec(dtype))?
+    .decompress_rep_levels(bytes)
+    .ok()?
+    .remaining_bytes;
+  let codec = if atom_bytes.starts_with(&Q_COMPRESS_MAGIC) {
+    Q_COMPRESS
+  } else if atom_bytes.starts_with(&ZSTD_MAGIC) {
+    ZSTD
+  } else if is_bitpacked(&atom_bytes) {
+    BITPACK
+  } else {
+    return None;
+  };
+
+  if supports(dtype, codec) {
+    Some(codec.to_string())
+  } else {
+    None
+  }
+}
+
+// Bitpacked bools are a big-endian u32 count followed by exactly enough bytes
+// to hold that many bits.
+fn is_bitpacked(bytes: &[u8]) -> bool {
+  if bytes.len() < 4 {
+    return false;
+  }
+  let mut count_bytes = [0_u8; 4];
+  count_bytes.copy_from_slice(&bytes[..4]);
+  let n = u32::from_be_bytes(count_bytes) as usize;
+  bytes.len() - 4 == n.div_ceil(8)
+}
+
 pub fn choose_codec(dtype: DataType) -> String {
   match dtype {
     DataType::Int64 => Q_COMPRESS.to_string(),
@@ -43,3 +207,292 @@ pub fn choose_codec(dtype: DataType) -> String {
   }
 }
 
+/// Chooses a codec for the data type that suits the values.
+///
+/// Like [`choose_codec`], but picks [`SPARSE`] for columns where few
+/// enough values are present that storing only their positions pays off,
+/// as long as the column has no more nulls than sparse data can hold.
+pub fn choose_codec_for_values(dtype: DataType, values: &[FieldValue]) -> String {
+  let n_present = values.iter()
+    .filter(|fv| fv.value.is_some())
+    .count();
+  let is_sparse = n_present as f64 <= SPARSE_MAX_DENSITY * values.len() as f64;
+  let fits_sparse = (values.len() - n_present) as u64 <= sparse_codec::MAX_NULLS;
+  if !values.is_empty() && is_sparse && fits_sparse {
+    SPARSE.to_string()
+  } else {
+    choose_codec(dtype)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::RepeatedFieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+
+  use crate::utils::ALL_DTYPES;
+
+  use super::*;
+  use super::super::{DELTA, RAW_BE, RAW_LE};
+
+  #[test]
+  fn test_supports() {
+    let supported = vec![
+      (DataType::Int64, Q_COMPRESS),
+      (DataType::Int64, DELTA),
+      (DataType::Int64, RAW_BE),
+      (DataType::Float32, RAW_LE),
+      (DataType::Float64, Q_COMPRESS),
+      (DataType::Bool, BITPACK),
+      (DataType::TimestampMicros, Q_COMPRESS),
+      (DataType::String, ZSTD),
+      (DataType::Bytes, ZSTD),
+    ];
+    for (dtype, codec) in supported {
+      assert!(supports(dtype, codec), "{:?} {}", dtype, codec);
+      assert!(new_codec(dtype, codec).is_ok());
+    }
+
+    let unsupported = vec![
+      (DataType::Int64, ZSTD),
+      (DataType::Int64, BITPACK),
+      (DataType::String, Q_COMPRESS),
+      (DataType::Bytes, DELTA),
+      (DataType::Bool, RAW_BE),
+      (DataType::Float64, "not_a_codec"),
+      (DataType::Int64, ""),
+    ];
+    for (dtype, codec) in unsupported {
+      assert!(!supports(dtype, codec), "{:?} {}", dtype, codec);
+      assert!(new_codec(dtype, codec).is_err());
+    }
+  }
+
+  #[test]
+  fn test_parameterized_codecs() -> CoreResult<()> {
+    let fvs = (0..100)
+      .map(|i| FieldValue { value: Some(Value::StringVal(format!("value {}", i % 7))) })
+      .collect::<Vec<_>>();
+    let plain = new_codec(DataType::String, ZSTD)?;
+    for codec in ["zstd:level=1", "zstd:level=19"] {
+      assert!(supports(DataType::String, codec));
+      let bytes = new_codec(DataType::String, codec)?.compress(&fvs, 0)?;
+      // the level doesn't matter when decompressing
+      assert_eq!(plain.decompress(&bytes, 0)?, fvs);
+      assert_eq!(new_codec(DataType::Bytes, codec)?.dtype(), DataType::Bytes);
+    }
+
+    let rejected = vec![
+      (DataType::String, "zstd:level=999", "invalid zstd level"),
+      (DataType::String, "zstd:level=high", "invalid zstd level"),
+      (DataType::String, "zstd:window=20", "unknown zstd parameter"),
+      (DataType::String, "zstd:level", "malformed parameter"),
+      (DataType::String, "zstd:", "malformed parameter"),
+      (DataType::Int64, "q_compress:level=3", "takes no parameters"),
+      (DataType::Int64, "zstd:level=3", "unavailable"),
+    ];
+    for (dtype, codec, message) in rejected {
+      assert!(!supports(dtype, codec), "{}", codec);
+      let err = new_codec(dtype, codec).err().unwrap();
+      assert!(err.to_string().contains(message), "{}: {}", codec, err);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_decompress_shape() -> CoreResult<()> {
+    let string_list = |strings: &[&str]| FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue {
+        vals: strings.iter()
+          .map(|s| FieldValue { value: Some(Value::StringVal(s.to_string())) })
+          .collect(),
+      })),
+    };
+    let fvs = vec![
+      string_list(&["abc", "de"]),
+      FieldValue::default(),
+      string_list(&["f"]),
+      string_list(&[""]),
+      string_list(&[]),
+    ];
+    let bytes = new_codec(DataType::String, ZSTD)?.compress(&fvs, 1)?;
+    assert_eq!(
+      decompress_shape(DataType::String, ZSTD, &bytes)?,
+      vec![3, 3, 3, 2, 3, 3, 2, 1, 0, 3, 2, 1, 2, 1, 1],
+    );
+    assert!(decompress_shape(DataType::String, Q_COMPRESS, &bytes).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_chosen_codecs_supported() {
+    for dtype in ALL_DTYPES {
+      assert!(supports(dtype, &choose_codec(dtype)));
+    }
+  }
+
+  #[test]
+  fn test_detect_codec() -> CoreResult<()> {
+    let ints = (0..100)
+      .map(|i| FieldValue { value: Some(Value::Int64Val(i * i)) })
+      .collect::<Vec<_>>();
+    let strings = vec![
+      FieldValue { value: Some(Value::StringVal("abc".to_string())) },
+      FieldValue::default(),
+    ];
+    let bools = vec![
+      FieldValue { value: Some(Value::BoolVal(true)) },
+      FieldValue { value: Some(Value::BoolVal(false)) },
+    ];
+    let cases = vec![
+      (DataType::Int64, Q_COMPRESS, &ints, Some(Q_COMPRESS)),
+      (DataType::Int64, DELTA, &ints, Some(Q_COMPRESS)),
+      (DataType::Int64, RAW_LE, &ints, None),
+      (DataType::String, ZSTD, &strings, Some(ZSTD)),
+      (DataType::Bool, Q_COMPRESS, &bools, Some(Q_COMPRESS)),
+      (DataType::Bool, BITPACK, &bools, Some(BITPACK)),
+    ];
+    for (dtype, codec, fvs, expected) in cases {
+      let value_codec = new_codec(dtype, codec)?;
+      let expected = expected.map(|c| c.to_string());
+      assert_eq!(detect_codec(dtype, &value_codec.compress(fvs, 0)?), expected, "{}", codec);
+      let with_stats = value_codec.compress_with_stats(fvs, 0)?;
+      assert_eq!(detect_codec(dtype, &with_stats), expected, "{} with stats", codec);
+    }
+
+    // zstd bytes for a dtype zstd can't hold
+    let zstd_bytes = new_codec(DataType::String, ZSTD)?.compress(&strings, 0)?;
+    assert_eq!(detect_codec(DataType::Int64, &zstd_bytes), None);
+    assert_eq!(detect_codec(DataType::Int64, b"garbage"), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_compress_columns_matches_individual() -> CoreResult<()> {
+    let ints = (0..1000)
+      .map(|i| FieldValue { value: Some(Value::Int64Val(i % 17)) })
+      .collect::<Vec<_>>();
+    let strings = (0..300)
+      .map(|i| FieldValue {
+        value: if i % 5 == 0 { None } else { Some(Value::StringVal(format!("s{}", i))) },
+      })
+      .collect::<Vec<_>>();
+    let bool_lists = vec![
+      FieldValue {
+        value: Some(Value::ListVal(RepeatedFieldValue {
+          vals: vec![FieldValue { value: Some(Value::BoolVal(true)) }],
+        })),
+      },
+      FieldValue::default(),
+    ];
+    let columns: Vec<(DataType, u8, &[FieldValue], &str)> = vec![
+      (DataType::Int64, 0, &ints, Q_COMPRESS),
+      (DataType::String, 0, &strings, ZSTD),
+      (DataType::Bool, 1, &bool_lists, BITPACK),
+      (DataType::Int64, 0, &ints[..10], RAW_LE),
+      (DataType::Float64, 0, &[], Q_COMPRESS),
+    ];
+
+    let individual = columns.iter()
+      .map(|(dtype, depth, values, codec)| new_codec(*dtype, codec)?.compress(values, *depth))
+      .collect::<CoreResult<Vec<_>>>()?;
+    assert_eq!(compress_columns(&columns)?, individual);
+    for n_threads in [1, 2, 3, 16] {
+      assert_eq!(compress_columns_on(&columns, n_threads)?, individual, "{} threads", n_threads);
+    }
+
+    assert!(compress_columns(&[]).unwrap().is_empty());
+    let bad_codec: [(DataType, u8, &[FieldValue], &str); 2] = [
+      (DataType::Int64, 0, &ints, Q_COMPRESS),
+      (DataType::String, 0, &strings, Q_COMPRESS),
+    ];
+    assert!(compress_columns_on(&bad_codec, 2).is_err());
+    Ok(())
+  }
+
+  // two distinct non-null values of the data type
+  fn sample_values(dtype: DataType) -> [Value; 2] {
+    match dtype {
+      DataType::String => [Value::StringVal("".to_string()), Value::StringVal("ab".to_string())],
+      DataType::Int64 => [Value::Int64Val(i64::MIN), Value::Int64Val(7)],
+      DataType::Bytes => [Value::BytesVal(vec![]), Value::BytesVal(vec![0, 255])],
+      DataType::Bool => [Value::BoolVal(true), Value::BoolVal(false)],
+      DataType::Float32 => [Value::Float32Val(-0.5), Value::Float32Val(f32::MAX)],
+      DataType::Float64 => [Value::Float64Val(1.5), Value::Float64Val(f64::MIN_POSITIVE)],
+      DataType::TimestampMicros => [
+        TimestampMicros::from_secs_and_nanos(-1, 0).to_value(),
+        TimestampMicros::from_secs_and_nanos(1_645_000_000, 123_000).to_value(),
+      ],
+    }
+  }
+
+  #[test]
+  fn test_empty_and_tiny_columns_round_trip() -> CoreResult<()> {
+    let codecs = [Q_COMPRESS, BITPACK, DELTA, ZSTD, RAW_BE, RAW_LE, SPARSE];
+    let fv = |value: &Value| FieldValue { value: Some(value.clone()) };
+    let list = |vals: Vec<FieldValue>| FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+    };
+    let null = FieldValue::default();
+
+    let mut n_pairs = 0;
+    for dtype in ALL_DTYPES {
+      let [a, b] = sample_values(dtype);
+      let flat_cases = [
+        vec![],
+        vec![fv(&a)],
+        vec![null.clone()],
+        vec![fv(&a), fv(&b)],
+        vec![null.clone(), null.clone()],
+        vec![null.clone(), fv(&b)],
+      ];
+      let nested_cases = [
+        vec![],
+        vec![list(vec![])],
+        vec![list(vec![fv(&a)])],
+        vec![null.clone()],
+        vec![list(vec![fv(&a), fv(&b)]), list(vec![])],
+      ];
+      for codec in codecs {
+        let value_codec = match value_codec(dtype, codec) {
+          Some(value_codec) => value_codec,
+          None => continue,
+        };
+        n_pairs += 1;
+        let cases = flat_cases.iter().map(|fvs| (0, fvs))
+          .chain(nested_cases.iter().map(|fvs| (1, fvs)));
+        for (depth, fvs) in cases {
+          let context = format!("{:?} {} depth {} with {} values", dtype, codec, depth, fvs.len());
+          let bytes = value_codec.compress(fvs, depth)
+            .map_err(|e| e.with_context(&context))?;
+          assert_eq!(&value_codec.decompress(&bytes, depth)?, fvs, "{}", context);
+          assert_eq!(value_codec.decompress_rep_levels(&bytes)?.levels.is_empty(), fvs.is_empty(), "{}", context);
+
+          let with_stats = value_codec.compress_with_stats(fvs, depth)
+            .map_err(|e| e.with_context(&context))?;
+          assert_eq!(&value_codec.decompress(&with_stats, depth)?, fvs, "{} with stats", context);
+
+          let appended = value_codec.append(&bytes, fvs, depth)?;
+          let mut expected = fvs.clone();
+          expected.extend(fvs.iter().cloned());
+          assert_eq!(value_codec.decompress(&appended, depth)?, expected, "{} appended", context);
+        }
+      }
+    }
+    // every codec supports at least one data type
+    assert!(n_pairs >= codecs.len());
+    Ok(())
+  }
+
+  #[test]
+  fn test_codec_dtypes_match() {
+    let codecs = [Q_COMPRESS, BITPACK, DELTA, ZSTD, RAW_BE, RAW_LE];
+    for dtype in ALL_DTYPES {
+      for codec in codecs {
+        if let Some(value_codec) = value_codec(dtype, codec) {
+          assert_eq!(value_codec.dtype(), dtype, "codec {}", codec);
+        }
+      }
+    }
+  }
+}
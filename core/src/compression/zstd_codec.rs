@@ -1,24 +1,160 @@
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
 use crate::primitives::Primitive;
-use crate::errors::CoreResult;
+use crate::errors::{CoreError, CoreResult};
 
-use super::Codec;
-use std::marker::PhantomData;
+use super::{Codec, CodecSpec, ZSTD};
 
 const ZSTD_LEVEL: i32 = 5;
 
+/// Compresses byte atoms with zstd, optionally against a shared dictionary.
+///
+/// A dictionary lets many small segments of a column with recurring values,
+/// like low-cardinality strings, share the common content instead of each
+/// embedding it.
+/// Data compressed with a dictionary can only be decompressed by a codec
+/// holding the same dictionary; since codec strings don't identify the
+/// dictionary, storing and distributing it is up to the caller.
+///
+/// The compression level can be set with the codec string `zstd:level=N`.
+/// It only affects compression; data decompresses the same at any level.
 #[derive(Clone, Debug, Default)]
 pub struct ZstdCodec<P: Primitive> {
+  dictionary: Option<Arc<[u8]>>,
+  level: Option<i32>,
   _phantom: PhantomData<P>,
 }
 
+impl<P: Primitive> ZstdCodec<P> {
+  pub fn with_dictionary(dictionary: Arc<[u8]>) -> Self {
+    ZstdCodec {
+      dictionary: Some(dictionary),
+      ..Default::default()
+    }
+  }
+
+  /// Creates a codec from the parameters of a `zstd` codec string.
+  ///
+  /// The only parameter is `level`, which must be a compression level zstd
+  /// accepts.
+  pub fn from_params(params: &[(&str, &str)]) -> CoreResult<Self> {
+    let mut res = Self::default();
+    for &(key, value) in params {
+      match key {
+        "level" => {
+          let level = value.parse::<i32>()
+            .ok()
+            .filter(|level| zstd::compression_level_range().contains(level))
+            .ok_or_else(|| CoreError::invalid(&format!(
+              "invalid zstd level {:?}; expected an integer in {:?}",
+              value,
+              zstd::compression_level_range(),
+            )))?;
+          res.level = Some(level);
+        },
+        _ => return Err(CoreError::invalid(&format!(
+          "unknown zstd parameter {}",
+          key,
+        ))),
+      }
+    }
+    Ok(res)
+  }
+
+  fn level(&self) -> i32 {
+    self.level.unwrap_or(ZSTD_LEVEL)
+  }
+}
+
 impl<P: Primitive<A=u8>> Codec for ZstdCodec<P> {
   type P = P;
 
   fn compress_atoms(&self, atoms: &[u8]) -> CoreResult<Vec<u8>> {
-    Ok(zstd::encode_all(atoms, ZSTD_LEVEL)?)
+    match &self.dictionary {
+      None => Ok(zstd::encode_all(atoms, self.level())?),
+      Some(dictionary) => {
+        let mut encoder = zstd::stream::Encoder::with_dictionary(
+          Vec::new(),
+          self.level(),
+          dictionary,
+        )?;
+        encoder.write_all(atoms)?;
+        Ok(encoder.finish()?)
+      }
+    }
   }
 
   fn decompress_atoms(&self, bytes: &[u8]) -> CoreResult<Vec<u8>> {
-    Ok(zstd::decode_all(bytes)?)
+    match &self.dictionary {
+      None => Ok(zstd::decode_all(bytes)?),
+      Some(dictionary) => {
+        let mut decoder = zstd::stream::Decoder::with_dictionary(bytes, dictionary)?;
+        let mut res = Vec::new();
+        decoder.read_to_end(&mut res)?;
+        Ok(res)
+      }
+    }
+  }
+}
+
+pub fn new_zstd_codec<P: Primitive<A=u8>>(codec: &str) -> Option<Box<dyn Codec<P=P>>> {
+  let spec = CodecSpec::parse(codec).ok()?;
+  if spec.name != ZSTD {
+    return None;
+  }
+  let codec = ZstdCodec::<P>::from_params(&spec.params).ok()?;
+  Some(Box::new(codec))
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes from sample values,
+/// for use with [`ZstdCodec::with_dictionary`].
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> CoreResult<Vec<u8>> {
+  Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use pancake_db_idl::dml::FieldValue;
+  use pancake_db_idl::dml::field_value::Value;
+
+  use crate::compression::ValueCodec;
+
+  use super::*;
+
+  fn string_fvs(strs: &[String]) -> Vec<FieldValue> {
+    strs.iter()
+      .map(|s| FieldValue {
+        value: Some(Value::StringVal(s.clone())),
+      })
+      .collect()
+  }
+
+  fn value_codec(codec: ZstdCodec<String>) -> Box<dyn ValueCodec> {
+    let codec: Box<dyn Codec<P=String>> = Box::new(codec);
+    Box::new(codec)
+  }
+
+  #[test]
+  fn test_shared_dictionary() -> CoreResult<()> {
+    let vocab = ["category_electronics", "category_groceries", "category_furniture", "category_toys"];
+    let strs = (0..4000)
+      .map(|i| format!("{}/{}", vocab[(i * 7) % vocab.len()], i % 13))
+      .collect::<Vec<_>>();
+    let dictionary: Arc<[u8]> = train_dictionary(&strs, 4096)?.into();
+    let shared = value_codec(ZstdCodec::with_dictionary(dictionary));
+
+    let segment0 = string_fvs(&strs[..50]);
+    let segment1 = string_fvs(&strs[50..80]);
+    let bytes0 = shared.compress(&segment0, 0)?;
+    let bytes1 = shared.compress(&segment1, 0)?;
+    assert_eq!(shared.decompress(&bytes0, 0)?, segment0);
+    assert_eq!(shared.decompress(&bytes1, 0)?, segment1);
+
+    let plain = value_codec(ZstdCodec::default());
+    assert!(bytes0.len() < plain.compress(&segment0, 0)?.len());
+    assert!(plain.decompress(&bytes0, 0).is_err());
+    Ok(())
   }
 }
@@ -1,6 +1,6 @@
 use std::fmt;
 use crate::errors::{CoreError, CoreResult};
-use super::{NULL_BYTE, ESCAPE_BYTE};
+use super::ESCAPE_BYTE;
 use std::fmt::{Formatter, Debug};
 
 #[derive(Clone)]
@@ -15,7 +15,7 @@ impl<'a> Debug for ByteReader<'a> {
       f,
       "ByteReader at {}; prev: {:?} next: {:?}",
       self.i,
-      &self.bytes[0.max(self.i - 10)..self.i],
+      &self.bytes[self.i.saturating_sub(10)..self.i],
       &self.bytes[self.i..self.bytes.len().min(self.i + 10)],
     )
   }
@@ -49,8 +49,16 @@ impl<'a> ByteReader<'a> {
   pub fn unescaped_read_one(&mut self) -> CoreResult<u8> {
     let b = self.read_one()?;
     if b == ESCAPE_BYTE {
-      Ok(!self.read_one()?)
-    } else if b >= NULL_BYTE {
+      if self.complete() {
+        return Err(CoreError::corrupt("escape byte at end of data"));
+      }
+      let escaped = !self.read_one()?;
+      // only reserved bytes are ever escaped
+      if !super::is_reserved_byte(escaped) {
+        return Err(CoreError::corrupt(&format!("invalid escape sequence at {}", self.i - 2)));
+      }
+      Ok(escaped)
+    } else if super::is_reserved_byte(b) {
       Err(CoreError::corrupt(&format!("unexpected unescaped byte at {}", self.i)))
     } else {
       Ok(b)
@@ -76,3 +84,37 @@ impl<'a> ByteReader<'a> {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use crate::encoding::{COUNT_BYTE, NULL_BYTE};
+  use crate::errors::CoreErrorKind;
+
+  use super::*;
+
+  fn assert_corrupt<T: Debug>(res: CoreResult<T>) {
+    match res {
+      Err(e) => assert_eq!(e.kind, CoreErrorKind::Corrupt),
+      Ok(x) => panic!("expected corrupt error but read {:?}", x),
+    }
+  }
+
+  #[test]
+  fn test_escapes() -> CoreResult<()> {
+    let mut reader = ByteReader::new(&[ESCAPE_BYTE, !NULL_BYTE, 7, ESCAPE_BYTE, !ESCAPE_BYTE]);
+    assert_eq!(reader.unescaped_read_n(3)?, vec![NULL_BYTE, 7, ESCAPE_BYTE]);
+    assert!(reader.complete());
+
+    assert_corrupt(ByteReader::new(&[5, ESCAPE_BYTE]).unescaped_read_n(2));
+    assert_corrupt(ByteReader::new(&[ESCAPE_BYTE, 0x10]).unescaped_read_one());
+    assert_corrupt(ByteReader::new(&[NULL_BYTE]).unescaped_read_one());
+    assert_corrupt(ByteReader::new(&[COUNT_BYTE]).unescaped_read_one());
+    Ok(())
+  }
+
+  #[test]
+  fn test_truncated() {
+    assert_corrupt(ByteReader::new(&[]).read_one());
+    assert_corrupt(ByteReader::new(&[0]).unescaped_read_u16());
+    assert_corrupt(ByteReader::new(&[0, 0, 0]).unescaped_read_n(4));
+  }
+}
@@ -2,6 +2,7 @@ use std::marker::PhantomData;
 
 use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
 use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dtype::DataType;
 
 use crate::encoding::byte_reader::ByteReader;
 use crate::errors::{CoreError, CoreResult};
@@ -104,10 +105,24 @@ impl<P: Primitive> Decodable<P> for ByteIdx {
 }
 
 pub trait Decoder<Output> {
+  /// Returns the data type of the primitive this decodes.
+  fn dtype(&self) -> DataType;
   fn decode_limited(&self, bytes: &[u8], limit: usize) -> CoreResult<Vec<Output>>;
   fn decode(&self, bytes: &[u8]) -> CoreResult<Vec<Output>> {
     self.decode_limited(bytes, usize::MAX)
   }
+
+  /// Like [`decode`][Decoder::decode], but decodes each value only when the
+  /// iterator reaches it, so callers can stop early without building the
+  /// rest.
+  ///
+  /// The provided implementation decodes everything first.
+  fn decode_iter<'a>(&'a self, bytes: &'a [u8]) -> Box<dyn Iterator<Item=CoreResult<Output>> + 'a> where Output: 'a {
+    match self.decode(bytes) {
+      Ok(values) => Box::new(values.into_iter().map(Ok)),
+      Err(e) => Box::new(std::iter::once(Err(e))),
+    }
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -118,34 +133,88 @@ pub struct DecoderImpl<P: Primitive, H> where H: Decodable<P> {
 }
 
 impl<P: Primitive, H> Decoder<H> for DecoderImpl<P, H> where H: Decodable<P> {
+  fn dtype(&self) -> DataType {
+    P::DTYPE
+  }
+
   fn decode_limited(
     &self,
     bytes: &[u8],
     limit: usize
   ) -> CoreResult<Vec<H>> {
-    let mut res = Vec::new();
-    let mut reader = ByteReader::new(bytes);
-    while !reader.complete() && res.len() < limit {
-      let b0 = reader.read_one()?;
-      if b0 == NULL_BYTE {
-        res.push(H::handle_null(reader.get_byte_idx()));
-      } else if b0 == COUNT_BYTE {
-        let count_bytes = utils::try_byte_array::<4>(&reader.unescaped_read_n(4)?)?;
-        let count = u32::from_be_bytes(count_bytes) as usize;
-        if res.is_empty() {
-          for _ in 0..count {
-            res.push(H::handle_null(reader.get_byte_idx()));
-          }
-        } else if res.len() != count {
-          return Err(CoreError::corrupt("in-file count did not match number of decoded entries"));
-        }
-      } else {
-        reader.back_one();
-        let v = self.decode_value(&mut reader, 0)?;
-        res.push(v);
+    self.iter(bytes).take(limit).collect()
+  }
+
+  fn decode_iter<'a>(&'a self, bytes: &'a [u8]) -> Box<dyn Iterator<Item=CoreResult<H>> + 'a> where H: 'a {
+    Box::new(self.iter(bytes))
+  }
+}
+
+// Yields decoded values in order, stopping after the first error.
+struct DecodeIter<'a, P: Primitive, H> where H: Decodable<P> {
+  decoder: &'a DecoderImpl<P, H>,
+  reader: ByteReader<'a>,
+  n_decoded: usize,
+  // nulls of a leading count not yet yielded, and the byte index after
+  // the count
+  pending_nulls: usize,
+  pending_nulls_byte_idx: usize,
+  failed: bool,
+}
+
+impl<'a, P: Primitive, H> DecodeIter<'a, P, H> where H: Decodable<P> {
+  // Reads the next framing byte and the value it starts, if any.
+  fn read_next(&mut self) -> CoreResult<Option<H>> {
+    let b0 = self.reader.read_one()?;
+    if b0 == NULL_BYTE {
+      Ok(Some(H::handle_null(self.reader.get_byte_idx())))
+    } else if b0 == COUNT_BYTE {
+      let count_bytes = utils::try_byte_array::<4>(&self.reader.unescaped_read_n(4)?)?;
+      let count = u32::from_be_bytes(count_bytes) as usize;
+      if self.n_decoded == 0 {
+        // implicit nulls are yielded one at a time and take no memory until
+        // then, so callers bound the work with a limit or by stopping early
+        self.pending_nulls = count;
+        self.pending_nulls_byte_idx = self.reader.get_byte_idx();
+      } else if self.n_decoded != count {
+        return Err(CoreError::corrupt("in-file count did not match number of decoded entries"));
+      }
+      Ok(None)
+    } else {
+      self.reader.back_one();
+      Ok(Some(self.decoder.decode_value(&mut self.reader)?))
+    }
+  }
+}
+
+impl<'a, P: Primitive, H> Iterator for DecodeIter<'a, P, H> where H: Decodable<P> {
+  type Item = CoreResult<H>;
+
+  fn next(&mut self) -> Option<CoreResult<H>> {
+    if self.failed {
+      return None;
+    }
+    loop {
+      if self.pending_nulls > 0 {
+        self.pending_nulls -= 1;
+        self.n_decoded += 1;
+        return Some(Ok(H::handle_null(self.pending_nulls_byte_idx)));
+      }
+      if self.reader.complete() {
+        return None;
+      }
+      match self.read_next() {
+        Ok(Some(value)) => {
+          self.n_decoded += 1;
+          return Some(Ok(value));
+        },
+        Ok(None) => (),
+        Err(e) => {
+          self.failed = true;
+          return Some(Err(e));
+        },
       }
     }
-    Ok(res)
   }
 }
 
@@ -158,29 +227,61 @@ impl<P: Primitive, H> DecoderImpl<P, H> where H: Decodable<P> {
     }
   }
 
-  fn decode_value(&self, reader: &mut ByteReader, current_depth: u8) -> CoreResult<H> {
-    if current_depth == self.nested_list_depth {
-      let atoms = if P::IS_ATOMIC {
-        let bytes = reader.unescaped_read_n(P::A::BYTE_SIZE)?;
-        vec![P::A::try_from_bytes(&bytes)?]
+  fn iter<'a>(&'a self, bytes: &'a [u8]) -> DecodeIter<'a, P, H> {
+    DecodeIter {
+      decoder: self,
+      reader: ByteReader::new(bytes),
+      n_decoded: 0,
+      pending_nulls: 0,
+      pending_nulls_byte_idx: 0,
+      failed: false,
+    }
+  }
+
+  // Iterative, like `rep_levels::extend_single_levels_and_atoms`.
+  fn decode_value(&self, reader: &mut ByteReader) -> CoreResult<H> {
+    // the outputs so far and expected length of each list being decoded
+    let mut stack: Vec<(Vec<H>, usize)> = Vec::new();
+    loop {
+      let current_depth = stack.len() as u8;
+      let mut completed = if current_depth == self.nested_list_depth {
+        let atoms = if P::IS_ATOMIC {
+          let bytes = reader.unescaped_read_n(P::A::BYTE_SIZE)?;
+          vec![P::A::try_from_bytes(&bytes)?]
+        } else {
+          let len = reader.unescaped_read_u16()? as usize;
+          let mut atoms = Vec::with_capacity(len);
+          for _ in 0..len {
+            let bytes = reader.unescaped_read_n(P::A::BYTE_SIZE)?;
+            atoms.push(P::A::try_from_bytes(&bytes)?);
+          }
+          atoms
+        };
+        H::handle_atoms(atoms, current_depth, reader.get_byte_idx())?
       } else {
         let len = reader.unescaped_read_u16()? as usize;
-        let mut atoms = Vec::with_capacity(len);
-        for _ in 0..len {
-          let bytes = reader.unescaped_read_n(P::A::BYTE_SIZE)?;
-          atoms.push(P::A::try_from_bytes(&bytes)?);
+        if len > 0 {
+          stack.push((Vec::with_capacity(len), len));
+          continue;
         }
-        atoms
+        H::combine(Vec::new(), current_depth, reader.get_byte_idx())
       };
-      H::handle_atoms(atoms, current_depth, reader.get_byte_idx())
-    } else {
-      let len = reader.unescaped_read_u16()? as usize;
-      let mut outputs = Vec::with_capacity(len);
-      for _ in 0..len {
-        let child = self.decode_value(reader, current_depth + 1)?;
-        outputs.push(child);
+
+      // hand the completed value to its parent, completing any lists it fills
+      loop {
+        let is_full = match stack.last_mut() {
+          None => return Ok(completed),
+          Some((outputs, len)) => {
+            outputs.push(completed);
+            outputs.len() == *len
+          },
+        };
+        if !is_full {
+          break;
+        }
+        let (outputs, _) = stack.pop().unwrap();
+        completed = H::combine(outputs, stack.len() as u8, reader.get_byte_idx());
       }
-      Ok(H::combine(outputs, current_depth, reader.get_byte_idx()))
     }
   }
 }
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use pancake_db_idl::dml::FieldValue;
 use pancake_db_idl::dtype::DataType;
 use q_compress::data_types::TimestampMicros;
@@ -8,16 +10,65 @@ pub use decoder::Decoder;
 pub use decoder::DecoderImpl;
 pub use encoder::Encoder;
 pub use encoder::EncoderImpl;
+pub use encoder::NestedValue;
+
+use byte_reader::ByteReader;
 
+use crate::errors::CoreResult;
 use crate::primitives::Primitive;
+use crate::rep_levels;
 
 mod byte_reader;
 mod decoder;
 mod encoder;
 
-const ESCAPE_BYTE: u8 = 255;
-const COUNT_BYTE: u8 = 254;
-const NULL_BYTE: u8 = 253;
+// The uncompressed encoding frames each top-level value as either
+// NULL_BYTE for a null or the value's escaped bytes: a big-endian u16
+// length before each list and each non-atomic primitive, then its elements
+// or atoms.
+// A COUNT_BYTE followed by an escaped big-endian u32 records how many
+// values precede it, or, at the start of a buffer, that many nulls.
+// Any data byte that would collide with a reserved byte is written as
+// ESCAPE_BYTE followed by its complement, so reserved bytes only ever
+// appear as framing.
+
+/// Precedes the complement of an escaped data byte.
+pub const ESCAPE_BYTE: u8 = 255;
+/// Precedes an escaped big-endian u32 count of values.
+///
+/// At the start of a buffer it stands for that many nulls; elsewhere it
+/// must equal the number of values before it.
+pub const COUNT_BYTE: u8 = 254;
+/// Stands for a single null top-level value.
+pub const NULL_BYTE: u8 = 253;
+
+/// Returns whether the byte has a framing meaning in the uncompressed
+/// encoding, and so must be escaped when it appears in data.
+///
+/// ```
+/// use pancake_db_core::encoding::{self, COUNT_BYTE, ESCAPE_BYTE, NULL_BYTE};
+/// use pancake_db_idl::dml::FieldValue;
+/// use pancake_db_idl::dml::field_value::Value;
+/// use pancake_db_idl::dtype::DataType;
+///
+/// assert!(encoding::is_reserved_byte(NULL_BYTE));
+/// assert!(!encoding::is_reserved_byte(b'a'));
+///
+/// let fvs = vec![
+///   FieldValue { value: Some(Value::StringVal("a\u{fe}".to_string())) },
+///   FieldValue::default(),
+/// ];
+/// let bytes = encoding::new_encoder(DataType::String, 0).encode(&fvs)?;
+/// // the string's 0xc3 0xbe UTF-8 bytes don't collide, so nothing is escaped
+/// assert_eq!(bytes, vec![0, 3, b'a', 0xc3, 0xbe, NULL_BYTE]);
+///
+/// let counted = encoding::new_encoder(DataType::Int64, 0).encode_count(255);
+/// assert_eq!(counted, vec![COUNT_BYTE, 0, 0, 0, ESCAPE_BYTE, 0]);
+/// # Ok::<(), pancake_db_core::errors::CoreError>(())
+/// ```
+pub fn is_reserved_byte(b: u8) -> bool {
+  b >= NULL_BYTE
+}
 
 fn encoder_for<P: Primitive>(nested_list_depth: u8) -> Box<dyn Encoder> {
   Box::new(EncoderImpl::<P>::new(nested_list_depth))
@@ -31,6 +82,24 @@ fn byte_idx_decoder_for<P: Primitive>(nested_list_depth: u8) -> Box<dyn Decoder<
   Box::new(DecoderImpl::<P, ByteIdx>::new(nested_list_depth))
 }
 
+/// Returns whether values of the data type can be encoded and decoded at
+/// the nested list depth.
+///
+/// Every data type has an encoding, but nesting deep enough to overflow the
+/// repetition levels can't be compressed or decoded into levels, so reading
+/// such a column would fail partway through.
+pub fn supports(dtype: DataType, nested_list_depth: u8) -> bool {
+  match dtype {
+    DataType::Int64 => rep_levels::supports_depth::<i64>(nested_list_depth),
+    DataType::String => rep_levels::supports_depth::<String>(nested_list_depth),
+    DataType::Float32 => rep_levels::supports_depth::<f32>(nested_list_depth),
+    DataType::Float64 => rep_levels::supports_depth::<f64>(nested_list_depth),
+    DataType::Bytes => rep_levels::supports_depth::<Vec<u8>>(nested_list_depth),
+    DataType::Bool => rep_levels::supports_depth::<bool>(nested_list_depth),
+    DataType::TimestampMicros => rep_levels::supports_depth::<TimestampMicros>(nested_list_depth),
+  }
+}
+
 pub fn new_encoder(dtype: DataType, nested_list_depth: u8) -> Box<dyn Encoder> {
   match dtype {
     DataType::Int64 => encoder_for::<i64>(nested_list_depth),
@@ -67,6 +136,63 @@ pub fn new_byte_idx_decoder(dtype: DataType, nested_list_depth: u8) -> Box<dyn D
   }
 }
 
+/// Finds the byte range of each value within uncompressed encoded data.
+///
+/// This applies only to the uncompressed encoding, such as the data from
+/// read responses without a codec; compressed data has no per-value byte
+/// positions.
+/// Ranges exclude count markers, so slicing the encoded bytes by a
+/// non-empty range and decoding the slice yields exactly that value.
+/// Implicit nulls declared by a leading count marker take no bytes, so
+/// their ranges are empty and sit just after the marker.
+pub fn decode_byte_ranges(
+  dtype: DataType,
+  nested_list_depth: u8,
+  bytes: &[u8],
+) -> CoreResult<Vec<Range<usize>>> {
+  let ends = new_byte_idx_decoder(dtype, nested_list_depth).decode(bytes)?;
+  let mut start = 0;
+  let mut res = Vec::with_capacity(ends.len());
+  for end in ends {
+    start = skip_count_markers(bytes, start)?;
+    res.push(start..end);
+    start = end;
+  }
+  Ok(res)
+}
+
+// Returns the index of the first byte at or after `start` that isn't part
+// of a count marker.
+fn skip_count_markers(bytes: &[u8], mut start: usize) -> CoreResult<usize> {
+  while bytes.get(start) == Some(&COUNT_BYTE) {
+    let mut reader = ByteReader::new(&bytes[start + 1..]);
+    reader.unescaped_read_n(4)?;
+    start += 1 + reader.get_byte_idx();
+  }
+  Ok(start)
+}
+
+/// Merges uncompressed encoded buffers into one that decodes to the
+/// concatenation of their values.
+///
+/// Buffers cannot simply be appended to each other, since a count marker
+/// at the start of a buffer denotes implicit nulls and count markers later
+/// in a buffer refer to positions within that buffer alone.
+/// Each buffer is validated by decoding it, and the result is re-encoded
+/// without count markers.
+pub fn concat_encoded(
+  dtype: DataType,
+  nested_list_depth: u8,
+  buffers: &[&[u8]],
+) -> CoreResult<Vec<u8>> {
+  let decoder = new_field_value_decoder(dtype, nested_list_depth);
+  let mut values = Vec::new();
+  for buffer in buffers {
+    values.extend(decoder.decode(buffer)?);
+  }
+  new_encoder(dtype, nested_list_depth).encode(&values)
+}
+
 #[cfg(test)]
 mod tests {
   use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
@@ -74,6 +200,7 @@ mod tests {
 
   use crate::errors::CoreResult;
   use crate::primitives::Primitive;
+  use crate::utils::ALL_DTYPES;
 
   use super::*;
   use crate::rep_levels::RepLevelsAndAtoms;
@@ -102,7 +229,7 @@ mod tests {
       Some(vec![0_u8, 255, 255, 254, 253]), // some bytes that need escaping
       None,
       Some(vec![]),
-      Some(vec![77].repeat(2081))
+      Some([77].repeat(2081))
     ];
 
     let values = bytess.iter()
@@ -136,7 +263,7 @@ mod tests {
 
     let values = ints.iter()
       .map(|maybe_x| FieldValue {
-        value: maybe_x.map(|x| Value::Int64Val(x)),
+        value: maybe_x.map(Value::Int64Val),
       })
       .collect::<Vec<FieldValue>>();
 
@@ -205,7 +332,7 @@ mod tests {
 
   #[test]
   fn test_decode_rep_levels() -> CoreResult<()> {
-    let strings = vec![
+    let strings = [
       Some(vec![
         "abc".to_string(),
         "de".to_string(),
@@ -250,4 +377,199 @@ mod tests {
     );
     Ok(())
   }
+
+  #[test]
+  fn test_byte_ranges_bracket_values() -> CoreResult<()> {
+    let values = [Some("abc"), None, Some(""), Some("\u{fe}\u{ff}z")]
+      .iter()
+      .map(|maybe_s| FieldValue {
+        value: maybe_s.map(|s| Value::StringVal(s.to_string())),
+      })
+      .collect::<Vec<_>>();
+    let encoded = encode::<String>(&values, 0)?;
+    let ranges = decode_byte_ranges(DataType::String, 0, &encoded)?;
+
+    assert_eq!(ranges.len(), values.len());
+    assert_eq!(ranges.last().unwrap().end, encoded.len());
+    for (range, value) in ranges.iter().zip(&values) {
+      assert_eq!(decode::<String>(&encoded[range.clone()], 0)?, vec![value.clone()]);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_byte_ranges_skip_count_markers() -> CoreResult<()> {
+    let ints = |xs: &[Option<i64>]| xs.iter()
+      .map(|x| FieldValue {
+        value: x.map(Value::Int64Val),
+      })
+      .collect::<Vec<_>>();
+    let encoder = new_encoder(DataType::Int64, 0);
+    // 255 implicit nulls, whose count needs an escape, then 7, then a
+    // mid-buffer count marker, then -1
+    let leading = encoder.encode_count(255);
+    assert_eq!(leading, vec![COUNT_BYTE, 0, 0, 0, ESCAPE_BYTE, 0]);
+    let mut bytes = leading.clone();
+    bytes.extend(encoder.encode(&ints(&[Some(7)]))?);
+    bytes.extend(encoder.encode_count(256));
+    bytes.extend(encoder.encode(&ints(&[Some(-1)]))?);
+    let mut values = ints(&[None; 255]);
+    values.extend(ints(&[Some(7), Some(-1)]));
+    assert_eq!(decode::<i64>(&bytes, 0)?, values);
+
+    let ranges = decode_byte_ranges(DataType::Int64, 0, &bytes)?;
+    assert_eq!(ranges.len(), values.len());
+    for range in &ranges[..255] {
+      assert_eq!(*range, leading.len()..leading.len());
+    }
+    for (range, value) in ranges.iter().zip(&values) {
+      let decoded = decode::<i64>(&bytes[range.clone()], 0)?;
+      if range.is_empty() {
+        assert_eq!(value.value, None);
+        assert!(decoded.is_empty());
+      } else {
+        assert_eq!(decoded, vec![value.clone()]);
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_concat_encoded() -> CoreResult<()> {
+    let ints = |xs: &[Option<i64>]| xs.iter()
+      .map(|x| FieldValue {
+        value: x.map(Value::Int64Val),
+      })
+      .collect::<Vec<_>>();
+    let encoder = new_encoder(DataType::Int64, 0);
+
+    let first = encoder.encode(&ints(&[Some(1), None, Some(-255)]))?;
+    // a buffer of only implicit nulls, as written by a count marker
+    let second = encoder.encode_count(2);
+    let mut third = encoder.encode(&ints(&[Some(253), Some(254)]))?;
+    third.extend(encoder.encode_count(2));
+
+    let merged = concat_encoded(DataType::Int64, 0, &[&first, &second, &third])?;
+    let decoded = new_field_value_decoder(DataType::Int64, 0).decode(&merged)?;
+    assert_eq!(
+      decoded,
+      ints(&[Some(1), None, Some(-255), None, None, Some(253), Some(254)]),
+    );
+    assert!(concat_encoded(DataType::Int64, 0, &[&first, &[1, 2]]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_deep_nesting() -> CoreResult<()> {
+    let depth = 255;
+    let nest = |leaf: Value, depth: u8| {
+      let mut res = leaf;
+      for _ in 0..depth {
+        res = build_list_val(vec![res]);
+      }
+      FieldValue { value: Some(res) }
+    };
+    let values = vec![
+      nest(Value::StringVal("abc".to_string()), depth),
+      FieldValue::default(),
+      nest(build_list_val(vec![]), depth - 1),
+    ];
+
+    let encoded = encode::<String>(&values, depth)?;
+    assert_eq!(decode::<String>(&encoded, depth)?, values);
+    assert!(decode::<String>(&encoded[..encoded.len() - 1], depth).is_err());
+
+    let too_shallow = vec![nest(Value::StringVal("abc".to_string()), depth - 1)];
+    assert!(encode::<String>(&too_shallow, depth).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_supports() {
+    for dtype in ALL_DTYPES {
+      assert!(supports(dtype, 0));
+      assert!(supports(dtype, 3));
+      assert!(supports(dtype, 253));
+      assert!(!supports(dtype, 255));
+    }
+    // atomic data types need one fewer level than strings and bytes
+    assert!(supports(DataType::Int64, 254));
+    assert!(supports(DataType::Bool, 254));
+    assert!(!supports(DataType::String, 254));
+    assert!(!supports(DataType::Bytes, 254));
+  }
+
+  #[test]
+  fn test_factory_dtypes_match() {
+    for dtype in ALL_DTYPES {
+      assert_eq!(new_encoder(dtype, 1).dtype(), dtype);
+      assert_eq!(new_field_value_decoder(dtype, 1).dtype(), dtype);
+      assert_eq!(new_byte_idx_decoder(dtype, 1).dtype(), dtype);
+    }
+  }
+
+  #[test]
+  fn test_malformed_buffers() {
+    let malformed: Vec<(&str, Vec<u8>)> = vec![
+      ("truncated count", vec![COUNT_BYTE, 0, 0]),
+      ("escape at end of count", vec![COUNT_BYTE, 0, 0, 0, ESCAPE_BYTE]),
+      ("escape at end of value", vec![0, 2, b'a', ESCAPE_BYTE]),
+      ("truncated length", vec![NULL_BYTE, 0]),
+      ("truncated value", vec![0, 3, b'a']),
+      ("null inside value", vec![0, 2, b'a', NULL_BYTE]),
+      ("count inside value", vec![0, 1, COUNT_BYTE, 0, 0, 0, 1]),
+      ("bad escape", vec![0, 1, ESCAPE_BYTE, b'a']),
+      ("mismatched count", vec![NULL_BYTE, COUNT_BYTE, 0, 0, 0, 2]),
+    ];
+    for (description, bytes) in &malformed {
+      let res = new_field_value_decoder(DataType::String, 0).decode(bytes);
+      assert!(res.is_err(), "{}", description);
+    }
+
+    // a null element of a list is never written
+    let nested_null = vec![0, 1, NULL_BYTE];
+    assert!(new_field_value_decoder(DataType::String, 1).decode(&nested_null).is_err());
+    assert!(new_field_value_decoder(DataType::Int64, 0).decode(&[0, 0, 0, 0, 0, 0, 0]).is_err());
+  }
+
+  #[test]
+  fn test_decode_iter() -> CoreResult<()> {
+    let values = vec![
+      FieldValue { value: Some(Value::Int64Val(3)) },
+      FieldValue::default(),
+    ];
+    let decoder = new_field_value_decoder(DataType::Int64, 0);
+    let mut bytes = vec![COUNT_BYTE, 0, 0, 0, 2];
+    bytes.extend(new_encoder(DataType::Int64, 0).encode(&values)?);
+    assert_eq!(
+      decoder.decode_iter(&bytes).collect::<CoreResult<Vec<_>>>()?,
+      decoder.decode(&bytes)?,
+    );
+
+    // values before a truncated one still decode, and iteration stops after
+    // the error
+    bytes.push(0);
+    let mut iter = decoder.decode_iter(&bytes);
+    for _ in 0..4 {
+      assert!(iter.next().unwrap().is_ok());
+    }
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn test_huge_count_respects_limit() -> CoreResult<()> {
+    let decoder = new_field_value_decoder(DataType::Int64, 0);
+    let bytes = vec![COUNT_BYTE, 0, 127, 127, 127];
+    assert_eq!(decoder.decode_limited(&bytes, 5)?, vec![FieldValue::default(); 5]);
+
+    // nulls are only produced as they're requested, so even the largest
+    // counts cost no more than the limit
+    let bytes = vec![COUNT_BYTE, 127, 127, 127, 127];
+    assert_eq!(decoder.decode_limited(&bytes, 5)?, vec![FieldValue::default(); 5]);
+    let first = decoder.decode_iter(&bytes).take(5).collect::<CoreResult<Vec<_>>>()?;
+    assert_eq!(first, vec![FieldValue::default(); 5]);
+    Ok(())
+  }
 }
@@ -16,6 +16,9 @@ pub enum CoreErrorKind {
   Invalid,
   Other,
   Corrupt,
+  // the data ended early, e.g. due to an incomplete transfer;
+  // unlike corrupt data, reading it again may succeed
+  Truncated,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -41,6 +44,18 @@ impl CoreError {
   pub fn corrupt(explanation: &str) -> CoreError {
     CoreError::create(explanation, CoreErrorKind::Corrupt)
   }
+
+  pub fn truncated(explanation: &str) -> CoreError {
+    CoreError::create(explanation, CoreErrorKind::Truncated)
+  }
+
+  // Prefixes the message with where the error occurred, keeping its kind.
+  pub(crate) fn with_context(self, context: &str) -> CoreError {
+    CoreError {
+      message: format!("{}: {}", context, self.message),
+      kind: self.kind,
+    }
+  }
 }
 
 impl Display for CoreError {
@@ -60,7 +75,12 @@ impl Display for CoreError {
         f,
         "corrupt data or incorrect decoder/decompressor; {}",
         self.message
-      )
+      ),
+      CoreErrorKind::Truncated => write!(
+        f,
+        "truncated data; {}",
+        self.message
+      ),
     }
   }
 }
@@ -79,7 +99,7 @@ impl From<QCompressError> for CoreError {
     let kind = match e.kind {
       QCompressErrorKind::Compatibility => CoreErrorKind::Other,
       QCompressErrorKind::Corruption => CoreErrorKind::Corrupt,
-      QCompressErrorKind::InsufficientData => CoreErrorKind::Corrupt,
+      QCompressErrorKind::InsufficientData => CoreErrorKind::Truncated,
       QCompressErrorKind::InvalidArgument => CoreErrorKind::Invalid,
     };
     CoreError {
@@ -90,3 +110,33 @@ impl From<QCompressError> for CoreError {
 }
 
 pub type CoreResult<T> = Result<T, CoreError>;
+
+#[cfg(test)]
+mod tests {
+  use crate::compression::Codec;
+  use crate::compression::q_codec::I64QCodec;
+
+  use super::*;
+
+  fn compressed_ints() -> CoreResult<Vec<u8>> {
+    let nums = (0..1000).collect::<Vec<i64>>();
+    I64QCodec {}.compress_atoms(&nums)
+  }
+
+  #[test]
+  fn test_truncated_q_compress() -> CoreResult<()> {
+    let bytes = compressed_ints()?;
+    let err = I64QCodec {}.decompress_atoms(&bytes[..bytes.len() / 2]).unwrap_err();
+    assert_eq!(err.kind, CoreErrorKind::Truncated);
+    Ok(())
+  }
+
+  #[test]
+  fn test_corrupt_q_compress() -> CoreResult<()> {
+    let mut bytes = compressed_ints()?;
+    bytes[0] = !bytes[0];
+    let err = I64QCodec {}.decompress_atoms(&bytes).unwrap_err();
+    assert_eq!(err.kind, CoreErrorKind::Corrupt);
+    Ok(())
+  }
+}
@@ -6,6 +6,6 @@ pub mod deletion;
 pub mod encoding;
 pub mod errors;
 pub mod primitives;
+pub mod rep_levels;
 
-mod rep_levels;
 mod utils;
@@ -2,8 +2,9 @@ use pancake_db_idl::dml::field_value::Value;
 use pancake_db_idl::dtype::DataType;
 
 use crate::compression::Codec;
+use crate::compression::bitpack_codec::BoolBitpackCodec;
 use crate::compression::q_codec::BoolQCodec;
-use crate::compression::Q_COMPRESS;
+use crate::compression::{BITPACK, Q_COMPRESS};
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 
@@ -54,6 +55,8 @@ impl Primitive for bool {
   fn new_codec(codec: &str) -> Option<Box<dyn Codec<P=Self>>> {
     if codec == Q_COMPRESS {
       Some(Box::new(BoolQCodec {}))
+    } else if codec == BITPACK {
+      Some(Box::new(BoolBitpackCodec {}))
     } else {
       None
     }
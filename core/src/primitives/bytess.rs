@@ -2,8 +2,7 @@ use pancake_db_idl::dml::field_value::Value;
 use pancake_db_idl::dtype::DataType;
 
 use crate::compression::Codec;
-use crate::compression::ZSTD;
-use crate::compression::zstd_codec::ZstdCodec;
+use crate::compression::zstd_codec;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 
@@ -45,10 +44,6 @@ impl Primitive for Vec<u8> {
   }
 
   fn new_codec(codec: &str) -> Option<Box<dyn Codec<P=Self>>> {
-    if codec == ZSTD {
-      Some(Box::new(ZstdCodec::<Vec<u8>>::default()))
-    } else {
-      None
-    }
+    zstd_codec::new_zstd_codec(codec)
   }
 }
@@ -0,0 +1,138 @@
+use pancake_db_idl::dml::field_value::Value;
+use pancake_db_idl::dtype::DataType;
+use q_compress::data_types::TimestampMicros;
+
+use crate::errors::{CoreError, CoreResult};
+
+use super::{Atom, Primitive};
+
+fn value_to_atom_bytes_for<P: Primitive>(value: &Value) -> CoreResult<Vec<u8>> {
+  let atoms = P::try_from_value(value)?.to_atoms();
+  let mut res = Vec::with_capacity(atoms.len() * P::A::BYTE_SIZE);
+  for atom in &atoms {
+    res.extend(atom.to_bytes());
+  }
+  Ok(res)
+}
+
+fn atom_bytes_to_value_for<P: Primitive>(bytes: &[u8]) -> CoreResult<Value> {
+  if bytes.len() % P::A::BYTE_SIZE != 0 {
+    return Err(CoreError::invalid(&format!(
+      "atom byte count {} is not a multiple of atom size {}",
+      bytes.len(),
+      P::A::BYTE_SIZE,
+    )));
+  }
+  let n_atoms = bytes.len() / P::A::BYTE_SIZE;
+  if P::IS_ATOMIC && n_atoms != 1 {
+    return Err(CoreError::invalid(&format!(
+      "expected exactly 1 atom for an atomic data type but found {}",
+      n_atoms,
+    )));
+  }
+  let atoms = bytes.chunks(P::A::BYTE_SIZE)
+    .map(P::A::try_from_bytes)
+    .collect::<CoreResult<Vec<_>>>()?;
+  Ok(P::try_from_atoms(&atoms)?.to_value())
+}
+
+/// Returns the size in bytes of a single atom of the data type.
+pub fn atom_byte_size(dtype: DataType) -> usize {
+  match dtype {
+    DataType::Int64 => <i64 as Primitive>::A::BYTE_SIZE,
+    DataType::String => <String as Primitive>::A::BYTE_SIZE,
+    DataType::Float32 => <f32 as Primitive>::A::BYTE_SIZE,
+    DataType::Float64 => <f64 as Primitive>::A::BYTE_SIZE,
+    DataType::Bytes => <Vec<u8> as Primitive>::A::BYTE_SIZE,
+    DataType::Bool => <bool as Primitive>::A::BYTE_SIZE,
+    DataType::TimestampMicros => <TimestampMicros as Primitive>::A::BYTE_SIZE,
+  }
+}
+
+/// Converts a non-list value of the data type into its atoms.
+///
+/// Since each data type has its own atom type, the atoms are returned
+/// concatenated as big-endian bytes, [`atom_byte_size`] bytes apiece.
+pub fn value_to_atoms(dtype: DataType, value: &Value) -> CoreResult<Vec<u8>> {
+  match dtype {
+    DataType::Int64 => value_to_atom_bytes_for::<i64>(value),
+    DataType::String => value_to_atom_bytes_for::<String>(value),
+    DataType::Float32 => value_to_atom_bytes_for::<f32>(value),
+    DataType::Float64 => value_to_atom_bytes_for::<f64>(value),
+    DataType::Bytes => value_to_atom_bytes_for::<Vec<u8>>(value),
+    DataType::Bool => value_to_atom_bytes_for::<bool>(value),
+    DataType::TimestampMicros => value_to_atom_bytes_for::<TimestampMicros>(value),
+  }
+}
+
+/// Converts atoms, as produced by [`value_to_atoms`], back into a value of
+/// the data type.
+///
+/// Data types other than strings and bytes take exactly one atom.
+pub fn atoms_to_value(dtype: DataType, atom_bytes: &[u8]) -> CoreResult<Value> {
+  match dtype {
+    DataType::Int64 => atom_bytes_to_value_for::<i64>(atom_bytes),
+    DataType::String => atom_bytes_to_value_for::<String>(atom_bytes),
+    DataType::Float32 => atom_bytes_to_value_for::<f32>(atom_bytes),
+    DataType::Float64 => atom_bytes_to_value_for::<f64>(atom_bytes),
+    DataType::Bytes => atom_bytes_to_value_for::<Vec<u8>>(atom_bytes),
+    DataType::Bool => atom_bytes_to_value_for::<bool>(atom_bytes),
+    DataType::TimestampMicros => atom_bytes_to_value_for::<TimestampMicros>(atom_bytes),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use prost_types::Timestamp;
+
+  use super::*;
+
+  #[test]
+  fn test_round_trip_all_dtypes() -> CoreResult<()> {
+    let cases = vec![
+      (DataType::Int64, Value::Int64Val(-77), 1),
+      (DataType::String, Value::StringVal("hé".to_string()), 3),
+      (DataType::Float32, Value::Float32Val(1.5), 1),
+      (DataType::Float64, Value::Float64Val(-0.25), 1),
+      (DataType::Bytes, Value::BytesVal(vec![0, 255, 7]), 3),
+      (DataType::Bool, Value::BoolVal(true), 1),
+      (DataType::TimestampMicros, Value::TimestampVal(Timestamp {
+        seconds: 1_600_000_000,
+        nanos: 123_000,
+      }), 1),
+    ];
+    for (dtype, value, n_atoms) in cases {
+      let atom_bytes = value_to_atoms(dtype, &value)?;
+      assert_eq!(atom_bytes.len(), n_atoms * atom_byte_size(dtype), "{:?}", dtype);
+      assert_eq!(atoms_to_value(dtype, &atom_bytes)?, value, "{:?}", dtype);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_mismatched_dtype() {
+    assert!(value_to_atoms(DataType::Int64, &Value::BoolVal(true)).is_err());
+    assert!(atoms_to_value(DataType::Int64, &[0, 1, 2]).is_err());
+  }
+
+  #[test]
+  fn test_atomic_dtypes_need_one_atom() -> CoreResult<()> {
+    let atomic_dtypes = [
+      DataType::Int64,
+      DataType::Float32,
+      DataType::Float64,
+      DataType::Bool,
+      DataType::TimestampMicros,
+    ];
+    for dtype in atomic_dtypes {
+      assert!(atoms_to_value(dtype, &[]).is_err(), "{:?}", dtype);
+      let two_atoms = vec![0; 2 * atom_byte_size(dtype)];
+      assert!(atoms_to_value(dtype, &two_atoms).is_err(), "{:?}", dtype);
+    }
+
+    // strings and bytes take any number of atoms, including none
+    assert_eq!(atoms_to_value(DataType::String, &[])?, Value::StringVal("".to_string()));
+    assert_eq!(atoms_to_value(DataType::Bytes, &[1, 2])?, Value::BytesVal(vec![1, 2]));
+    Ok(())
+  }
+}
@@ -4,6 +4,7 @@ use pancake_db_idl::dtype::DataType;
 use crate::compression::Codec;
 use crate::compression::q_codec::{F64QCodec, F32QCodec};
 use crate::compression::Q_COMPRESS;
+use crate::compression::raw_codec;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 use crate::utils;
@@ -19,6 +20,15 @@ impl Atom for f32 {
     let byte_array = utils::try_byte_array::<4>(bytes)?;
     Ok(f32::from_be_bytes(byte_array))
   }
+
+  fn to_bytes_le(&self) -> Vec<u8> {
+    self.to_le_bytes().to_vec()
+  }
+
+  fn try_from_bytes_le(bytes: &[u8]) -> CoreResult<Self> {
+    let byte_array = utils::try_byte_array::<4>(bytes)?;
+    Ok(f32::from_le_bytes(byte_array))
+  }
 }
 
 impl Atom for f64 {
@@ -32,6 +42,15 @@ impl Atom for f64 {
     let byte_array = utils::try_byte_array::<8>(bytes)?;
     Ok(f64::from_be_bytes(byte_array))
   }
+
+  fn to_bytes_le(&self) -> Vec<u8> {
+    self.to_le_bytes().to_vec()
+  }
+
+  fn try_from_bytes_le(bytes: &[u8]) -> CoreResult<Self> {
+    let byte_array = utils::try_byte_array::<8>(bytes)?;
+    Ok(f64::from_le_bytes(byte_array))
+  }
 }
 
 impl Primitive for f32 {
@@ -63,7 +82,7 @@ impl Primitive for f32 {
     if codec == Q_COMPRESS {
       Some(Box::new(F32QCodec {}))
     } else {
-      None
+      raw_codec::new_raw_codec(codec)
     }
   }
 }
@@ -97,7 +116,7 @@ impl Primitive for f64 {
     if codec == Q_COMPRESS {
       Some(Box::new(F64QCodec {}))
     } else {
-      None
+      raw_codec::new_raw_codec(codec)
     }
   }
 }
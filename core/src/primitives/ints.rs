@@ -2,8 +2,9 @@ use pancake_db_idl::dml::field_value::Value;
 use pancake_db_idl::dtype::DataType;
 
 use crate::compression::Codec;
-use crate::compression::q_codec::I64QCodec;
-use crate::compression::Q_COMPRESS;
+use crate::compression::q_codec::{I64DeltaQCodec, I64QCodec};
+use crate::compression::{DELTA, Q_COMPRESS};
+use crate::compression::raw_codec;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 use crate::utils;
@@ -19,6 +20,15 @@ impl Atom for i64 {
     let byte_array = utils::try_byte_array::<8>(bytes)?;
     Ok(i64::from_be_bytes(byte_array))
   }
+
+  fn to_bytes_le(&self) -> Vec<u8> {
+    self.to_le_bytes().to_vec()
+  }
+
+  fn try_from_bytes_le(bytes: &[u8]) -> CoreResult<Self> {
+    let byte_array = utils::try_byte_array::<8>(bytes)?;
+    Ok(i64::from_le_bytes(byte_array))
+  }
 }
 
 impl Primitive for i64 {
@@ -49,9 +59,67 @@ impl Primitive for i64 {
   fn new_codec(codec: &str) -> Option<Box<dyn Codec<P=Self>>> {
     if codec == Q_COMPRESS {
       Some(Box::new(I64QCodec {}))
+    } else if codec == DELTA {
+      Some(Box::new(I64DeltaQCodec {}))
     } else {
-      None
+      raw_codec::new_raw_codec(codec)
+    }
+  }
+}
+
+// 128-bit atoms have no data type of their own yet; they exist so wider
+// logical types like decimals and large IDs can share one representation.
+// See `q_codec::WideIntQCodec` for compressing them.
+macro_rules! wide_int_atom {
+  ($t: ty) => {
+    impl Atom for $t {
+      const BYTE_SIZE: usize = 16;
+
+      fn to_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+      }
+
+      fn try_from_bytes(bytes: &[u8]) -> CoreResult<Self> {
+        let byte_array = utils::try_byte_array::<16>(bytes)?;
+        Ok(<$t>::from_be_bytes(byte_array))
+      }
+
+      fn to_bytes_le(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+      }
+
+      fn try_from_bytes_le(bytes: &[u8]) -> CoreResult<Self> {
+        let byte_array = utils::try_byte_array::<16>(bytes)?;
+        Ok(<$t>::from_le_bytes(byte_array))
+      }
     }
   }
 }
 
+wide_int_atom!(i128);
+wide_int_atom!(u128);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_round_trips<A: Atom + PartialEq>(atoms: &[A]) -> CoreResult<()> {
+    for atom in atoms {
+      let bytes = atom.to_bytes();
+      assert_eq!(bytes.len(), A::BYTE_SIZE);
+      assert_eq!(A::try_from_bytes(&bytes)?, *atom);
+      assert_eq!(A::try_from_bytes_le(&atom.to_bytes_le())?, *atom);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_wide_int_boundaries() -> CoreResult<()> {
+    assert_round_trips(&[i128::MIN, -1, 0, 1, i128::MAX])?;
+    assert_round_trips(&[u128::MIN, 1, u128::MAX])?;
+    assert_eq!((-2_i128).to_bytes()[0], 255);
+    assert_eq!(1_u128.to_bytes()[15], 1);
+    assert!(i128::try_from_bytes(&[0; 8]).is_err());
+    Ok(())
+  }
+}
@@ -1,9 +1,13 @@
+pub use dispatch::{atom_byte_size, atoms_to_value, value_to_atoms};
+pub use stats::{min_max, StatsOrd};
 pub use traits::{Atom, Primitive};
 
 mod bools;
 mod bytess;
+mod dispatch;
 mod floats;
 mod ints;
+mod stats;
 mod strings;
 mod timestamps;
 mod traits;
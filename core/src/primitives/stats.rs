@@ -0,0 +1,115 @@
+use std::cmp::Ordering;
+
+use q_compress::data_types::TimestampMicros;
+
+/// The ordering of primitive values used for column statistics.
+///
+/// Floats follow IEEE 754 ordering, so negative infinity is the least value
+/// and positive infinity the greatest.
+/// NaN is unordered with respect to everything, including itself, and is
+/// excluded from min and max entirely.
+/// This keeps stats the same no matter where NaNs appear in a column.
+pub trait StatsOrd {
+  /// Compares two values, returning `None` if either is excluded from
+  /// statistics.
+  fn stats_cmp(&self, other: &Self) -> Option<Ordering>;
+}
+
+macro_rules! total_stats_ord {
+  ($t: ty) => {
+    impl StatsOrd for $t {
+      fn stats_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+      }
+    }
+  }
+}
+
+total_stats_ord!(bool);
+total_stats_ord!(i64);
+total_stats_ord!(String);
+total_stats_ord!(Vec<u8>);
+
+impl StatsOrd for f32 {
+  fn stats_cmp(&self, other: &Self) -> Option<Ordering> {
+    self.partial_cmp(other)
+  }
+}
+
+impl StatsOrd for f64 {
+  fn stats_cmp(&self, other: &Self) -> Option<Ordering> {
+    self.partial_cmp(other)
+  }
+}
+
+impl StatsOrd for TimestampMicros {
+  fn stats_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.to_total_parts().cmp(&other.to_total_parts()))
+  }
+}
+
+/// Returns the least and greatest values according to [`StatsOrd`],
+/// skipping values excluded from statistics.
+///
+/// Returns `None` if no value is eligible.
+/// Among equal values, the first is kept.
+pub fn min_max<'a, T, I>(values: I) -> Option<(&'a T, &'a T)> where
+  T: StatsOrd + 'a,
+  I: IntoIterator<Item=&'a T>,
+{
+  let mut res: Option<(&T, &T)> = None;
+  for value in values {
+    if value.stats_cmp(value).is_none() {
+      continue;
+    }
+    res = match res {
+      None => Some((value, value)),
+      Some((min, max)) => Some((
+        if value.stats_cmp(min) == Some(Ordering::Less) { value } else { min },
+        if value.stats_cmp(max) == Some(Ordering::Greater) { value } else { max },
+      )),
+    };
+  }
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_float_policy() {
+    let xs = [f64::NAN, 1.0, f64::INFINITY, f64::NAN, f64::NEG_INFINITY, -3.5];
+    assert_eq!(min_max(&xs), Some((&f64::NEG_INFINITY, &f64::INFINITY)));
+
+    let finite = [2.0_f32, f32::NAN, -1.0];
+    assert_eq!(min_max(&finite), Some((&-1.0, &2.0)));
+
+    assert_eq!(f32::NAN.stats_cmp(&f32::NAN), None);
+    assert_eq!(f32::INFINITY.stats_cmp(&f32::MAX), Some(Ordering::Greater));
+  }
+
+  #[test]
+  fn test_all_nan() {
+    assert_eq!(min_max(&[f64::NAN, f64::NAN]), None);
+    assert_eq!(min_max(&Vec::<f64>::new()), None);
+  }
+
+  #[test]
+  fn test_nan_position_independent() {
+    let a = [f64::NAN, 5.0, -2.0];
+    let b = [5.0, -2.0, f64::NAN];
+    assert_eq!(min_max(&a), min_max(&b));
+  }
+
+  #[test]
+  fn test_other_types() {
+    let strings = ["b".to_string(), "a".to_string(), "c".to_string()];
+    assert_eq!(min_max(&strings), Some((&strings[1], &strings[2])));
+    let timestamps = [
+      TimestampMicros::from_secs_and_nanos(5, 0),
+      TimestampMicros::from_secs_and_nanos(-5, 0),
+    ];
+    assert_eq!(min_max(&timestamps), Some((&timestamps[1], &timestamps[0])));
+  }
+}
@@ -2,8 +2,7 @@ use pancake_db_idl::dml::field_value::Value;
 use pancake_db_idl::dtype::DataType;
 
 use crate::compression::Codec;
-use crate::compression::ZSTD;
-use crate::compression::zstd_codec::ZstdCodec;
+use crate::compression::zstd_codec;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::Primitive;
 
@@ -33,11 +32,7 @@ impl Primitive for String {
   }
 
   fn new_codec(codec: &str) -> Option<Box<dyn Codec<P=Self>>> {
-    if codec == ZSTD {
-      Some(Box::new(ZstdCodec::<String>::default()))
-    } else {
-      None
-    }
+    zstd_codec::new_zstd_codec(codec)
   }
 }
 
@@ -45,11 +40,13 @@ impl Primitive for String {
 mod tests {
   use pancake_db_idl::dml::FieldValue;
 
+  use crate::compression::ZSTD;
+
   use super::*;
 
   #[test]
   fn test_serde() -> CoreResult<()> {
-    let strs = vec!["orange", "banana", "grapefruit", "ÿ\\'\""];
+    let strs = ["orange", "banana", "grapefruit", "ÿ\\'\""];
     let fvs = strs.iter()
       .map(|s| FieldValue {
         value: Some(Value::StringVal(s.to_string())),
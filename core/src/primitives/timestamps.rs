@@ -6,8 +6,9 @@ use q_compress::data_types::{NumberLike, TimestampMicros};
 use prost_types::Timestamp;
 
 use crate::compression::Codec;
-use crate::compression::q_codec::TimestampMicrosQCodec;
-use crate::compression::Q_COMPRESS;
+use crate::compression::q_codec::{TimestampMicrosDeltaQCodec, TimestampMicrosQCodec};
+use crate::compression::{DELTA, Q_COMPRESS};
+use crate::compression::raw_codec;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 
@@ -55,8 +56,10 @@ impl Primitive for TimestampMicros {
   fn new_codec(codec: &str) -> Option<Box<dyn Codec<P=Self>>> {
     if codec == Q_COMPRESS {
       Some(Box::new(TimestampMicrosQCodec {}))
+    } else if codec == DELTA {
+      Some(Box::new(TimestampMicrosDeltaQCodec {}))
     } else {
-      None
+      raw_codec::new_raw_codec(codec)
     }
   }
 }
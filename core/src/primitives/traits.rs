@@ -3,6 +3,7 @@ use pancake_db_idl::dtype::DataType;
 
 use crate::compression::{Codec, ValueCodec};
 use crate::errors::CoreResult;
+use crate::primitives::StatsOrd;
 use std::fmt::Debug;
 
 pub trait Atom: 'static + Copy + Debug + Default + Send + Sync {
@@ -10,9 +11,23 @@ pub trait Atom: 'static + Copy + Debug + Default + Send + Sync {
 
   fn to_bytes(&self) -> Vec<u8>;
   fn try_from_bytes(bytes: &[u8]) -> CoreResult<Self> where Self: Sized;
+
+  // little-endian counterparts of the big-endian methods above;
+  // override these when the type can convert without a byte swap
+  fn to_bytes_le(&self) -> Vec<u8> {
+    let mut res = self.to_bytes();
+    res.reverse();
+    res
+  }
+
+  fn try_from_bytes_le(bytes: &[u8]) -> CoreResult<Self> where Self: Sized {
+    let mut be_bytes = bytes.to_vec();
+    be_bytes.reverse();
+    Self::try_from_bytes(&be_bytes)
+  }
 }
 
-pub trait Primitive: 'static + Default + Send + Sync {
+pub trait Primitive: 'static + Default + Send + Sync + StatsOrd {
   type A: Atom;
 
   const DTYPE: DataType;
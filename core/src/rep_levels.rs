@@ -1,3 +1,17 @@
+//! Repetition levels describe the nesting structure of a column, separately
+//! from its atoms.
+//!
+//! Each field value contributes a run of levels:
+//! * a null top-level value is `0`,
+//! * each atom of a non-atomic primitive (e.g. a byte of a string) is
+//!   `depth + 2`,
+//! * the end of a primitive value is `depth + 1`, and
+//! * the end of a list at traversal depth `d` is `d + 1`.
+//!
+//! Here `depth` is the column's nested list depth.
+//! For example, with depth 1, the list `["ab"]` yields levels `3, 3, 2, 1`
+//! and atoms `a, b`, while an empty list yields just `1`.
+
 use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
 use pancake_db_idl::dml::field_value::Value;
 use q_compress::Compressor;
@@ -5,6 +19,12 @@ use q_compress::Compressor;
 use crate::errors::{CoreError, CoreResult};
 use crate::primitives::{Atom, Primitive};
 
+/// The intermediate representation of a column between field values and
+/// compressed bytes.
+///
+/// See the [module docs](self) for the level encoding.
+/// Every top-level value's levels are self-contained, so chunks of a column
+/// can be combined by concatenation with [`extend`][Self::extend].
 #[derive(Clone, Debug, Default)]
 pub struct RepLevelsAndAtoms<A: Atom> {
   pub levels: Vec<u8>,
@@ -12,10 +32,34 @@ pub struct RepLevelsAndAtoms<A: Atom> {
 }
 
 impl<A: Atom> RepLevelsAndAtoms<A> {
+  /// Wraps levels and atoms without validating them; validation happens
+  /// when converting to field values.
+  pub fn new(levels: Vec<u8>, atoms: Vec<A>) -> Self {
+    RepLevelsAndAtoms { levels, atoms }
+  }
+
+  /// Equivalent to [`extract_levels_and_atoms`].
+  pub fn from_field_values<P: Primitive<A=A>>(
+    fvs: &[FieldValue],
+    schema_depth: u8,
+  ) -> CoreResult<Self> {
+    extract_levels_and_atoms::<P>(fvs, schema_depth)
+  }
+
+  /// Appends another chunk's values after this one's.
   pub fn extend(&mut self, other: &RepLevelsAndAtoms<A>) {
     self.levels.extend(&other.levels);
     self.atoms.extend(&other.atoms);
   }
+
+  /// Rebuilds the field values these levels and atoms describe.
+  ///
+  /// Returns an error if the levels are inconsistent with `schema_depth`
+  /// or the atoms.
+  pub fn into_field_values<P: Primitive<A=A>>(self, schema_depth: u8) -> CoreResult<Vec<FieldValue>> {
+    AtomNester::<P>::from_levels_and_values(self.levels, self.atoms, schema_depth)
+      .nested_field_values()
+  }
 }
 
 #[derive(Clone, Debug)]
@@ -24,91 +68,193 @@ pub struct RepLevelsAndBytes {
   pub remaining_bytes: Vec<u8>,
 }
 
+/// Computes the repetition levels and atoms of `fvs`, without compressing
+/// them.
+///
+/// Returns an error if any value's nesting does not match `schema_depth`
+/// or if a value does not match the primitive type `P`.
+/// See the [module docs](self) for the level encoding.
+///
+/// ```
+/// use pancake_db_core::rep_levels::extract_levels_and_atoms;
+/// use pancake_db_idl::dml::{FieldValue, RepeatedFieldValue};
+/// use pancake_db_idl::dml::field_value::Value;
+///
+/// fn string_list(strings: &[&str]) -> FieldValue {
+///   let vals = strings.iter()
+///     .map(|s| FieldValue { value: Some(Value::StringVal(s.to_string())) })
+///     .collect();
+///   FieldValue { value: Some(Value::ListVal(RepeatedFieldValue { vals })) }
+/// }
+///
+/// let fvs = vec![
+///   string_list(&["abc", "de"]),
+///   FieldValue::default(),
+///   string_list(&["f"]),
+///   string_list(&[""]),
+///   string_list(&[]),
+/// ];
+/// let levels_and_atoms = extract_levels_and_atoms::<String>(&fvs, 1)?;
+/// assert_eq!(
+///   levels_and_atoms.levels,
+///   vec![3, 3, 3, 2, 3, 3, 2, 1, 0, 3, 2, 1, 2, 1, 1],
+/// );
+/// assert_eq!(levels_and_atoms.atoms, b"abcdef".to_vec());
+/// # Ok::<(), pancake_db_core::errors::CoreError>(())
+/// ```
 pub fn extract_levels_and_atoms<P: Primitive>(
   fvs: &[FieldValue],
   schema_depth: u8,
 ) -> CoreResult<RepLevelsAndAtoms<P::A>> {
+  if !supports_depth::<P>(schema_depth) {
+    return Err(CoreError::invalid(&format!(
+      "nested list depth {} is too deep for repetition levels of this data type",
+      schema_depth,
+    )));
+  }
+
   let mut res = RepLevelsAndAtoms::<P::A>::default();
-  for fv in fvs {
-    let sub_levels_and_atoms = extract_single_levels_and_atoms::<P>(fv, schema_depth, 0)?;
-    res.extend(&sub_levels_and_atoms);
+  for (i, fv) in fvs.iter().enumerate() {
+    extend_single_levels_and_atoms::<P>(fv, schema_depth, &mut res)
+      .map_err(|e| e.with_context(&format!("value {}", i)))?;
   }
   Ok(res)
 }
 
-pub fn extract_single_levels_and_atoms<P: Primitive>(
-  fv: &FieldValue,
+/// Rebuilds `n` field values from the levels and atoms of only the present
+/// values, plus a separate bitmap of which values are present.
+///
+/// This suits columnar formats like Arrow that track nulls in a validity
+/// bitmap rather than in the repetition levels.
+/// The bitmap holds one bit per value, least significant bit first, and a
+/// set bit means the value is present.
+/// `present` describes the present values in order, so its levels must
+/// never be the null level `0`.
+///
+/// The result equals the standard decode of the levels with nulls embedded.
+/// Returns an error if the bitmap is too short for `n` values or the number
+/// of present values it marks differs from the number `present` describes.
+pub fn field_values_with_presence<P: Primitive>(
+  presence_bitmap: &[u8],
+  n: usize,
+  present: RepLevelsAndAtoms<P::A>,
   schema_depth: u8,
-  traverse_depth: u8
-) -> CoreResult<RepLevelsAndAtoms<P::A>> {
-  match &fv.value {
-    None => {
-      if traverse_depth != 0 {
-        return Err(CoreError::invalid("null value found in nested position"));
-      }
+) -> CoreResult<Vec<FieldValue>> {
+  if presence_bitmap.len() < n.div_ceil(8) {
+    return Err(CoreError::invalid(&format!(
+      "presence bitmap of {} bytes is too short for {} values",
+      presence_bitmap.len(),
+      n,
+    )));
+  }
+  if present.levels.contains(&0) {
+    return Err(CoreError::invalid(
+      "levels of present values contain a null level"
+    ));
+  }
 
-      Ok(RepLevelsAndAtoms {
-        levels: vec![0],
-        atoms: vec![],
-      })
-    },
-    Some(Value::ListVal(repeated)) => {
-      if traverse_depth >= schema_depth {
-        return Err(CoreError::invalid("traversed to deeper than schema depth"));
-      }
+  let present_fvs = present.into_field_values::<P>(schema_depth)?;
+  let n_present = (0..n)
+    .filter(|&i| is_present(presence_bitmap, i))
+    .count();
+  if n_present != present_fvs.len() {
+    return Err(CoreError::invalid(&format!(
+      "presence bitmap marks {} values present but {} were provided",
+      n_present,
+      present_fvs.len(),
+    )));
+  }
 
-      let mut res = RepLevelsAndAtoms::default();
-      for fv in &repeated.vals {
-        let sub_levels_and_atoms = extract_single_levels_and_atoms::<P>(
-          fv,
-          traverse_depth + 1,
-          schema_depth
-        )?;
-        res.extend(&sub_levels_and_atoms);
-      }
-      res.levels.push(traverse_depth + 1);
-      Ok(res)
-    },
-    Some(v) => {
-      if traverse_depth != schema_depth {
-        return Err(CoreError::invalid(
-          &format!(
-            "traverse depth of {} does not match schema depth of {}",
-            traverse_depth,
-            schema_depth
-          )
-        ))
-      }
+  let mut present_fvs = present_fvs.into_iter();
+  Ok((0..n)
+    .map(|i| if is_present(presence_bitmap, i) {
+      present_fvs.next().unwrap()
+    } else {
+      FieldValue::default()
+    })
+    .collect())
+}
 
-      let atoms = P::try_from_value(v)?.to_atoms();
-      let res = if P::IS_ATOMIC {
-        RepLevelsAndAtoms {
-          levels: vec![schema_depth + 1],
-          atoms: vec![atoms[0]],
-        }
-      } else {
-        let mut levels = atoms.iter()
-          .map(|_| schema_depth + 2)
-          .collect::<Vec<u8>>();
-        levels.push(schema_depth + 1);
-        RepLevelsAndAtoms {
-          levels,
-          atoms
-        }
-      };
+fn is_present(presence_bitmap: &[u8], i: usize) -> bool {
+  presence_bitmap[i / 8] & (1 << (i % 8)) != 0
+}
 
-      Ok(res)
-    },
+// Whether every repetition level at this nesting depth fits in a byte.
+pub(crate) fn supports_depth<P: Primitive>(schema_depth: u8) -> bool {
+  let leaf_level_offset = if P::IS_ATOMIC { 1 } else { 2 };
+  schema_depth.checked_add(leaf_level_offset).is_some()
+}
+
+// Iterates rather than recursing on depth, so deep nesting can't overflow
+// the stack.
+fn extend_single_levels_and_atoms<P: Primitive>(
+  fv: &FieldValue,
+  schema_depth: u8,
+  res: &mut RepLevelsAndAtoms<P::A>,
+) -> CoreResult<()> {
+  // the remaining elements of each list being traversed; a value's traverse
+  // depth is the number of lists it is nested in
+  let mut stack: Vec<std::slice::Iter<FieldValue>> = Vec::new();
+  let mut next = Some(fv);
+  loop {
+    if let Some(fv) = next.take() {
+      let traverse_depth = stack.len() as u8;
+      match &fv.value {
+        None => {
+          if traverse_depth != 0 {
+            return Err(CoreError::invalid("null value found in nested position"));
+          }
+          res.levels.push(0);
+        },
+        Some(Value::ListVal(repeated)) => {
+          if traverse_depth >= schema_depth {
+            return Err(CoreError::invalid("traversed to deeper than schema depth"));
+          }
+          stack.push(repeated.vals.iter());
+        },
+        Some(v) => {
+          if traverse_depth != schema_depth {
+            return Err(CoreError::invalid(
+              &format!(
+                "traverse depth of {} does not match schema depth of {}",
+                traverse_depth,
+                schema_depth
+              )
+            ))
+          }
+
+          let atoms = P::try_from_value(v)?.to_atoms();
+          if P::IS_ATOMIC {
+            res.atoms.push(atoms[0]);
+          } else {
+            res.levels.extend(atoms.iter().map(|_| schema_depth + 2));
+            res.atoms.extend(atoms);
+          }
+          res.levels.push(schema_depth + 1);
+        },
+      }
+    }
+
+    match stack.last_mut() {
+      None => return Ok(()),
+      Some(vals) => match vals.next() {
+        Some(fv) => next = Some(fv),
+        None => {
+          stack.pop();
+          res.levels.push(stack.len() as u8 + 1);
+        },
+      },
+    }
   }
 }
 
-pub fn compress_rep_levels(rep_levels: Vec<u8>) -> CoreResult<Vec<u8>> {
+pub(crate) fn compress_rep_levels(rep_levels: Vec<u8>) -> CoreResult<Vec<u8>> {
   let rep_levels = rep_levels.iter().map(|&l| l as u32).collect::<Vec<u32>>();
   let compressor = Compressor::<u32>::default();
   Ok(compressor.simple_compress(&rep_levels))
 }
 
-pub struct AtomNester<P: Primitive> {
+pub(crate) struct AtomNester<P: Primitive> {
   rep_levels: Vec<u8>,
   atoms: Vec<P::A>,
   schema_depth: u8,
@@ -127,56 +273,264 @@ impl<P: Primitive> AtomNester<P> {
     }
   }
 
-  fn nested_field_value(&mut self, traverse_depth: u8) -> CoreResult<FieldValue> {
-    let mut level = self.rep_levels[self.i];
-    if traverse_depth == 0 && level == 0 {
+  fn level(&self) -> CoreResult<u8> {
+    self.rep_levels.get(self.i)
+      .copied()
+      .ok_or_else(|| CoreError::corrupt("repetition levels ended mid-value"))
+  }
+
+  // Iterative, like `extend_single_levels_and_atoms`.
+  fn nested_field_value(&mut self) -> CoreResult<FieldValue> {
+    if self.level()? == 0 {
       //null
       self.i += 1;
-      Ok(FieldValue::default())
-    } else if traverse_depth < self.schema_depth {
-      //list
-      let mut res = Vec::new();
-      while level > traverse_depth + 1 {
-        res.push(self.nested_field_value(traverse_depth + 1)?);
-        level = self.rep_levels[self.i];
-      }
+      return Ok(FieldValue::default());
+    }
 
-      if level == traverse_depth + 1 {
-        self.i += 1;
-      }
-      Ok(FieldValue {
-        value: Some(Value::ListVal(RepeatedFieldValue {
-          vals: res,
-        })),
-      })
-    } else if traverse_depth == self.schema_depth {
-      let start = self.j;
-      if P::IS_ATOMIC {
-        self.i += 1;
-        self.j += 1;
+    // the elements so far of each list being built; the next value's
+    // traverse depth is the number of lists it is nested in
+    let mut stack: Vec<Vec<FieldValue>> = Vec::new();
+    loop {
+      let traverse_depth = stack.len() as u8;
+      let mut completed = if traverse_depth < self.schema_depth {
+        //list
+        stack.push(Vec::new());
+        None
       } else {
-        while level == self.schema_depth + 2 {
+        Some(self.leaf_field_value()?)
+      };
+
+      loop {
+        let list_depth = match stack.len() {
+          0 => return completed.ok_or_else(|| CoreError::corrupt("invalid repetition level found")),
+          n => n as u8 - 1,
+        };
+        if let Some(fv) = completed.take() {
+          stack.last_mut().unwrap().push(fv);
+        }
+
+        let level = self.level()?;
+        if level > list_depth + 1 {
+          // another element of this list follows
+          break;
+        }
+        if level == list_depth + 1 {
           self.i += 1;
-          self.j += 1;
-          level = self.rep_levels[self.i];
         }
-        self.i += 1;
-      };
-      let atoms = &self.atoms[start..self.j];
-      let value = P::try_from_atoms(atoms)?.to_value();
-      Ok(FieldValue {
-        value: Some(value),
-      })
-    } else {
-      Err(CoreError::corrupt("invalid repetition level found"))
+        let vals = stack.pop().unwrap();
+        completed = Some(FieldValue {
+          value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+        });
+      }
     }
   }
 
+  fn leaf_field_value(&mut self) -> CoreResult<FieldValue> {
+    let start = self.j;
+    if P::IS_ATOMIC {
+      self.i += 1;
+      self.j += 1;
+    } else {
+      while self.level()? as u16 == self.schema_depth as u16 + 2 {
+        self.i += 1;
+        self.j += 1;
+      }
+      self.i += 1;
+    };
+    let atoms = self.atoms.get(start..self.j)
+      .ok_or_else(|| CoreError::corrupt("fewer atoms than repetition levels indicate"))?;
+    let value = P::try_from_atoms(atoms)?.to_value();
+    Ok(FieldValue {
+      value: Some(value),
+    })
+  }
+
   pub fn nested_field_values(&mut self) -> CoreResult<Vec<FieldValue>> {
-    let mut res = Vec::new();
-    while self.i < self.rep_levels.len() {
-      res.push(self.nested_field_value(0)?);
+    self.collect()
+  }
+}
+
+// Builds one top-level field value at a time.
+impl<P: Primitive> Iterator for AtomNester<P> {
+  type Item = CoreResult<FieldValue>;
+
+  fn next(&mut self) -> Option<CoreResult<FieldValue>> {
+    if self.i >= self.rep_levels.len() {
+      return None;
+    }
+    let res = self.nested_field_value();
+    if res.is_err() {
+      // stop after the first error
+      self.i = self.rep_levels.len();
+    }
+    Some(res)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::errors::CoreErrorKind;
+
+  use super::*;
+
+  fn list_of(vals: Vec<FieldValue>) -> FieldValue {
+    FieldValue {
+      value: Some(Value::ListVal(RepeatedFieldValue { vals })),
+    }
+  }
+
+  fn int(x: i64) -> FieldValue {
+    FieldValue {
+      value: Some(Value::Int64Val(x)),
+    }
+  }
+
+  #[test]
+  fn test_doubly_nested_levels() -> CoreResult<()> {
+    let fvs = vec![
+      list_of(vec![list_of(vec![int(1), int(2)]), list_of(vec![])]),
+      list_of(vec![]),
+    ];
+    let levels_and_atoms = extract_levels_and_atoms::<i64>(&fvs, 2)?;
+    assert_eq!(levels_and_atoms.levels, vec![3, 3, 2, 2, 1, 1]);
+    assert_eq!(levels_and_atoms.atoms, vec![1, 2]);
+
+    let mut nester = AtomNester::<i64>::from_levels_and_values(
+      levels_and_atoms.levels,
+      levels_and_atoms.atoms,
+      2,
+    );
+    assert_eq!(nester.nested_field_values()?, fvs);
+    Ok(())
+  }
+
+  fn deeply_nested(depth: u8, leaf: FieldValue) -> FieldValue {
+    let mut res = leaf;
+    for _ in 0..depth {
+      res = list_of(vec![res]);
     }
-    Ok(res)
+    res
+  }
+
+  #[test]
+  fn test_deep_nesting() -> CoreResult<()> {
+    let depth = 254;
+    let fvs = vec![
+      deeply_nested(depth, int(7)),
+      FieldValue::default(),
+      deeply_nested(depth - 1, list_of(vec![int(8), int(9)])),
+    ];
+    let levels_and_atoms = extract_levels_and_atoms::<i64>(&fvs, depth)?;
+    assert_eq!(levels_and_atoms.atoms, vec![7, 8, 9]);
+
+    let mut nester = AtomNester::<i64>::from_levels_and_values(
+      levels_and_atoms.levels,
+      levels_and_atoms.atoms,
+      depth,
+    );
+    assert_eq!(nester.nested_field_values()?, fvs);
+
+    // levels would exceed the u8 range
+    assert!(extract_levels_and_atoms::<i64>(&[], 255).is_err());
+    assert!(extract_levels_and_atoms::<String>(&[], 254).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_truncated_levels() {
+    let mut nester = AtomNester::<i64>::from_levels_and_values(vec![3, 3], vec![1, 2], 2);
+    assert!(nester.nested_field_values().is_err());
+  }
+
+  #[test]
+  fn test_depth_mismatch() {
+    let fvs = vec![list_of(vec![list_of(vec![int(1)])])];
+    assert!(extract_levels_and_atoms::<i64>(&fvs, 1).is_err());
+    assert!(extract_levels_and_atoms::<i64>(&[int(1)], 1).is_err());
+
+    // one value nested too deep among valid ones
+    let fvs = vec![
+      list_of(vec![int(1)]),
+      FieldValue::default(),
+      list_of(vec![]),
+      list_of(vec![list_of(vec![int(2)])]),
+      list_of(vec![int(3)]),
+    ];
+    let err = extract_levels_and_atoms::<i64>(&fvs, 1).unwrap_err();
+    assert_eq!(err.kind, CoreErrorKind::Invalid);
+    assert!(err.to_string().contains("value 3:"), "{}", err);
+
+    // and one not nested deep enough
+    let fvs = vec![list_of(vec![list_of(vec![int(1)])]), list_of(vec![int(2)])];
+    let err = extract_levels_and_atoms::<i64>(&fvs, 2).unwrap_err();
+    assert!(err.to_string().contains("value 1:"), "{}", err);
+  }
+
+  #[test]
+  fn test_combine_chunks() -> CoreResult<()> {
+    let chunks = vec![
+      vec![list_of(vec![int(1), int(2)]), FieldValue::default()],
+      vec![],
+      vec![list_of(vec![]), list_of(vec![int(3)])],
+    ];
+    let mut combined = RepLevelsAndAtoms::default();
+    for chunk in &chunks {
+      combined.extend(&RepLevelsAndAtoms::from_field_values::<i64>(chunk, 1)?);
+    }
+    assert_eq!(combined.levels, vec![2, 2, 1, 0, 1, 2, 1]);
+    assert_eq!(combined.atoms, vec![1, 2, 3]);
+
+    let expected = chunks.concat();
+    assert_eq!(combined.into_field_values::<i64>(1)?, expected);
+    Ok(())
+  }
+
+  #[test]
+  fn test_new_into_field_values() -> CoreResult<()> {
+    let levels_and_atoms = RepLevelsAndAtoms::new(vec![2, 2, 1, 0, 1], b"hi".to_vec());
+    let fvs = levels_and_atoms.into_field_values::<String>(0)?;
+    assert_eq!(fvs, vec![
+      FieldValue { value: Some(Value::StringVal("hi".to_string())) },
+      FieldValue::default(),
+      FieldValue { value: Some(Value::StringVal("".to_string())) },
+    ]);
+
+    let missing_atoms = RepLevelsAndAtoms::new(vec![2, 2, 1], b"h".to_vec());
+    assert!(missing_atoms.into_field_values::<String>(0).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_field_values_with_presence() -> CoreResult<()> {
+    let fvs = vec![
+      list_of(vec![int(1), int(2)]),
+      FieldValue::default(),
+      list_of(vec![]),
+      FieldValue::default(),
+      FieldValue::default(),
+      FieldValue::default(),
+      FieldValue::default(),
+      FieldValue::default(),
+      list_of(vec![int(3)]),
+    ];
+    let present = fvs.iter()
+      .filter(|fv| fv.value.is_some())
+      .cloned()
+      .collect::<Vec<_>>();
+    let present = extract_levels_and_atoms::<i64>(&present, 1)?;
+    let bitmap = [0b00000101, 0b00000001];
+
+    let standard = extract_levels_and_atoms::<i64>(&fvs, 1)?
+      .into_field_values::<i64>(1)?;
+    let decoded = field_values_with_presence::<i64>(&bitmap, fvs.len(), present.clone(), 1)?;
+    assert_eq!(decoded, standard);
+    assert_eq!(decoded, fvs);
+
+    // too few values marked present, too short a bitmap, and embedded nulls
+    assert!(field_values_with_presence::<i64>(&[0b101, 0], fvs.len(), present.clone(), 1).is_err());
+    assert!(field_values_with_presence::<i64>(&bitmap[..1], fvs.len(), present, 1).is_err());
+    let with_null = RepLevelsAndAtoms::new(vec![1, 0], vec![7_i64]);
+    assert!(field_values_with_presence::<i64>(&[0b11], 2, with_null, 0).is_err());
+    Ok(())
   }
 }
@@ -1,6 +1,21 @@
 use std::array::TryFromSliceError;
 use std::convert::TryInto;
 
+#[cfg(test)]
+use pancake_db_idl::dtype::DataType;
+
+/// Every data type, for tests that should cover them all.
+#[cfg(test)]
+pub const ALL_DTYPES: [DataType; 7] = [
+  DataType::Int64,
+  DataType::String,
+  DataType::Float32,
+  DataType::Float64,
+  DataType::Bytes,
+  DataType::Bool,
+  DataType::TimestampMicros,
+];
+
 pub fn try_byte_array<const N: usize>(v: &[u8]) -> Result<[u8; N], TryFromSliceError> {
   v.try_into()
 }